@@ -0,0 +1,119 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+//! The `#[incomplete_test]` attribute, re-exported by incomplete_file's
+//! "macros" feature.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::{Expr, ExprLit, ItemFn, Lit, Meta, ReturnType, Token};
+
+// How many seeds a test runs with unless told otherwise
+const DEFAULT_RUNS: usize = 16;
+
+/**
+ * Turn a function taking `&IncompleteOptions` into a test that runs it
+ * with many seeds, through IncompleteOptions::check_seeds on options from
+ * IncompleteOptions::from_env: seeds that failed before are replayed
+ * first, and a new failing seed is reported, shrunk, and recorded. The
+ * function may return `()` or `std::io::Result<()>`, and other attributes
+ * such as `#[ignore]` are applied to the test.
+ *
+ * ```ignore
+ * #[incomplete_test(runs = 64)]
+ * fn reads_everything(options: &IncompleteOptions) -> std::io::Result<()> {
+ *     let mut reader = options.wrap_reader(&b"hello"[..]);
+ *     let mut buffer = vec![];
+ *     reader.read_to_end(&mut buffer)?;
+ *     assert_eq!(buffer, b"hello");
+ *     Ok(())
+ * }
+ * ```
+ *
+ * `runs` defaults to 16.
+ */
+#[proc_macro_attribute]
+pub fn incomplete_test(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    match expand(attr.into(), item.into()) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(attr: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
+    let mut runs = DEFAULT_RUNS;
+    for arg in Punctuated::<Meta, Token![,]>::parse_terminated.parse2(attr)? {
+        match &arg {
+            Meta::NameValue(pair) if pair.path.is_ident("runs") => match &pair.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Int(n), ..
+                }) => runs = n.base10_parse()?,
+                value => return Err(syn::Error::new_spanned(value, "expected a number of runs")),
+            },
+            _ => return Err(syn::Error::new_spanned(arg, "expected `runs = N`")),
+        }
+    }
+
+    let mut function: ItemFn = syn::parse2(item)?;
+    if function.sig.inputs.len() != 1 {
+        return Err(syn::Error::new_spanned(
+            &function.sig,
+            "an incomplete_test takes one argument, the &IncompleteOptions to run with",
+        ));
+    }
+
+    let attrs = std::mem::take(&mut function.attrs);
+    let vis = &function.vis;
+    let name = &function.sig.ident;
+    let test = match function.sig.output {
+        ReturnType::Default => quote! {
+            |options: &::incomplete_file::IncompleteOptions| {
+                #name(options);
+                ::std::result::Result::Ok(())
+            }
+        },
+        ReturnType::Type(..) => quote! { #name },
+    };
+
+    Ok(quote! {
+        #[test]
+        #(#attrs)*
+        #vis fn #name() {
+            #function
+
+            if let ::std::result::Result::Err(e) = ::incomplete_file::IncompleteOptions::from_env()
+                .and_then(|options| options.check_seeds(#runs, #test))
+            {
+                panic!("{}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_test() -> syn::Result<()> {
+        let item = quote! {
+            #[ignore]
+            fn reads(options: &IncompleteOptions) {}
+        };
+        let tokens = expand(quote! { runs = 4 }, item.clone())?.to_string();
+        assert!(tokens.starts_with("# [test] # [ignore] fn reads ()"));
+        assert!(tokens.contains("check_seeds (4usize"));
+
+        assert!(expand(quote! { seeds = 4 }, item).is_err());
+        assert!(expand(TokenStream::new(), quote! { fn reads() {} }).is_err());
+        Ok(())
+    }
+}
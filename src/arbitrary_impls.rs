@@ -0,0 +1,128 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// arbitrary::Arbitrary implementations, so fuzz targets can take a whole
+// injection schedule from their input
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use std::io::ErrorKind;
+
+use crate::{Decision, FailureMode, IncompleteOptions, IoOp, Policy};
+
+/**
+ * Arbitrary policies include records of 1 to 65536 bytes, never of none.
+ */
+impl<'a> Arbitrary<'a> for Policy {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.choose_index(7)? {
+            0 => Policy::Uniform,
+            1 => Policy::Geometric,
+            2 => Policy::HeavyTailed,
+            3 => Policy::Bimodal,
+            4 => Policy::Boundary,
+            5 => Policy::Records(usize::from(u.arbitrary::<u16>()?) + 1),
+            _ => Policy::OneByte,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for FailureMode {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(&[FailureMode::Transient, FailureMode::Sticky])?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for IoOp {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(&[
+            IoOp::Read,
+            IoOp::Write,
+            IoOp::Flush,
+            IoOp::Seek,
+            IoOp::SetLen,
+            IoOp::SyncAll,
+            IoOp::SyncData,
+        ])?)
+    }
+}
+
+/**
+ * As with quickcheck, arbitrary decisions are the ones a correct
+ * read_exact or write_all loop must survive: passing an operation through,
+ * limiting it to between 1 and 65536 bytes, or failing it with Interrupted.
+ */
+impl<'a> Arbitrary<'a> for Decision {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.choose_index(3)? {
+            0 => Decision::Full,
+            1 => Decision::Limit(usize::from(u.arbitrary::<u16>()?) + 1),
+            _ => Decision::Fail(ErrorKind::Interrupted),
+        })
+    }
+}
+
+/**
+ * Arbitrary options follow a script of arbitrary decisions, see
+ * IncompleteOptions::script, so that a fuzzer searches over every way the
+ * operations could be split up:
+ *
+ * ```
+ * # use arbitrary::{Arbitrary, Unstructured};
+ * # use incomplete_file::IncompleteOptions;
+ * # use std::io::Read;
+ * # let data = [3, 0, 7, 1, 0, 0, 0, 9];
+ * let mut u = Unstructured::new(&data);
+ * let options = IncompleteOptions::arbitrary(&mut u).unwrap();
+ * let mut reader = options.wrap_reader(u.take_rest());
+ * // parse from reader
+ * ```
+ */
+impl<'a> Arbitrary<'a> for IncompleteOptions {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut options = IncompleteOptions::default();
+        options.script(Vec::<Decision>::arbitrary(u)?);
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn arbitrary_test() -> std::io::Result<()> {
+        // one decision, a limit of 2 bytes, then the data
+        let input = [1, 1, 1, 0, 0, b'a', b'b', b'c'];
+        let mut u = Unstructured::new(&input);
+        let options = IncompleteOptions::arbitrary(&mut u).unwrap();
+        assert_eq!(options.script, Some(vec![Decision::Limit(2)]));
+
+        let data = u.take_rest();
+        let mut reader = options.wrap_reader(data);
+        let mut buffer = [0; 3];
+        assert_eq!(reader.read(&mut buffer)?, 2);
+
+        // whatever the input, the data still arrives intact
+        for seed in 0..64u8 {
+            let input: Vec<u8> = (0..64)
+                .map(|i| seed.wrapping_mul(i).wrapping_add(i))
+                .collect();
+            let mut u = Unstructured::new(&input);
+            let options = IncompleteOptions::arbitrary(&mut u).unwrap();
+            let mut reader = options.wrap_reader(&input[..]);
+            let mut buffer = vec![];
+            reader.read_to_end(&mut buffer)?;
+            assert_eq!(buffer, input);
+        }
+
+        // records are reachable, and never empty
+        let mut u = Unstructured::new(&[5, 0, 0]);
+        assert_eq!(Policy::arbitrary(&mut u).unwrap(), Policy::Records(1));
+
+        Ok(())
+    }
+}
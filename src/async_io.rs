@@ -0,0 +1,778 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use rand::Rng;
+use std::io::Result;
+#[cfg(feature = "tokio")]
+use std::io::SeekFrom;
+#[cfg(any(feature = "tokio", feature = "smol"))]
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::policy::{decided_len, permit};
+use crate::{IncompleteOptions, Injector, IoEvent, IoOp, IoStats, PartialPolicy};
+
+/**
+ * Wraps any tokio or futures AsyncRead and/or AsyncWrite (with the "tokio"
+ * and "futures" features respectively) and truncates each read and write
+ * the same way IncompleteFile does, so that futures like read_exact and
+ * write_all are exercised against partial progress.
+ *
+ * A decision is made once per operation: if the inner object returns
+ * Poll::Pending the same truncated size is used when the operation is polled
+ * again, cut to the buffer it is polled with.
+ *
+ * With IncompleteOptions::pending_count, every operation also returns
+ * Poll::Pending a number of times before it goes ahead, waking the task
+ * each time so that it is polled again.
+ *
+ * Delays from IncompleteOptions::latency and throttle do not block the
+ * executor: the operation returns Poll::Pending, and a timer thread wakes
+ * the task once the delay has passed.
+ */
+pub struct IncompleteAsync<T, P: PartialPolicy = Injector> {
+    inner: T,
+    policy: P,
+    read_size: Option<usize>,
+    write_size: Option<usize>,
+    flush_permitted: bool,
+    pending_count: usize,
+    pending_left: usize,
+    // when delayed reads, writes, and flushes may go ahead
+    read_due: Option<Instant>,
+    write_due: Option<Instant>,
+    flush_due: Option<Instant>,
+}
+
+/**
+ * An IncompleteAsync wrapping a tokio::fs::File.
+ */
+#[cfg(feature = "tokio")]
+pub type IncompleteAsyncFile<P = Injector> = IncompleteAsync<tokio::fs::File, P>;
+
+#[cfg(feature = "tokio")]
+impl IncompleteAsyncFile {
+    pub async fn create(path: &dyn AsRef<Path>) -> Result<Self> {
+        let options = IncompleteOptions::from_env()?;
+        options.create_async(path).await
+    }
+
+    pub async fn open(path: &dyn AsRef<Path>) -> Result<Self> {
+        let options = IncompleteOptions::from_env()?;
+        options.open_async(path).await
+    }
+}
+
+/**
+ * An IncompleteAsync wrapping an async_fs::File, which implements the
+ * futures traits and works on smol, async-std, or any other executor.
+ */
+#[cfg(feature = "smol")]
+pub type IncompleteSmolFile<P = Injector> = IncompleteAsync<async_fs::File, P>;
+
+#[cfg(feature = "smol")]
+impl IncompleteSmolFile {
+    pub async fn create(path: &dyn AsRef<Path>) -> Result<Self> {
+        let options = IncompleteOptions::from_env()?;
+        options.create_smol(path).await
+    }
+
+    pub async fn open(path: &dyn AsRef<Path>) -> Result<Self> {
+        let options = IncompleteOptions::from_env()?;
+        options.open_smol(path).await
+    }
+}
+
+impl<T> IncompleteAsync<T> {
+    pub fn new(inner: T) -> Self {
+        IncompleteOptions::new().wrap_async(inner)
+    }
+}
+
+impl<T, G: Rng> IncompleteAsync<T, Injector<G>> {
+    /**
+     * The seed this wrapper's RNG was created with, see
+     * IncompleteOptions::seed.
+     */
+    pub fn seed(&self) -> Option<u64> {
+        self.policy.seed()
+    }
+
+    /**
+     * Counts of this wrapper's operations so far, see Injector::stats.
+     */
+    pub fn stats(&self) -> IoStats {
+        self.policy.stats()
+    }
+
+    /**
+     * Every operation on this wrapper so far, see Injector::events.
+     */
+    pub fn events(&self) -> &[IoEvent] {
+        self.policy.events()
+    }
+}
+
+impl<T, P: PartialPolicy> IncompleteAsync<T, P> {
+    /**
+     * Wrap `inner`, letting `policy` decide how every operation is
+     * truncated.
+     */
+    pub fn with_policy(inner: T, policy: P) -> Self {
+        Self {
+            inner,
+            policy,
+            read_size: None,
+            write_size: None,
+            flush_permitted: false,
+            pending_count: 0,
+            pending_left: 0,
+            read_due: None,
+            write_due: None,
+            flush_due: None,
+        }
+    }
+
+    pub(crate) fn with_pending_count(mut self, count: usize) -> Self {
+        self.pending_count = count;
+        self.pending_left = count;
+        self
+    }
+
+    /**
+     * Return Poll::Pending if this operation has not yet been pending
+     * pending_count times, scheduling a wake so it is polled again. Once it
+     * is ready the count starts over for the next operation.
+     */
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.pending_left > 0 {
+            self.pending_left -= 1;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        Poll::Ready(())
+    }
+
+    /**
+     * Return Poll::Pending until the policy's delay for `op` has passed,
+     * with a thread waking the task when it has instead of the executor
+     * being blocked. The deadline is kept across polls, so the delay is
+     * only drawn once per operation.
+     */
+    fn poll_delay(&mut self, cx: &mut Context<'_>, op: IoOp) -> Poll<()> {
+        let due = match op {
+            IoOp::Read => &mut self.read_due,
+            IoOp::Write => &mut self.write_due,
+            _ => &mut self.flush_due,
+        };
+        let deadline = match *due {
+            Some(deadline) => deadline,
+            None => {
+                let delay = self.policy.delay(op);
+                if delay.is_zero() {
+                    return Poll::Ready(());
+                }
+                *due.insert(Instant::now() + delay)
+            }
+        };
+
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            *due = None;
+            return Poll::Ready(());
+        }
+        let waker = cx.waker().clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(left);
+            waker.wake();
+        });
+        Poll::Pending
+    }
+
+    // Called whenever an operation finishes, successfully or not
+    fn reset_pending(&mut self) {
+        self.pending_left = self.pending_count;
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+
+    /**
+     * Consume the wrapper, returning the wrapped object.
+     */
+    pub fn into_inner(mut self) -> T {
+        self.policy.released();
+        self.inner
+    }
+}
+
+impl<T, P: PartialPolicy> IncompleteAsync<T, P> {
+    /**
+     * Poll a read of up to `len` bytes: `poll` is called with the inner
+     * object and the truncated size, and returns how many bytes it read.
+     * This is shared by the tokio and futures implementations.
+     */
+    fn poll_read_with(
+        &mut self,
+        cx: &mut Context<'_>,
+        len: usize,
+        poll: impl FnOnce(&mut T, &mut Context<'_>, usize) -> Poll<Result<usize>>,
+    ) -> Poll<Result<usize>> {
+        ready!(self.poll_pending(cx));
+
+        // a retry after a cancelled poll may come with a smaller buffer
+        let truncated_size = match self.read_size.take() {
+            Some(size) => size.min(len),
+            None => {
+                ready!(self.poll_delay(cx, IoOp::Read));
+                match decided_len(&mut self.policy, IoOp::Read, len) {
+                    Ok(size) => size,
+                    Err(e) => {
+                        self.reset_pending();
+                        return Poll::Ready(Err(e));
+                    }
+                }
+            }
+        };
+
+        let result = match truncated_size {
+            0 => Ok(0),
+            _ => {
+                self.read_size = Some(truncated_size);
+                let result = ready!(poll(&mut self.inner, cx, truncated_size));
+                self.read_size = None;
+                result
+            }
+        };
+
+        self.reset_pending();
+        let n = result?;
+        self.policy.completed(IoOp::Read, n);
+        Poll::Ready(Ok(n))
+    }
+
+    /**
+     * Poll a write of up to `len` bytes, see poll_read_with.
+     */
+    fn poll_write_with(
+        &mut self,
+        cx: &mut Context<'_>,
+        len: usize,
+        poll: impl FnOnce(&mut T, &mut Context<'_>, usize) -> Poll<Result<usize>>,
+    ) -> Poll<Result<usize>> {
+        ready!(self.poll_pending(cx));
+
+        // a retry after a cancelled poll may come with a smaller buffer
+        let truncated_size = match self.write_size.take() {
+            Some(size) => size.min(len),
+            None => {
+                ready!(self.poll_delay(cx, IoOp::Write));
+                match decided_len(&mut self.policy, IoOp::Write, len) {
+                    Ok(size) => size,
+                    Err(e) => {
+                        self.reset_pending();
+                        return Poll::Ready(Err(e));
+                    }
+                }
+            }
+        };
+
+        let result = match truncated_size {
+            0 => Ok(0),
+            _ => {
+                self.write_size = Some(truncated_size);
+                let result = ready!(poll(&mut self.inner, cx, truncated_size));
+                self.write_size = None;
+                result
+            }
+        };
+
+        self.reset_pending();
+        let n = result?;
+        self.policy.completed(IoOp::Write, n);
+        Poll::Ready(Ok(n))
+    }
+
+    /**
+     * Poll a flush, unless the policy fails it, see poll_read_with.
+     */
+    fn poll_flush_with(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: impl FnOnce(&mut T, &mut Context<'_>) -> Poll<Result<()>>,
+    ) -> Poll<Result<()>> {
+        ready!(self.poll_pending(cx));
+
+        if !self.flush_permitted {
+            ready!(self.poll_delay(cx, IoOp::Flush));
+            if let Err(e) = decided_len(&mut self.policy, IoOp::Flush, 0) {
+                self.reset_pending();
+                return Poll::Ready(Err(e));
+            }
+            self.flush_permitted = true;
+        }
+
+        let result = ready!(poll(&mut self.inner, cx));
+        self.flush_permitted = false;
+        self.reset_pending();
+        if result.is_ok() {
+            self.policy.completed(IoOp::Flush, 0);
+        }
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncRead + Unpin, P: PartialPolicy + Unpin> AsyncRead for IncompleteAsync<T, P> {
+    // See IncompleteFile's Read implementation
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let n = ready!(self
+            .get_mut()
+            .poll_read_with(cx, buf.remaining(), |inner, cx, size| {
+                let mut limited = ReadBuf::new(buf.initialize_unfilled_to(size));
+                let result = ready!(Pin::new(inner).poll_read(cx, &mut limited));
+                Poll::Ready(result.map(|()| limited.filled().len()))
+            }))?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncWrite + Unpin, P: PartialPolicy + Unpin> AsyncWrite for IncompleteAsync<T, P> {
+    // See IncompleteFile's Write implementation
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut()
+            .poll_write_with(cx, buf.len(), |inner, cx, size| {
+                Pin::new(inner).poll_write(cx, &buf[0..size])
+            })
+    }
+
+    // Pass-through flush, unless the policy fails it
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut()
+            .poll_flush_with(cx, |inner, cx| Pin::new(inner).poll_flush(cx))
+    }
+
+    // Pass-through shutdown, which flushes, so the policy is told of a
+    // flush once it succeeds
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.inner).poll_shutdown(cx))?;
+        this.policy.completed(IoOp::Flush, 0);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T: futures_io::AsyncRead + Unpin, P: PartialPolicy + Unpin> futures_io::AsyncRead
+    for IncompleteAsync<T, P>
+{
+    // See IncompleteFile's Read implementation
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        self.get_mut()
+            .poll_read_with(cx, buf.len(), |inner, cx, size| {
+                Pin::new(inner).poll_read(cx, &mut buf[0..size])
+            })
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T: futures_io::AsyncWrite + Unpin, P: PartialPolicy + Unpin> futures_io::AsyncWrite
+    for IncompleteAsync<T, P>
+{
+    // See IncompleteFile's Write implementation
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut()
+            .poll_write_with(cx, buf.len(), |inner, cx, size| {
+                Pin::new(inner).poll_write(cx, &buf[0..size])
+            })
+    }
+
+    // Pass-through flush, unless the policy fails it
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut()
+            .poll_flush_with(cx, |inner, cx| Pin::new(inner).poll_flush(cx))
+    }
+
+    // Pass-through close, which counts as a flush, see poll_shutdown
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.inner).poll_close(cx))?;
+        this.policy.completed(IoOp::Flush, 0);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncSeek + Unpin, P: PartialPolicy + Unpin> AsyncSeek for IncompleteAsync<T, P> {
+    // Pass-through seek, unless the policy fails it
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> Result<()> {
+        let this = self.get_mut();
+        permit(&mut this.policy, IoOp::Seek)?;
+        Pin::new(&mut this.inner).start_seek(position)
+    }
+
+    // Pass-through completion, reporting the new position to the policy
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        let this = self.get_mut();
+        let position = ready!(Pin::new(&mut this.inner).poll_complete(cx))?;
+        this.policy.seeked(position);
+        Poll::Ready(Ok(position))
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use rand::RngCore;
+    use std::future::Future;
+    use std::io::ErrorKind;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use crate::UnflushedDrop;
+
+    #[tokio::test]
+    async fn read_exact_test() -> Result<()> {
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        let mut reader = IncompleteAsync::new(&random_data[..]);
+
+        // a short read must happen at least once for a buffer this size
+        let mut buffer = vec![0; random_data.len()];
+        let n = reader.read(&mut buffer).await?;
+        assert!(n < random_data.len());
+
+        reader.read_exact(&mut buffer[n..]).await?;
+        assert_eq!(random_data, buffer);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn file_test() -> Result<()> {
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("random");
+
+        let mut writer = IncompleteAsyncFile::create(&path).await?;
+        let n = writer.write(&random_data).await?;
+        assert!(n < random_data.len());
+        writer.write_all(&random_data[n..]).await?;
+        writer.flush().await?;
+
+        let mut reader = IncompleteAsyncFile::open(&path).await?;
+        let mut actual_data = vec![];
+        reader.read_to_end(&mut actual_data).await?;
+        assert_eq!(random_data, actual_data);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn seek_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("random");
+
+        let mut file = IncompleteOptions::default()
+            .fail_on(IoOp::Seek, 2, ErrorKind::Other)
+            .wrap_async(tokio::fs::File::create(&path).await?);
+        file.write_all(b"hello world").await?;
+
+        assert_eq!(file.seek(SeekFrom::Start(6)).await?, 6);
+        let e = file.seek(SeekFrom::Start(0)).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+
+        // the failed seek did not move the file
+        file.write_all(b"there").await?;
+        file.flush().await?;
+        assert_eq!(std::fs::read(&path)?, b"hello there");
+
+        Ok(())
+    }
+
+    // Counts how many times it has been woken
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn pending_test() -> Result<()> {
+        let data = vec![1; 320];
+        let mut reader = IncompleteOptions::default()
+            .pending_count(3)
+            .wrap_async(&data[..]);
+
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        // every read is pending three times, with a wake each time
+        for _ in 0..2 {
+            let mut buffer = vec![0; 320];
+            let mut read = Box::pin(reader.read(&mut buffer));
+            let mut pending = 0;
+            let n = loop {
+                match read.as_mut().poll(&mut cx) {
+                    Poll::Ready(n) => break n?,
+                    Poll::Pending => pending += 1,
+                }
+            };
+            assert!(n > 0);
+            assert_eq!(pending, 3);
+        }
+        assert_eq!(counter.0.load(Ordering::SeqCst), 6);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancelled_test() -> Result<()> {
+        let (mut other, end) = tokio::io::duplex(4);
+        let mut end = IncompleteOptions::default()
+            .faithful(IoOp::Read)
+            .faithful(IoOp::Write)
+            .wrap_async(end);
+
+        // a read and a write that are pending when they are dropped
+        assert!(end.read(&mut [0; 32]).now_or_never().is_none());
+        end.write_all(&[1; 4]).await?;
+        assert!(end.write(&[2; 32]).now_or_never().is_none());
+
+        // then go again with smaller buffers
+        other.write_all(b"hell").await?;
+        let mut buffer = [0; 2];
+        assert_eq!(end.read(&mut buffer).await?, 2);
+        assert_eq!(&buffer, b"he");
+        other.read_exact(&mut [0; 4]).await?;
+        assert_eq!(end.write(&[3; 2]).await?, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn latency_test() -> Result<()> {
+        let data = [7; 100];
+        let mut reader = IncompleteOptions::default()
+            .full_op_probability(1.0)
+            .latency(Duration::from_millis(200), Duration::from_millis(200))
+            .wrap_async(&data[..]);
+
+        // the read waits without blocking the thread polling it
+        let start = Instant::now();
+        let mut buffer = [0; 100];
+        assert!(reader.read(&mut buffer).now_or_never().is_none());
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(reader.read(&mut buffer).await?, 100);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        // and so does a throttled one
+        let mut reader = IncompleteOptions::default()
+            .full_op_probability(1.0)
+            .throttle(500)
+            .wrap_async(&data[..]);
+        assert_eq!(reader.read(&mut buffer).await?, 50);
+        let start = Instant::now();
+        assert!(reader.read(&mut buffer).now_or_never().is_none());
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert_eq!(reader.read(&mut buffer).await?, 50);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_test() -> Result<()> {
+        // shutting down flushes, so dropping afterwards is fine
+        let mut writer = IncompleteOptions::default()
+            .on_unflushed_drop(UnflushedDrop::Panic)
+            .wrap_async(vec![]);
+        writer.write_all(b"hello").await?;
+        assert_eq!(writer.policy().unflushed(), 5);
+        writer.shutdown().await?;
+        assert_eq!(writer.policy().unflushed(), 0);
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "futures"))]
+mod futures_tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::{AsyncReadExt, AsyncWriteExt, Cursor};
+    use futures::FutureExt;
+    use rand::RngCore;
+
+    use crate::UnflushedDrop;
+
+    #[test]
+    fn copy_test() -> Result<()> {
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        block_on(async {
+            let mut reader = IncompleteAsync::new(Cursor::new(random_data.clone()));
+
+            // a short read must happen at least once for a buffer this size
+            let mut buffer = vec![0; random_data.len()];
+            let n = reader.read(&mut buffer).await?;
+            assert!(n < random_data.len());
+            reader.read_exact(&mut buffer[n..]).await?;
+            assert_eq!(random_data, buffer);
+
+            let mut writer = IncompleteAsync::new(Cursor::new(vec![]));
+            futures::io::copy(&mut &random_data[..], &mut writer).await?;
+            writer.flush().await?;
+            assert_eq!(random_data, writer.into_inner().into_inner());
+
+            Ok(())
+        })
+    }
+
+    // Pending until it is opened, then reads from and writes to a Vec
+    struct Gate {
+        open: bool,
+        data: Vec<u8>,
+    }
+
+    impl futures_io::AsyncRead for Gate {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<Result<usize>> {
+            let this = self.get_mut();
+            if !this.open {
+                return Poll::Pending;
+            }
+            let n = buf.len().min(this.data.len());
+            buf[..n].copy_from_slice(&this.data[..n]);
+            this.data.drain(..n);
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl futures_io::AsyncWrite for Gate {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize>> {
+            let this = self.get_mut();
+            if !this.open {
+                return Poll::Pending;
+            }
+            this.data.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn cancelled_test() -> Result<()> {
+        let gate = Gate {
+            open: false,
+            data: b"hello".to_vec(),
+        };
+        let mut gate = IncompleteOptions::default()
+            .faithful(IoOp::Read)
+            .faithful(IoOp::Write)
+            .wrap_async(gate);
+
+        // a read and a write that are pending when they are dropped
+        assert!(gate.read(&mut [0; 32]).now_or_never().is_none());
+        assert!(gate.write(&[1; 32]).now_or_never().is_none());
+
+        // then go again with smaller buffers
+        gate.inner.open = true;
+        block_on(async {
+            let mut buffer = [0; 2];
+            assert_eq!(gate.read(&mut buffer).await?, 2);
+            assert_eq!(&buffer, b"he");
+            assert_eq!(gate.write(&[1; 2]).await?, 2);
+            assert_eq!(gate.into_inner().data, b"llo\x01\x01");
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn close_test() -> Result<()> {
+        // closing flushes, so dropping afterwards is fine
+        let mut writer = IncompleteOptions::default()
+            .on_unflushed_drop(UnflushedDrop::Panic)
+            .wrap_async(Cursor::new(vec![]));
+        block_on(async {
+            writer.write_all(b"hello").await?;
+            writer.close().await?;
+            assert_eq!(writer.policy().unflushed(), 0);
+
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "smol")]
+    #[test]
+    fn smol_file_test() -> Result<()> {
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("random");
+
+        block_on(async {
+            let mut writer = IncompleteSmolFile::create(&path).await?;
+            let n = writer.write(&random_data).await?;
+            assert!(n < random_data.len());
+            writer.write_all(&random_data[n..]).await?;
+            writer.flush().await?;
+
+            let mut reader = IncompleteSmolFile::open(&path).await?;
+            let mut actual_data = vec![];
+            reader.read_to_end(&mut actual_data).await?;
+            assert_eq!(random_data, actual_data);
+
+            Ok(())
+        })
+    }
+}
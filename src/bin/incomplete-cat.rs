@@ -0,0 +1,127 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+//! Copy stdin to stdout with partial reads and writes, to chaos test the
+//! programs on either side of a shell pipeline:
+//!
+//! ```text
+//! producer | incomplete-cat --max-size 16 | consumer
+//! ```
+//!
+//! The seed is printed to stderr so that a failing run can be replayed with
+//! --seed, and the INCOMPLETE_* variables IncompleteOptions::new reads apply
+//! too, with the flags taking precedence. Reads and writes are truncated
+//! independently, with seeds derived from the printed one. There is no flag to
+//! inject Interrupted errors, since the copy would retry them without the
+//! programs on either side ever seeing them.
+//!
+//! --fail-after-bytes and --fail-after-ops make the copy fail part way
+//! through, and incomplete-cat exits with status 1, so the producer gets a
+//! broken pipe and the consumer an early end of file. --latency and --throttle
+//! slow the copy down.
+
+use incomplete_file::{IncompleteOptions, IncompleteStdin, IncompleteStdout, Injector};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::num::NonZeroU64;
+use std::process::ExitCode;
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage: incomplete-cat [options]
+
+Copy stdin to stdout, truncating every read and write.
+
+options:
+    --seed N                       replay the run that printed seed N
+    --min-size N                   never truncate to fewer than N bytes
+    --max-size N                   never truncate to more than N bytes
+    --full-op-probability P        pass a fraction P of operations through
+    --fail-after-bytes N           fail once N bytes have been copied
+    --fail-after-ops N             fail after N reads, or after N writes
+    --latency MS                   sleep MS milliseconds before every operation
+    --throttle N                   copy at most N bytes a second
+    --quiet                        do not print the seed
+    --help                         print this message
+";
+
+// Parse the value following a flag
+fn value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} needs a valid value", flag),
+        )
+    })
+}
+
+fn run() -> Result<()> {
+    let mut options = IncompleteOptions::from_env()?;
+    let mut quiet = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--seed" => {
+                options.seed(value(&flag, args.next())?);
+            }
+            "--min-size" => {
+                options.min_size(value(&flag, args.next())?);
+            }
+            "--max-size" => {
+                options.max_size(value(&flag, args.next())?);
+            }
+            "--full-op-probability" => {
+                options.full_op_probability(value(&flag, args.next())?);
+            }
+            "--fail-after-bytes" => {
+                options.fail_at_offset(value(&flag, args.next())?, ErrorKind::Other);
+            }
+            "--fail-after-ops" => {
+                options.fail_after_ops(value(&flag, args.next())?, ErrorKind::Other);
+            }
+            "--latency" => {
+                let latency = Duration::from_millis(value(&flag, args.next())?);
+                options.latency(latency, latency);
+            }
+            "--throttle" => {
+                options.throttle(value::<NonZeroU64>(&flag, args.next())?.get());
+            }
+            "--quiet" => quiet = true,
+            "--help" => {
+                print!("{}", USAGE);
+                return Ok(());
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown option {}\n\n{}", flag, USAGE),
+                ))
+            }
+        }
+    }
+
+    let mut injector = Injector::from_options(&options);
+    if let (false, Some(seed)) = (quiet, injector.seed()) {
+        eprintln!("incomplete-cat: seed {}", seed);
+    }
+    let writes = injector.fork();
+    let mut reader = IncompleteStdin::with_policy(std::io::stdin().lock(), injector);
+    let mut writer = IncompleteStdout::with_policy(std::io::stdout().lock(), writes);
+    std::io::copy(&mut reader, &mut writer)?;
+    writer.flush()
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        // a closed pipe downstream is how pipelines normally end
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("incomplete-cat: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
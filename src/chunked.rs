@@ -0,0 +1,74 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::collections::VecDeque;
+use std::io::{Read, Result};
+
+/**
+ * A reader that serves a hand-crafted sequence of chunks: each read returns
+ * at most one chunk, so a test can reproduce an exact sequence of partial
+ * reads, for example a header split across two reads. A read into a buffer
+ * smaller than the current chunk returns part of it, and the rest of that
+ * chunk is served next. Empty chunks are skipped, because a zero byte read
+ * would signal EOF.
+ */
+pub struct ChunkedReader {
+    chunks: VecDeque<Vec<u8>>,
+    offset: usize,
+}
+
+impl ChunkedReader {
+    pub fn from_chunks(chunks: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        Self {
+            chunks: chunks
+                .into_iter()
+                .filter(|chunk| !chunk.is_empty())
+                .collect(),
+            offset: 0,
+        }
+    }
+}
+
+impl Read for ChunkedReader {
+    // Serve what is left of the current chunk, or EOF once they run out
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let chunk = match self.chunks.front() {
+            Some(chunk) => chunk,
+            None => return Ok(0),
+        };
+
+        let n = (&chunk[self.offset..]).read(buf)?;
+        self.offset += n;
+        if self.offset == chunk.len() {
+            self.chunks.pop_front();
+            self.offset = 0;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_test() -> Result<()> {
+        let mut reader =
+            ChunkedReader::from_chunks([b"HTTP/1.1 2".to_vec(), vec![], b"00 OK\r\n".to_vec()]);
+
+        let mut buffer = vec![0; 64];
+        assert_eq!(reader.read(&mut buffer)?, 10);
+        assert_eq!(&buffer[..10], b"HTTP/1.1 2");
+
+        // a small buffer splits a chunk further
+        assert_eq!(reader.read(&mut buffer[..3])?, 3);
+        assert_eq!(reader.read(&mut buffer)?, 4);
+        assert_eq!(&buffer[..4], b"OK\r\n");
+        assert_eq!(reader.read(&mut buffer)?, 0);
+
+        Ok(())
+    }
+}
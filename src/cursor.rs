@@ -0,0 +1,159 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use rand::Rng;
+use std::io::{Cursor, IoSlice, IoSliceMut, Read, Result, Seek, SeekFrom, Write};
+
+use crate::policy::{
+    flush_through, permit, read_through, read_vectored_through, write_through,
+    write_vectored_through,
+};
+use crate::{IncompleteOptions, Injector, IoEvent, IoOp, IoStats, PartialPolicy};
+
+/**
+ * An in-memory stand-in for IncompleteFile: reads, writes, and seeks over a
+ * Vec<u8> are truncated and failed the same way, with no filesystem access.
+ */
+pub struct IncompleteCursor<P: PartialPolicy = Injector> {
+    cursor: Cursor<Vec<u8>>,
+    policy: P,
+}
+
+impl IncompleteCursor {
+    pub fn new(data: Vec<u8>) -> Self {
+        IncompleteOptions::new().wrap_cursor(data)
+    }
+}
+
+impl<G: Rng> IncompleteCursor<Injector<G>> {
+    /**
+     * The seed this cursor's RNG was created with, see IncompleteOptions::seed.
+     */
+    pub fn seed(&self) -> Option<u64> {
+        self.policy.seed()
+    }
+
+    /**
+     * Counts of this cursor's operations so far, see Injector::stats.
+     */
+    pub fn stats(&self) -> IoStats {
+        self.policy.stats()
+    }
+
+    /**
+     * Every operation on this cursor so far, see Injector::events.
+     */
+    pub fn events(&self) -> &[IoEvent] {
+        self.policy.events()
+    }
+}
+
+impl<P: PartialPolicy> IncompleteCursor<P> {
+    /**
+     * Start at the beginning of `data`, letting `policy` decide how every
+     * operation is truncated.
+     */
+    pub fn with_policy(data: Vec<u8>, policy: P) -> Self {
+        Self {
+            cursor: Cursor::new(data),
+            policy,
+        }
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+
+    pub fn get_ref(&self) -> &Vec<u8> {
+        self.cursor.get_ref()
+    }
+
+    pub fn position(&self) -> u64 {
+        self.cursor.position()
+    }
+
+    /**
+     * Consume the cursor, returning everything written to it.
+     */
+    pub fn into_inner(mut self) -> Vec<u8> {
+        self.policy.released();
+        self.cursor.into_inner()
+    }
+}
+
+impl<P: PartialPolicy> Read for IncompleteCursor<P> {
+    // See IncompleteFile's Read implementation
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        read_through(&mut self.policy, buf, |buf| self.cursor.read(buf))
+    }
+
+    // See IncompleteFile's Read implementation
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        read_vectored_through(&mut self.policy, bufs, |buf| self.cursor.read(buf))
+    }
+}
+
+impl<P: PartialPolicy> Write for IncompleteCursor<P> {
+    // See IncompleteFile's Write implementation
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        write_through(&mut self.policy, buf, |buf| self.cursor.write(buf))
+    }
+
+    // See IncompleteFile's Write implementation
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        write_vectored_through(&mut self.policy, bufs, |bufs| {
+            self.cursor.write_vectored(bufs)
+        })
+    }
+
+    // Pass-through flush, unless the policy fails it
+    fn flush(&mut self) -> Result<()> {
+        flush_through(&mut self.policy, || self.cursor.flush())
+    }
+}
+
+impl<P: PartialPolicy> Seek for IncompleteCursor<P> {
+    // See IncompleteFile's Seek implementation
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        permit(&mut self.policy, IoOp::Seek)?;
+        let position = self.cursor.seek(pos)?;
+        self.policy.seeked(position);
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    #[test]
+    fn cursor_test() -> Result<()> {
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        let mut cursor = IncompleteCursor::new(vec![]);
+
+        // a short write must happen at least once for a buffer this size
+        let n = cursor.write(&random_data)?;
+        assert!(n < random_data.len());
+        cursor.write_all(&random_data[n..])?;
+
+        cursor.rewind()?;
+        let mut actual_data = vec![];
+        cursor.read_to_end(&mut actual_data)?;
+        assert_eq!(random_data, actual_data);
+        assert_eq!(cursor.position(), random_data.len() as u64);
+
+        assert_eq!(cursor.into_inner(), random_data);
+
+        Ok(())
+    }
+}
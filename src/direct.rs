@@ -0,0 +1,110 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// Holding reads and writes to the alignment rules of direct I/O
+
+use std::io::{Error, ErrorKind, Result, Seek};
+
+use crate::{IncompleteFile, PartialPolicy};
+
+impl<P: PartialPolicy> IncompleteFile<P> {
+    pub(crate) fn with_alignment(mut self, alignment: usize) -> Self {
+        self.alignment = Some(alignment);
+        self
+    }
+
+    /**
+     * Fail a transfer between `bufs` and the file at `offset`, or at its
+     * cursor if that is None, with InvalidInput as Linux does for O_DIRECT,
+     * unless every buffer's address and length and the offset are all
+     * multiples of the alignment. Without one, everything is allowed.
+     */
+    pub(crate) fn check_aligned<'a>(
+        &self,
+        bufs: impl IntoIterator<Item = &'a [u8]>,
+        offset: Option<u64>,
+    ) -> Result<()> {
+        let alignment = match self.alignment {
+            Some(alignment) => alignment,
+            None => return Ok(()),
+        };
+        let offset = match offset {
+            Some(offset) => offset,
+            None => (&self.file).stream_position()?,
+        };
+
+        let misaligned = |value: u64| !value.is_multiple_of(alignment as u64);
+        if misaligned(offset) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "direct I/O at offset {} is not aligned to {} bytes",
+                    offset, alignment
+                ),
+            ));
+        }
+        for buf in bufs.into_iter().filter(|buf| !buf.is_empty()) {
+            if misaligned(buf.as_ptr() as u64) || misaligned(buf.len() as u64) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "direct I/O of {} bytes at {:p} is not aligned to {} bytes",
+                        buf.len(),
+                        buf.as_ptr(),
+                        alignment
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::IncompleteOptions;
+    use std::io::{ErrorKind, Read, Result, Seek, SeekFrom, Write};
+    use std::os::unix::fs::FileExt;
+
+    #[test]
+    fn direct_io_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("direct");
+        let mut options = IncompleteOptions::default();
+        options.direct_io(512);
+        let mut file = options.create(&path)?;
+
+        // a buffer of 8 blocks, starting on a block boundary
+        let mut storage = vec![7; 5 * 1024];
+        let start = storage.as_ptr().align_offset(512);
+        let buffer = &mut storage[start..start + 4096];
+
+        // short transfers are whole blocks
+        let n = file.write(buffer)?;
+        assert_eq!(n % 512, 0);
+        file.write_all(&buffer[n..])?;
+
+        // a misaligned length, address, or offset is refused
+        let misaligned = [&buffer[..100], &buffer[1..513]];
+        for buf in misaligned {
+            assert_eq!(file.write(buf).unwrap_err().kind(), ErrorKind::InvalidInput);
+        }
+        assert_eq!(
+            file.read_at(&mut buffer[..512], 1).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+
+        let mut file = options.open(&path)?;
+        // seeking anywhere is fine, but not reading from there
+        file.seek(SeekFrom::Start(100))?;
+        assert!(file.read(&mut buffer[..512]).is_err());
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(buffer)?;
+        assert_eq!(buffer, [7; 4096]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,464 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use rand::Rng;
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::sync::{Arc, Condvar, Mutex};
+#[cfg(any(feature = "tokio", feature = "futures"))]
+use std::{
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use crate::policy::{flush_through, read_through, write_through};
+#[cfg(any(feature = "tokio", feature = "futures"))]
+use crate::IncompleteAsync;
+use crate::{IncompleteOptions, Injector, IoEvent, IoStats, PartialPolicy};
+
+/**
+ * Connect two in-memory endpoints, each of which truncates and injects
+ * faults into its reads and writes with default options. See
+ * IncompleteOptions::duplex.
+ */
+pub fn faulty_duplex(capacity: usize) -> (IncompleteDuplex, IncompleteDuplex) {
+    IncompleteOptions::new().duplex(capacity)
+}
+
+/**
+ * Like faulty_duplex, but the endpoints implement the async traits instead.
+ */
+#[cfg(any(feature = "tokio", feature = "futures"))]
+pub fn faulty_duplex_async(
+    capacity: usize,
+) -> (IncompleteAsync<DuplexEnd>, IncompleteAsync<DuplexEnd>) {
+    IncompleteOptions::new().duplex_async(capacity)
+}
+
+// One direction of a duplex: bytes written by one end, waiting to be read by
+// the other
+struct Pipe {
+    buffer: VecDeque<u8>,
+    capacity: usize,
+    reader_gone: bool,
+    writer_gone: bool,
+    #[cfg(any(feature = "tokio", feature = "futures"))]
+    read_waker: Option<Waker>,
+    #[cfg(any(feature = "tokio", feature = "futures"))]
+    write_waker: Option<Waker>,
+}
+
+struct Shared {
+    pipe: Mutex<Pipe>,
+    changed: Condvar,
+}
+
+impl Pipe {
+    // Wake whoever is waiting on the other side of the pipe
+    fn wake_reader(&mut self) {
+        #[cfg(any(feature = "tokio", feature = "futures"))]
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_writer(&mut self) {
+        #[cfg(any(feature = "tokio", feature = "futures"))]
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /**
+     * Read what is buffered into buf, returning None if the read has to wait
+     * for the other end to write (or go away).
+     */
+    fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.buffer.is_empty() {
+            return match self.writer_gone || buf.is_empty() {
+                true => Some(0),
+                false => None,
+            };
+        }
+
+        let n = buf.len().min(self.buffer.len());
+        for (byte, value) in buf.iter_mut().zip(self.buffer.drain(..n)) {
+            *byte = value;
+        }
+        self.wake_writer();
+        Some(n)
+    }
+
+    /**
+     * Buffer as much of buf as there is room for, returning None if the
+     * write has to wait for the other end to read. Writes fail with
+     * BrokenPipe once the other end has gone away.
+     */
+    fn write(&mut self, buf: &[u8]) -> Option<Result<usize>> {
+        if self.reader_gone {
+            return Some(Err(Error::new(ErrorKind::BrokenPipe, "duplex end dropped")));
+        }
+        if buf.is_empty() {
+            return Some(Ok(0));
+        }
+
+        let n = buf.len().min(self.capacity - self.buffer.len());
+        if n == 0 {
+            return None;
+        }
+        self.buffer.extend(&buf[0..n]);
+        self.wake_reader();
+        Some(Ok(n))
+    }
+}
+
+impl Shared {
+    fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(Self {
+            pipe: Mutex::new(Pipe {
+                buffer: VecDeque::with_capacity(capacity),
+                capacity,
+                reader_gone: false,
+                writer_gone: false,
+                #[cfg(any(feature = "tokio", feature = "futures"))]
+                read_waker: None,
+                #[cfg(any(feature = "tokio", feature = "futures"))]
+                write_waker: None,
+            }),
+            changed: Condvar::new(),
+        })
+    }
+
+    // Block on the condition variable until op can go ahead
+    fn wait<T>(&self, mut op: impl FnMut(&mut Pipe) -> Option<T>) -> T {
+        let mut pipe = self.pipe.lock().unwrap();
+        loop {
+            if let Some(result) = op(&mut pipe) {
+                self.changed.notify_all();
+                return result;
+            }
+            pipe = self.changed.wait(pipe).unwrap();
+        }
+    }
+
+    // Update the pipe, and tell anyone waiting on it
+    fn close(&self, close: impl FnOnce(&mut Pipe)) {
+        let mut pipe = self.pipe.lock().unwrap();
+        close(&mut pipe);
+        pipe.wake_reader();
+        pipe.wake_writer();
+        self.changed.notify_all();
+    }
+}
+
+/**
+ * One end of an in-memory duplex pipe, see DuplexEnd::pair. Reads wait for
+ * the other end to write, and return 0 once it has been dropped or shut
+ * down; writes wait while `capacity` bytes are waiting to be read, and fail
+ * with BrokenPipe once the other end has been dropped. The blocking
+ * implementations wait on a condition variable, so use the ends from
+ * different threads or keep transfers within capacity.
+ */
+pub struct DuplexEnd {
+    incoming: Arc<Shared>,
+    outgoing: Arc<Shared>,
+}
+
+impl DuplexEnd {
+    /**
+     * Two connected ends, with `capacity` bytes of buffering in each
+     * direction. Panics if `capacity` is 0.
+     */
+    pub fn pair(capacity: usize) -> (Self, Self) {
+        assert!(capacity > 0, "a duplex needs room for at least one byte");
+
+        let (a, b) = (Shared::new(capacity), Shared::new(capacity));
+        (
+            Self {
+                incoming: a.clone(),
+                outgoing: b.clone(),
+            },
+            Self {
+                incoming: b,
+                outgoing: a,
+            },
+        )
+    }
+
+    /**
+     * Stop writing: once the other end has read everything buffered, its
+     * reads return 0.
+     */
+    pub fn shutdown(&self) {
+        self.outgoing.close(|pipe| pipe.writer_gone = true);
+    }
+}
+
+impl Drop for DuplexEnd {
+    fn drop(&mut self) {
+        self.shutdown();
+        self.incoming.close(|pipe| pipe.reader_gone = true);
+    }
+}
+
+impl Read for DuplexEnd {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        Ok(self.incoming.wait(|pipe| pipe.read(buf)))
+    }
+}
+
+impl Write for DuplexEnd {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.outgoing.wait(|pipe| pipe.write(buf))
+    }
+
+    // Writes are visible to the other end as soon as they return
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "tokio", feature = "futures"))]
+impl DuplexEnd {
+    fn poll_read_buf(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<usize> {
+        let mut pipe = self.incoming.pipe.lock().unwrap();
+        match pipe.read(buf) {
+            Some(n) => {
+                self.incoming.changed.notify_all();
+                Poll::Ready(n)
+            }
+            None => {
+                pipe.read_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let mut pipe = self.outgoing.pipe.lock().unwrap();
+        match pipe.write(buf) {
+            Some(result) => {
+                self.outgoing.changed.notify_all();
+                Poll::Ready(result)
+            }
+            None => {
+                pipe.write_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for DuplexEnd {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let n = std::task::ready!(self.get_mut().poll_read_buf(cx, buf.initialize_unfilled()));
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for DuplexEnd {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut().poll_write_buf(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.shutdown();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_io::AsyncRead for DuplexEnd {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        self.get_mut().poll_read_buf(cx, buf).map(Ok)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_io::AsyncWrite for DuplexEnd {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut().poll_write_buf(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.shutdown();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/**
+ * A DuplexEnd that truncates its reads and writes the same way
+ * IncompleteFile does, so that client and server code can be tested against
+ * each other without a filesystem or network.
+ */
+pub struct IncompleteDuplex<P: PartialPolicy = Injector> {
+    end: DuplexEnd,
+    policy: P,
+}
+
+impl<G: Rng> IncompleteDuplex<Injector<G>> {
+    /**
+     * The seed this end's RNG was created with, see IncompleteOptions::seed.
+     */
+    pub fn seed(&self) -> Option<u64> {
+        self.policy.seed()
+    }
+
+    /**
+     * Counts of this end's operations so far, see Injector::stats.
+     */
+    pub fn stats(&self) -> IoStats {
+        self.policy.stats()
+    }
+
+    /**
+     * Every operation on this end so far, see Injector::events.
+     */
+    pub fn events(&self) -> &[IoEvent] {
+        self.policy.events()
+    }
+}
+
+impl<P: PartialPolicy> IncompleteDuplex<P> {
+    /**
+     * Wrap `end`, letting `policy` decide how every operation is truncated.
+     */
+    pub fn with_policy(end: DuplexEnd, policy: P) -> Self {
+        Self { end, policy }
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+
+    /**
+     * Stop writing, see DuplexEnd::shutdown.
+     */
+    pub fn shutdown(&self) {
+        self.end.shutdown();
+    }
+
+    /**
+     * Consume the wrapper, returning the wrapped end.
+     */
+    pub fn into_inner(mut self) -> DuplexEnd {
+        self.policy.released();
+        self.end
+    }
+}
+
+impl<P: PartialPolicy> Read for IncompleteDuplex<P> {
+    // See IncompleteFile's Read implementation
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        read_through(&mut self.policy, buf, |buf| self.end.read(buf))
+    }
+}
+
+impl<P: PartialPolicy> Write for IncompleteDuplex<P> {
+    // See IncompleteFile's Write implementation
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        write_through(&mut self.policy, buf, |buf| self.end.write(buf))
+    }
+
+    // Pass-through flush, unless the policy fails it
+    fn flush(&mut self) -> Result<()> {
+        flush_through(&mut self.policy, || self.end.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    #[test]
+    fn duplex_test() -> Result<()> {
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        let (mut client, mut server) = faulty_duplex(320);
+
+        // echo everything back until the client shuts down
+        let thread = std::thread::spawn(move || -> Result<()> {
+            let mut buffer = vec![0; 320];
+            loop {
+                let n = server.read(&mut buffer)?;
+                if n == 0 {
+                    return Ok(());
+                }
+                server.write_all(&buffer[0..n])?;
+            }
+        });
+
+        let mut echoed = vec![];
+        for chunk in random_data.chunks(320) {
+            client.write_all(chunk)?;
+            let mut buffer = vec![0; chunk.len()];
+            client.read_exact(&mut buffer)?;
+            echoed.extend_from_slice(&buffer);
+        }
+        client.shutdown();
+        thread.join().unwrap()?;
+
+        assert_eq!(echoed, random_data);
+
+        // the server has gone away
+        let e = client.write_all(b"hello").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::BrokenPipe);
+        assert_eq!(client.read(&mut [0; 16])?, 0);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn duplex_async_test() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        // far more data than fits, so the writer must wait on the reader
+        let (mut client, mut server) = faulty_duplex_async(64);
+        let (_, received) = tokio::try_join!(
+            async {
+                client.write_all(&random_data).await?;
+                client.shutdown().await
+            },
+            async {
+                let mut received = vec![];
+                server.read_to_end(&mut received).await?;
+                Ok(received)
+            },
+        )?;
+        assert_eq!(received, random_data);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// Systematically exploring every combination of decisions, like loom does
+// for thread interleavings
+
+use std::io::{Error, ErrorKind, Result};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use crate::{Decision, IncompleteOptions};
+
+/**
+ * Where a run is in the tree of decisions: which branch to take at each of
+ * the first decisions, and which were taken so far.
+ */
+#[derive(Debug, Default)]
+pub(crate) struct Explorer {
+    depth: usize,
+    path: Vec<usize>,
+    // the branch taken at each decision, and how many there were to take
+    taken: Vec<(usize, usize)>,
+    decisions: Vec<Decision>,
+}
+
+// The branches at a read or write of `len` bytes
+fn branches(len: usize) -> Vec<Decision> {
+    let mut branches = vec![Decision::Full];
+    if len > 1 {
+        branches.push(Decision::Limit(1));
+    }
+    if len > 2 {
+        branches.push(Decision::Limit(len - 1));
+    }
+    branches.push(Decision::Fail(ErrorKind::Interrupted));
+    branches
+}
+
+impl Explorer {
+    /**
+     * Decide a read or write of `len` bytes: the first `depth` decisions
+     * follow the path, and later ones are passed through.
+     */
+    pub(crate) fn decide(&mut self, len: usize) -> Decision {
+        let position = self.taken.len();
+        if position >= self.depth {
+            return Decision::Full;
+        }
+
+        let branches = branches(len);
+        let branch = self.path.get(position).copied().unwrap_or(0);
+        let branch = branch.min(branches.len() - 1);
+        self.taken.push((branch, branches.len()));
+        self.decisions.push(branches[branch]);
+        branches[branch]
+    }
+
+    // The path of the next run, in depth first order, if any is left
+    fn next_path(&self) -> Option<Vec<usize>> {
+        let last = self
+            .taken
+            .iter()
+            .rposition(|(branch, branches)| branch + 1 < *branches)?;
+        let mut path: Vec<usize> = self.taken[..last]
+            .iter()
+            .map(|(branch, _)| *branch)
+            .collect();
+        path.push(self.taken[last].0 + 1);
+        Some(path)
+    }
+}
+
+pub(crate) fn explore(
+    options: &IncompleteOptions,
+    depth: usize,
+    mut test: impl FnMut(&IncompleteOptions) -> Result<()>,
+) -> Result<usize> {
+    let mut path = vec![];
+    let mut runs = 0;
+    loop {
+        let explorer = Arc::new(Mutex::new(Explorer {
+            depth,
+            path,
+            ..Explorer::default()
+        }));
+        let mut run = options.clone();
+        run.explorer = Some(explorer.clone());
+
+        runs += 1;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| test(&run)));
+        let explorer = explorer.lock().unwrap();
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                return Err(Error::new(
+                    e.kind(),
+                    format!(
+                        "failed on run {} with decisions {:?}: {}",
+                        runs, explorer.decisions, e
+                    ),
+                ))
+            }
+            Err(payload) => {
+                eprintln!(
+                    "incomplete_file: panicked on run {} with decisions {:?}",
+                    runs, explorer.decisions
+                );
+                panic::resume_unwind(payload)
+            }
+        }
+
+        match explorer.next_path() {
+            Some(next) => path = next,
+            None => return Ok(runs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn explore_test() -> Result<()> {
+        // every branch of three reads of a 4 byte stream
+        let mut seen = std::collections::HashSet::new();
+        let runs = IncompleteOptions::default().explore(3, |options| {
+            let mut reader = options.wrap_reader(&b"abcd"[..]);
+            let mut buffer = vec![];
+            loop {
+                match reader.read_to_end(&mut buffer) {
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    result => result?,
+                };
+                break;
+            }
+            assert_eq!(buffer, b"abcd");
+            seen.insert(reader.stats().reads);
+            Ok(())
+        })?;
+        assert!(runs > 16);
+        assert!(seen.len() > 2);
+
+        // bails out only on Interrupted after a one byte read
+        let error = IncompleteOptions::default()
+            .explore(2, |options| {
+                let mut reader = options.wrap_reader(&b"abcd"[..]);
+                let mut buffer = [0; 4];
+                let first = reader.read(&mut buffer)?;
+                match reader.read(&mut buffer[first..]) {
+                    Err(e) if first == 1 => Err(e),
+                    _ => Ok(()),
+                }
+            })
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "failed on run 6 with decisions [Limit(1), Fail(Interrupted)]: injected by incomplete_file"
+        );
+
+        Ok(())
+    }
+}
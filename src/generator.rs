@@ -0,0 +1,88 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::io::{Read, Result};
+
+use crate::{IncompleteOptions, IncompleteReader};
+
+/**
+ * Generate a possibly endless stream of data from `generate` with partial
+ * reads on top, see IncompleteOptions::wrap_generator.
+ */
+pub fn generated_reader<F: FnMut(&mut [u8]) -> usize>(
+    generate: F,
+) -> IncompleteReader<GeneratedReader<F>> {
+    IncompleteOptions::new().wrap_generator(generate)
+}
+
+/**
+ * A reader that produces its data by calling a closure. The closure fills
+ * the front of the buffer it is given and returns how many bytes it wrote,
+ * or 0 to end the stream, so gigabytes of data can be fed to a parser
+ * without building them up in memory or on disk. Apply Read::take to cut an
+ * endless generator off.
+ */
+pub struct GeneratedReader<F: FnMut(&mut [u8]) -> usize> {
+    generate: F,
+}
+
+impl<F: FnMut(&mut [u8]) -> usize> GeneratedReader<F> {
+    pub fn new(generate: F) -> Self {
+        Self { generate }
+    }
+}
+
+impl<F: FnMut(&mut [u8]) -> usize> Read for GeneratedReader<F> {
+    // Let the closure fill the buffer, clamping whatever it returns
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        Ok((self.generate)(buf).min(buf.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_test() -> Result<()> {
+        // an endless stream of counting bytes
+        let mut next = 0u8;
+        let reader = generated_reader(|buf: &mut [u8]| {
+            for byte in buf.iter_mut() {
+                *byte = next;
+                next = next.wrapping_add(1);
+            }
+            buf.len()
+        });
+
+        let mut reader = reader.take(1 << 20);
+        let mut buffer = vec![0; 320];
+        let mut total = 0;
+        let mut short = false;
+        loop {
+            let n = reader.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            short |= n < buffer.len();
+
+            // only what was read was generated, so the count carries on
+            assert!(buffer[..n]
+                .iter()
+                .enumerate()
+                .all(|(i, b)| *b == (total + i) as u8));
+            total += n;
+        }
+
+        assert!(short);
+        assert_eq!(total, 1 << 20);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::io::{Read, Result, Write};
+use std::sync::{Arc, Mutex};
+
+/**
+ * An in-memory reader that simulates a growing file, as followed by
+ * `tail -f`: reads return 0 once they catch up with the data appended so
+ * far, and serve new data once more is appended through an Appender. Wrap it
+ * in an IncompleteReader to get partial reads on top.
+ */
+pub struct GrowingReader {
+    data: Arc<Mutex<Vec<u8>>>,
+    position: usize,
+}
+
+/**
+ * Appends data to a GrowingReader from elsewhere in a test. Appenders can be
+ * cloned and sent to other threads.
+ */
+#[derive(Clone)]
+pub struct Appender {
+    data: Arc<Mutex<Vec<u8>>>,
+}
+
+impl GrowingReader {
+    pub fn new() -> Self {
+        Self {
+            data: Arc::new(Mutex::new(vec![])),
+            position: 0,
+        }
+    }
+
+    pub fn appender(&self) -> Appender {
+        Appender {
+            data: self.data.clone(),
+        }
+    }
+}
+
+impl Default for GrowingReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Appender {
+    pub fn append(&self, data: &[u8]) {
+        self.data.lock().unwrap().extend_from_slice(data);
+    }
+}
+
+impl Read for GrowingReader {
+    // Serve whatever has been appended since the last read, or EOF for now
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let data = self.data.lock().unwrap();
+        let mut available = &data[self.position..];
+        let n = available.read(buf)?;
+        self.position += n;
+        Ok(n)
+    }
+}
+
+impl Write for Appender {
+    // Appending never fails or writes short
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.append(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IncompleteReader;
+
+    #[test]
+    fn follow_test() -> Result<()> {
+        let growing = GrowingReader::new();
+        let mut appender = growing.appender();
+        let mut reader = IncompleteReader::new(growing);
+
+        let mut buffer = vec![];
+        assert_eq!(reader.read_to_end(&mut buffer)?, 0);
+
+        // data appended later is served by the next reads
+        appender.write_all(b"first line\n")?;
+        reader.read_to_end(&mut buffer)?;
+        assert_eq!(buffer, b"first line\n");
+
+        let thread = std::thread::spawn(move || appender.append(b"second line\n"));
+        thread.join().unwrap();
+
+        reader.read_to_end(&mut buffer)?;
+        assert_eq!(buffer, b"first line\nsecond line\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use crate::IncompleteOptions;
+
+/**
+ * Prints the seed and options a test ran with if the test panics while
+ * the guard is alive, so that a randomized failure can be replayed instead
+ * of vanishing. See IncompleteOptions::seed_guard:
+ *
+ * ```
+ * # use incomplete_file::IncompleteOptions;
+ * let mut options = IncompleteOptions::new();
+ * let _guard = options.seed_guard();
+ * let reader = options.wrap_reader(std::io::empty());
+ * // a panic from here on prints the seed
+ * ```
+ */
+pub struct SeedGuard {
+    seed: u64,
+    options: IncompleteOptions,
+}
+
+impl SeedGuard {
+    pub(crate) fn new(seed: u64, options: &IncompleteOptions) -> Self {
+        Self {
+            seed,
+            options: options.clone(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // What is printed on a panic
+    fn message(&self) -> String {
+        format!(
+            "incomplete_file: panicked with seed {}, replay it with IncompleteOptions::seed({}). Options: {:?}",
+            self.seed, self.seed, self.options,
+        )
+    }
+}
+
+impl Drop for SeedGuard {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            eprintln!("{}", self.message());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Result};
+
+    #[test]
+    fn seed_guard_test() -> Result<()> {
+        let mut options = IncompleteOptions::default();
+        let guard = options.seed_guard();
+        assert!(guard.message().contains(&format!("seed({})", guard.seed())));
+
+        // every wrapper made afterwards uses the reported seed
+        let reader = options.wrap_reader(std::io::empty());
+        assert_eq!(reader.seed(), Some(guard.seed()));
+
+        // the guard reports without getting in the way of the panic
+        let result = std::panic::catch_unwind(move || {
+            let _guard = guard;
+            let mut reader = options.wrap_reader(std::io::empty());
+            reader.read_exact(&mut [0; 1]).unwrap();
+        });
+        assert!(result.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,388 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// One-call checks that a function copes with partial I/O
+
+use rand::Rng;
+use std::fmt::Debug;
+use std::io::{Error, Read, Result, Write};
+
+use crate::injector::derive_seed;
+use crate::{IncompleteOptions, Policy};
+
+// How many seeds each stage is run with
+const RUNS: u64 = 8;
+
+// The most bytes check_every_split splits up, some 8 million ways
+const MAX_SPLIT_LEN: usize = 24;
+
+// A stage of escalation: a name for a configuration, and how to apply it to
+// options. Unlike the Profile severity levels, none of these return Ok(0)
+// early, so correct code passes every stage.
+type Stage = (&'static str, fn(&mut IncompleteOptions));
+
+// The stages checked, from the mildest to the most hostile
+const STAGES: [Stage; 6] = [
+    ("occasional", |options| {
+        options.full_op_probability(0.75);
+    }),
+    ("uniform", |_| {}),
+    ("geometric", |options| {
+        options.policy(Policy::Geometric);
+    }),
+    ("boundary", |options| {
+        options.policy(Policy::Boundary);
+    }),
+    ("interrupted", |options| {
+        options.interrupted_probability(0.25);
+    }),
+    ("one byte", |options| {
+        options
+            .policy(Policy::OneByte)
+            .interrupted_probability(0.25);
+    }),
+];
+
+// Run `check` with options for every stage and seed, starting from the
+// options the default constructors use, so INCOMPLETE_SEED replays a run
+fn escalate(check: impl FnMut(&IncompleteOptions) -> Result<()>) -> Result<()> {
+    escalate_from(&IncompleteOptions::from_env()?, check)
+}
+
+// See escalate, deriving the seeds from the one set on `base`, if any, and
+// returning the first failure labelled with what it was run with
+fn escalate_from(
+    base: &IncompleteOptions,
+    mut check: impl FnMut(&IncompleteOptions) -> Result<()>,
+) -> Result<()> {
+    let seed = base.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    for (name, stage) in STAGES {
+        for run in 0..RUNS {
+            let mut options = base.clone();
+            stage(&mut options);
+            options.seed(derive_seed(seed, run));
+            if let Err(e) = check(&options) {
+                return Err(Error::new(
+                    e.kind(),
+                    format!(
+                        "failed at the {} stage with seed {} (INCOMPLETE_SEED={} replays it): {}. Options: {:?}",
+                        name,
+                        options.seed.unwrap(),
+                        seed,
+                        e,
+                        options,
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Check that `f` reads `data` the same way however its reads are split up.
+ * `f` is run once on `data` directly, and then again and again on a reader
+ * of it under increasingly hostile configurations: mostly complete reads,
+ * uniformly and geometrically distributed sizes, boundary sizes,
+ * Interrupted errors, and single bytes. Returns the error from the first
+ * run that failed or returned something else, labelled with the seed and
+ * options to replay it with.
+ */
+pub fn check_reader<T: PartialEq + Debug>(
+    data: &[u8],
+    mut f: impl FnMut(&mut dyn Read) -> Result<T>,
+) -> Result<()> {
+    let expected = f(&mut &data[..])?;
+    escalate(|options| {
+        let actual = f(&mut options.wrap_reader(data))?;
+        match actual == expected {
+            true => Ok(()),
+            false => Err(Error::other(format!(
+                "returned {:?} instead of {:?}",
+                actual, expected
+            ))),
+        }
+    })
+}
+
+/**
+ * Check that `f` writes the same bytes however its writes are split up,
+ * see check_reader.
+ */
+pub fn check_writer(mut f: impl FnMut(&mut dyn Write) -> Result<()>) -> Result<()> {
+    let mut expected = vec![];
+    f(&mut expected)?;
+    escalate(|options| {
+        let mut writer = options.wrap_writer(vec![]);
+        f(&mut writer)?;
+        let actual = writer.into_inner();
+        match actual == expected {
+            true => Ok(()),
+            false => Err(Error::other(format!(
+                "wrote {} bytes ({:?}...) instead of {} ({:?}...)",
+                actual.len(),
+                &actual[..actual.len().min(16)],
+                expected.len(),
+                &expected[..expected.len().min(16)],
+            ))),
+        }
+    })
+}
+
+/**
+ * Check that `f` reads `data` the same way however its first `max_len`
+ * bytes are split up, by trying every split instead of sampling them: the
+ * reads are limited to each composition of those bytes into chunks in
+ * turn, and reads past them are passed through. There are 2^(n-1) ways to
+ * split n bytes, so this is practical for headers of up to about 20 bytes,
+ * and panics if `max_len` is over 24. Returns the number of splits
+ * checked, or the first failure, labelled with the split to replay with
+ * IncompleteOptions::plan.
+ */
+pub fn check_every_split<T: PartialEq + Debug>(
+    data: &[u8],
+    max_len: usize,
+    mut f: impl FnMut(&mut dyn Read) -> Result<T>,
+) -> Result<usize> {
+    assert!(
+        max_len <= MAX_SPLIT_LEN,
+        "{} bytes is too many to split every way",
+        max_len
+    );
+    let expected = f(&mut &data[..])?;
+    let len = data.len().min(max_len);
+
+    // bit i of a split says whether a chunk ends after byte i + 1
+    let splits = 1u64 << len.saturating_sub(1);
+    for split in 0..splits {
+        let mut plan = vec![];
+        let mut start = 0;
+        for end in 1..=len {
+            if end == len || split & (1 << (end - 1)) != 0 {
+                plan.push(end - start);
+                start = end;
+            }
+        }
+
+        let label =
+            |e: Error| Error::new(e.kind(), format!("failed when split as {:?}: {}", plan, e));
+        let mut reader = IncompleteOptions::default()
+            .plan(plan.clone())
+            .wrap_reader(data);
+        let actual = f(&mut reader).map_err(label)?;
+        if actual != expected {
+            return Err(label(Error::other(format!(
+                "returned {:?} instead of {:?}",
+                actual, expected
+            ))));
+        }
+    }
+
+    Ok(splits as usize)
+}
+
+// Encode every value into one stream, then decode them all back from it,
+// with partial operations if there are options
+fn round_trip<T: PartialEq + Debug>(
+    options: Option<&IncompleteOptions>,
+    values: &[T],
+    encode: &mut impl FnMut(&T, &mut dyn Write) -> Result<()>,
+    decode: &mut impl FnMut(&mut dyn Read) -> Result<T>,
+) -> Result<()> {
+    let encoded = match options {
+        Some(options) => {
+            let mut writer = options.wrap_writer(vec![]);
+            for value in values {
+                encode(value, &mut writer)?;
+            }
+            writer.into_inner()
+        }
+        None => {
+            let mut encoded = vec![];
+            for value in values {
+                encode(value, &mut encoded)?;
+            }
+            encoded
+        }
+    };
+
+    let mut reader: Box<dyn Read + '_> = match options {
+        Some(options) => Box::new(options.wrap_reader(&encoded[..])),
+        None => Box::new(&encoded[..]),
+    };
+    for (index, value) in values.iter().enumerate() {
+        let decoded = decode(&mut reader)?;
+        if decoded != *value {
+            return Err(Error::other(format!(
+                "value {} decoded as {:?} instead of {:?}",
+                index, decoded, value
+            )));
+        }
+    }
+
+    let mut rest = vec![];
+    reader.read_to_end(&mut rest)?;
+    match rest.len() {
+        0 => Ok(()),
+        n => Err(Error::other(format!(
+            "{} of the {} bytes encoded were left over after decoding",
+            n,
+            encoded.len()
+        ))),
+    }
+}
+
+/**
+ * Check that `values` survive being encoded one after another with
+ * `encode` and decoded back with `decode`, however the writes and reads
+ * are split up. The round trip is made once directly, and then under the
+ * same configurations as check_reader, with both the encoder and the
+ * decoder seeing partial operations. A value that decodes differently, or
+ * bytes left over after decoding every value, fail the check. Returns the
+ * first failure, labelled with the seed and options to replay it with.
+ */
+pub fn check_round_trip<T: PartialEq + Debug>(
+    values: &[T],
+    mut encode: impl FnMut(&T, &mut dyn Write) -> Result<()>,
+    mut decode: impl FnMut(&mut dyn Read) -> Result<T>,
+) -> Result<()> {
+    round_trip(None, values, &mut encode, &mut decode)
+        .map_err(|e| Error::new(e.kind(), format!("failed without partial I/O: {}", e)))?;
+    escalate(|options| round_trip(Some(options), values, &mut encode, &mut decode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn check_reader_test() -> Result<()> {
+        let data = b"a fairly long line of text\n";
+        check_reader(data, |reader| {
+            let mut line = String::new();
+            std::io::BufReader::new(reader).read_to_string(&mut line)?;
+            Ok(line)
+        })?;
+
+        // a single read is not enough
+        let error = check_reader(data, |reader| {
+            let mut buffer = [0; 64];
+            let n = reader.read(&mut buffer)?;
+            Ok(buffer[..n].to_vec())
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("stage with seed"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_writer_test() -> Result<()> {
+        check_writer(|writer| writer.write_all(b"hello world"))?;
+
+        // one write is not enough either, and neither is ignoring Interrupted
+        let sloppy = |writer: &mut dyn Write| writer.write(b"hello world").map(|_| ());
+        assert!(check_writer(sloppy).is_err());
+        let error = check_writer(|writer| {
+            let mut data = &b"hello world"[..];
+            while !data.is_empty() {
+                data = &data[writer.write(data)?..];
+            }
+            Ok(())
+        })
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Interrupted);
+
+        Ok(())
+    }
+
+    #[test]
+    fn escalate_test() {
+        let seeds = |base: &IncompleteOptions| {
+            let mut seeds = vec![];
+            let _ = escalate_from(base, |options| {
+                seeds.push(options.seed.unwrap());
+                Ok(())
+            });
+            seeds
+        };
+
+        // the same base seed runs the same seeds, as INCOMPLETE_SEED does
+        let mut base = IncompleteOptions::default();
+        base.seed(7);
+        assert_eq!(seeds(&base).len(), STAGES.len() * RUNS as usize);
+        assert_eq!(seeds(&base), seeds(&base));
+
+        let error = escalate_from(&base, |_| Err(Error::other("no"))).unwrap_err();
+        assert!(error.to_string().contains("INCOMPLETE_SEED=7 replays it"));
+    }
+
+    #[test]
+    fn check_every_split_test() -> Result<()> {
+        // a two byte length and a body
+        let data = b"\x00\x04body, and then the rest";
+        let parse = |reader: &mut dyn Read| {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            let mut body = vec![0; u16::from_be_bytes(len) as usize];
+            reader.read_exact(&mut body)?;
+            Ok(body)
+        };
+        assert_eq!(check_every_split(data, 6, parse)?, 32);
+
+        // reading the length with one read breaks once the first chunk is a byte
+        let error = check_every_split(data, 6, |reader| {
+            let mut len = [0; 2];
+            let n = reader.read(&mut len)?;
+            Ok((n, u16::from_be_bytes(len)))
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("split as [1, 5]"));
+
+        // a bound past the end of the data only splits the data
+        assert_eq!(check_every_split(b"\x00\x01b", MAX_SPLIT_LEN, parse)?, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "too many to split")]
+    fn check_every_split_bound_test() {
+        let _ = check_every_split(&[0; 64], MAX_SPLIT_LEN + 1, |_| Ok(()));
+    }
+
+    #[test]
+    fn check_round_trip_test() -> Result<()> {
+        let values = ["", "one", "a longer string, to be split"].map(String::from);
+
+        // length prefixed strings
+        let encode = |value: &String, writer: &mut dyn Write| {
+            writer.write_all(&(value.len() as u32).to_le_bytes())?;
+            writer.write_all(value.as_bytes())
+        };
+        check_round_trip(&values, encode, |reader| {
+            let mut len = [0; 4];
+            reader.read_exact(&mut len)?;
+            let mut value = vec![0; u32::from_le_bytes(len) as usize];
+            reader.read_exact(&mut value)?;
+            Ok(String::from_utf8(value).unwrap())
+        })?;
+
+        // a decoder trusting a single read for the body
+        let error = check_round_trip(&values, encode, |reader| {
+            let mut len = [0; 4];
+            reader.read_exact(&mut len)?;
+            let mut value = vec![0; u32::from_le_bytes(len) as usize];
+            let n = reader.read(&mut value)?;
+            Ok(String::from_utf8_lossy(&value[..n]).into_owned())
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("decoded as"));
+
+        Ok(())
+    }
+}
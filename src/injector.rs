@@ -0,0 +1,849 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::ops::{Range, RangeInclusive};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::explore::Explorer;
+use crate::telemetry::Telemetry;
+use crate::{
+    Decision, FailureMode, IncompleteOptions, IoEvent, IoOp, IoStats, PartialPolicy, Policy,
+    UnflushedDrop,
+};
+
+/**
+ * The default PartialPolicy, built from IncompleteOptions: truncation sizes
+ * are drawn from an RNG, within the configured bounds, or taken from a plan.
+ */
+pub struct Injector<R: Rng = StdRng> {
+    rng: R,
+    seed: Option<u64>,
+    min_size: usize,
+    max_size: usize,
+    buffer_size: usize,
+    block_size: Option<usize>,
+    full_op_probability: f64,
+    interrupted_probability: f64,
+    write_zero_probability: f64,
+    spurious_eof_probability: f64,
+    would_block_probability: f64,
+    would_block_limit: Option<usize>,
+    consecutive_would_block: usize,
+    flush_errors: Option<(f64, ErrorKind)>,
+    schedule: Vec<(IoOp, usize, ErrorKind)>,
+    op_counts: HashMap<IoOp, usize>,
+    failure_mode: FailureMode,
+    dead: Option<ErrorKind>,
+    offset_triggers: Vec<(u64, ErrorKind)>,
+    op_triggers: Vec<(usize, ErrorKind)>,
+    pattern_triggers: Vec<(IoOp, Vec<u8>, ErrorKind)>,
+    // the last bytes each kind of operation moved, that could start a match,
+    // and the errors of the triggers that matched, still to be injected
+    seen: HashMap<IoOp, Vec<u8>>,
+    armed: VecDeque<ErrorKind>,
+    position: u64,
+    storage_quota: Option<u64>,
+    written: u64,
+    // bytes written since the last successful flush or sync
+    unflushed: u64,
+    on_unflushed_drop: UnflushedDrop,
+    read_only_after: Option<usize>,
+    latency: Option<RangeInclusive<Duration>>,
+    throttle: Option<u64>,
+    // when the first throttled operation started, and bytes read and
+    // written so far
+    throttle_start: Option<Instant>,
+    throttled: u64,
+    warm_up: usize,
+    calm_after: Option<usize>,
+    calm_after_bytes: Option<u64>,
+    window: Option<Range<u64>>,
+    bursts: Option<(usize, usize)>,
+    // reads and writes decided so far, and whether the current one is calm
+    io_decisions: usize,
+    calm: bool,
+    policy: Policy,
+    plan: Option<VecDeque<usize>>,
+    script: Option<VecDeque<Decision>>,
+    recorder: Option<Arc<Mutex<Vec<Decision>>>>,
+    explorer: Option<Arc<Mutex<Explorer>>>,
+    // the injectors that decide the operations configured separately
+    overrides: Vec<(IoOp, Injector)>,
+    options: IncompleteOptions,
+    forks: u64,
+    stats: IoStats,
+    events: Option<Vec<IoEvent>>,
+    telemetry: Telemetry,
+}
+
+// Forks at and above this are the injectors for IncompleteOptions::op_options
+const OVERRIDE_FORK: u64 = u64::MAX / 2;
+
+// Mix a seed and a fork number into a new seed (SplitMix64's finalizer)
+pub(crate) fn derive_seed(seed: u64, fork: u64) -> u64 {
+    let mut z = seed.wrapping_add(fork.wrapping_mul(0x9e3779b97f4a7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+impl Injector {
+    pub fn from_options(options: &IncompleteOptions) -> Self {
+        let seed = options.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        Self::build(options, StdRng::seed_from_u64(seed), Some(seed))
+    }
+
+    /**
+     * An injector with the same options for a duplicate of the wrapped
+     * object, with its own RNG. The new seed is derived from this
+     * injector's seed and how many times it has been forked, so a run that
+     * forks is as reproducible as one that does not. The fork starts at the
+     * same stream position, and is dead if this injector is.
+     */
+    pub fn fork(&mut self) -> Self {
+        self.forks += 1;
+        let seed = match self.seed {
+            Some(seed) => derive_seed(seed, self.forks),
+            None => self.rng.gen(),
+        };
+
+        let mut fork = Self::build(&self.options, StdRng::seed_from_u64(seed), Some(seed));
+        fork.position = self.position;
+        fork.written = self.written;
+        fork.dead = self.dead;
+        fork
+    }
+}
+
+impl<R: Rng> Injector<R> {
+    /**
+     * An injector with default options that draws sizes from `rng`.
+     */
+    pub fn with_rng(rng: R) -> Self {
+        Self::build(&IncompleteOptions::new(), rng, None)
+    }
+
+    fn build(options: &IncompleteOptions, mut rng: R, seed: Option<u64>) -> Self {
+        assert!(
+            options.min_size <= options.max_size,
+            "min_size {} is larger than max_size {}",
+            options.min_size,
+            options.max_size,
+        );
+
+        let overrides = options
+            .op_options
+            .iter()
+            .zip(OVERRIDE_FORK..)
+            .map(|((op, op_options), fork)| {
+                let seed = match seed {
+                    Some(seed) => derive_seed(seed, fork),
+                    None => rng.gen(),
+                };
+                let injector = Injector::build(op_options, StdRng::seed_from_u64(seed), Some(seed));
+                (*op, injector)
+            })
+            .collect();
+
+        Self {
+            rng,
+            seed,
+            min_size: options.min_size,
+            max_size: options.max_size,
+            buffer_size: options.buffer_size,
+            block_size: options.block_size,
+            full_op_probability: options.full_op_probability,
+            interrupted_probability: options.interrupted_probability,
+            write_zero_probability: options.write_zero_probability,
+            spurious_eof_probability: options.spurious_eof_probability,
+            would_block_probability: options.would_block_probability,
+            would_block_limit: options.would_block_limit,
+            consecutive_would_block: 0,
+            flush_errors: options.flush_errors,
+            schedule: options.schedule.clone(),
+            op_counts: HashMap::new(),
+            failure_mode: options.failure_mode,
+            dead: None,
+            offset_triggers: options.offset_triggers.clone(),
+            op_triggers: options.op_triggers.clone(),
+            pattern_triggers: options.pattern_triggers.clone(),
+            seen: HashMap::new(),
+            armed: VecDeque::new(),
+            position: 0,
+            storage_quota: options.storage_quota,
+            written: 0,
+            unflushed: 0,
+            on_unflushed_drop: options.on_unflushed_drop,
+            read_only_after: options.read_only_after,
+            latency: options.latency.clone(),
+            throttle: options.throttle,
+            throttle_start: None,
+            throttled: 0,
+            warm_up: options.warm_up,
+            calm_after: options.calm_after,
+            calm_after_bytes: options.calm_after_bytes,
+            window: options.window.clone(),
+            bursts: options.bursts,
+            io_decisions: 0,
+            calm: false,
+            policy: options.policy,
+            plan: options
+                .plan
+                .as_ref()
+                .map(|plan| plan.iter().copied().collect()),
+            script: options
+                .script
+                .as_ref()
+                .map(|script| script.iter().copied().collect()),
+            recorder: options.recorder.clone(),
+            explorer: options.explorer.clone(),
+            overrides,
+            options: options.clone(),
+            forks: 0,
+            stats: IoStats::default(),
+            events: options.trace.then(Vec::new),
+            telemetry: Telemetry::new(options, seed),
+        }
+    }
+
+    /**
+     * The seed this injector's RNG was created with, or None if the RNG was
+     * supplied by the caller.
+     */
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /**
+     * Counts of the operations decided so far. A fork starts counting from
+     * zero.
+     */
+    pub fn stats(&self) -> IoStats {
+        self.stats
+    }
+
+    /**
+     * How many bytes have been written since the last successful flush or
+     * sync, or since the start, see IncompleteOptions::on_unflushed_drop.
+     */
+    pub fn unflushed(&self) -> u64 {
+        self.unflushed
+    }
+
+    // Never check for unflushed writes on drop, for wrappers whose writes
+    // cannot be lost by dropping them
+    pub(crate) fn unbuffered(mut self) -> Self {
+        self.on_unflushed_drop = UnflushedDrop::Ignore;
+        for (_, injector) in &mut self.overrides {
+            injector.on_unflushed_drop = UnflushedDrop::Ignore;
+        }
+        self
+    }
+
+    /**
+     * Every operation decided so far, oldest first, if
+     * IncompleteOptions::trace was set. Otherwise this is always empty.
+     */
+    pub fn events(&self) -> &[IoEvent] {
+        self.events.as_deref().unwrap_or_default()
+    }
+
+    // Draw a size from min -> upper inclusive according to the policy
+    fn sample(&mut self, min: usize, upper: usize) -> usize {
+        match self.policy {
+            Policy::Uniform => self.rng.gen_range(min..=upper),
+
+            Policy::Geometric => {
+                // inverse transform sampling, with a success probability of
+                // 1/2 for each extra byte
+                let u = 1.0 - self.rng.gen::<f64>();
+                let extra = (u.ln() / 0.5f64.ln()).floor() as usize;
+                min.saturating_add(extra).min(upper)
+            }
+
+            Policy::HeavyTailed => {
+                // a Pareto distribution with shape 1: P(extra >= k) = 1/(k+1)
+                let u = 1.0 - self.rng.gen::<f64>();
+                let extra = (1.0 / u).floor() as usize - 1;
+                min.saturating_add(extra).min(upper)
+            }
+
+            Policy::Boundary => {
+                let mut candidates = vec![min, upper];
+                for power in (0..usize::BITS).map(|k| 1usize << k) {
+                    for size in [power - 1, power, power.saturating_add(1)] {
+                        if (min..=upper).contains(&size) {
+                            candidates.push(size);
+                        }
+                    }
+                }
+
+                candidates[self.rng.gen_range(0..candidates.len())]
+            }
+
+            Policy::Records(size) => {
+                let next = size - (self.position % size as u64) as usize;
+                let candidates: Vec<usize> = [next - 1, next, next.saturating_add(1)]
+                    .into_iter()
+                    .filter(|size| (min..=upper).contains(size))
+                    .collect();
+                match candidates.is_empty() {
+                    // as close to the boundary as the bounds allow
+                    true => next.clamp(min, upper),
+                    false => candidates[self.rng.gen_range(0..candidates.len())],
+                }
+            }
+
+            // decide never samples for this policy
+            Policy::OneByte => 1,
+
+            Policy::Bimodal => {
+                if self.rng.gen_bool(0.5) {
+                    min
+                } else {
+                    upper
+                }
+            }
+        }
+    }
+}
+
+impl<R: Rng> Injector<R> {
+    // Follow the stream position and the bytes moved, here and in the
+    // injectors for separately configured operations
+    fn track(&mut self, op: IoOp, transferred: usize) {
+        if let IoOp::Read | IoOp::Write = op {
+            self.position += transferred as u64;
+            self.throttled += transferred as u64;
+        }
+        match op {
+            IoOp::Write => {
+                self.written += transferred as u64;
+                self.unflushed += transferred as u64;
+            }
+            IoOp::Flush | IoOp::SyncAll | IoOp::SyncData => self.unflushed = 0,
+            _ => {}
+        }
+        for (_, injector) in &mut self.overrides {
+            injector.track(op, transferred);
+        }
+    }
+
+    // Whether the n'th read or write (from 0) passes through untouched
+    fn is_calm(&self, n: usize) -> bool {
+        if n < self.warm_up
+            || self.calm_after.is_some_and(|ops| n >= ops)
+            || self
+                .calm_after_bytes
+                .is_some_and(|bytes| self.throttled >= bytes)
+            || self
+                .window
+                .as_ref()
+                .is_some_and(|window| !window.contains(&self.position))
+        {
+            return true;
+        }
+        match self.bursts {
+            Some((calm, stormy)) => (n - self.warm_up) % (calm + stormy) < calm,
+            None => false,
+        }
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        !self.calm && probability > 0.0 && self.rng.gen_bool(probability)
+    }
+
+    // Inject a scheduled or triggered error, remembering it if it is sticky
+    fn inject(&mut self, kind: ErrorKind) -> Option<ErrorKind> {
+        if self.failure_mode == FailureMode::Sticky {
+            self.dead = Some(kind);
+        }
+        Some(kind)
+    }
+
+    // How many more bytes may be written before the storage quota is used up
+    fn quota_left(&self) -> Option<u64> {
+        self.storage_quota
+            .map(|quota| quota.saturating_sub(self.written))
+    }
+
+    /**
+     * Round a truncated operation of len bytes down to a whole number of
+     * blocks, but no fewer than one. Operations of less than a block, the
+     * final remainder of a transfer, go through whole.
+     */
+    fn align(&self, decision: Decision, len: usize) -> Decision {
+        match (decision, self.block_size) {
+            (Decision::Limit(size), Some(block)) if size < len => {
+                match (size / block * block).max(block) {
+                    aligned if aligned < len => Decision::Limit(aligned),
+                    _ => Decision::Full,
+                }
+            }
+            _ => decision,
+        }
+    }
+
+    /**
+     * Shorten a read or write of len bytes so that it moves no more than
+     * buffer_size bytes, stops exactly at the next offset trigger or the
+     * start of the window if it would otherwise cross them, writes no more
+     * than the storage quota has left, and moves no more than a tenth of a
+     * second's worth of bytes when throttled.
+     */
+    fn cap(&self, op: IoOp, decision: Decision, len: usize) -> Decision {
+        let next = self
+            .offset_triggers
+            .iter()
+            .map(|(offset, _)| offset.saturating_sub(self.position))
+            .chain(
+                self.window
+                    .as_ref()
+                    .filter(|window| window.start > self.position)
+                    .map(|window| window.start - self.position),
+            )
+            .min();
+        let mut cap = match next {
+            Some(distance) => distance.min(self.buffer_size as u64),
+            None => self.buffer_size as u64,
+        };
+        if let (IoOp::Write, Some(left)) = (op, self.quota_left()) {
+            cap = cap.min(left);
+        }
+        if let Some(bytes_per_second) = self.throttle {
+            cap = cap.min((bytes_per_second / 10).max(1));
+        }
+
+        match decision {
+            Decision::Full if cap < len as u64 => Decision::Limit(cap as usize),
+            Decision::Limit(size) if cap < size as u64 => Decision::Limit(cap as usize),
+            _ => decision,
+        }
+    }
+
+    /**
+     * Pick an error to fail an operation with instead of performing it, if
+     * any. Once a sticky error has been injected every operation fails with
+     * it. Otherwise scheduled errors come first, then writes and set_len
+     * fail with PermissionDenied once the file system has been remounted
+     * read-only. Then for reads and writes,
+     * offset triggers fire on the first operation at or past their offset,
+     * operation count triggers fire on the operation after their count,
+     * writes fail with StorageFull once the storage quota is used up, an
+     * interrupted_probability fraction of operations fail with
+     * Interrupted, and a would_block_probability fraction with WouldBlock
+     * (but never more than would_block_limit in a row).
+     */
+    fn fault(&mut self, op: IoOp) -> Option<ErrorKind> {
+        if self.dead.is_some() {
+            return self.dead;
+        }
+
+        let count = self.op_counts.entry(op).or_insert(0);
+        *count += 1;
+        let count = *count;
+
+        let scheduled = self
+            .schedule
+            .iter()
+            .find(|(scheduled_op, n, _)| *scheduled_op == op && *n == count);
+        if let Some((_, _, kind)) = scheduled {
+            return self.inject(*kind);
+        }
+
+        if let (IoOp::Write | IoOp::SetLen, Some(n)) = (op, self.read_only_after) {
+            let writes = self.op_counts.get(&IoOp::Write).unwrap_or(&0);
+            let earlier_writes = writes - usize::from(op == IoOp::Write);
+            if earlier_writes >= n {
+                return Some(ErrorKind::PermissionDenied);
+            }
+        }
+
+        if let (IoOp::Flush, Some((probability, kind))) = (op, self.flush_errors) {
+            // not chance, as flushes are never calm
+            if probability > 0.0 && self.rng.gen_bool(probability) {
+                return self.inject(kind);
+            }
+        }
+
+        if !matches!(op, IoOp::Read | IoOp::Write) {
+            return None;
+        }
+
+        let position = self.position;
+        let triggered = self
+            .offset_triggers
+            .iter()
+            .position(|(offset, _)| *offset <= position);
+        if let Some(index) = triggered {
+            let (_, kind) = self.offset_triggers.remove(index);
+            return self.inject(kind);
+        }
+
+        if let Some(kind) = self.armed.pop_front() {
+            return self.inject(kind);
+        }
+
+        let io_ops = self.op_counts.get(&IoOp::Read).unwrap_or(&0)
+            + self.op_counts.get(&IoOp::Write).unwrap_or(&0);
+        let triggered = self.op_triggers.iter().find(|(n, _)| io_ops == n + 1);
+        if let Some((_, kind)) = triggered {
+            return self.inject(*kind);
+        }
+
+        if op == IoOp::Write && self.quota_left() == Some(0) {
+            return Some(ErrorKind::StorageFull);
+        }
+
+        if self.chance(self.interrupted_probability) {
+            return Some(ErrorKind::Interrupted);
+        }
+
+        let may_block = match self.would_block_limit {
+            Some(limit) => self.consecutive_would_block < limit,
+            None => true,
+        };
+        if may_block && self.chance(self.would_block_probability) {
+            self.consecutive_would_block += 1;
+            return Some(ErrorKind::WouldBlock);
+        }
+        self.consecutive_would_block = 0;
+
+        None
+    }
+
+    /**
+     * Pick a size to truncate an operation of len bytes to: at least
+     * min_size, at most max_size, and always less than len. Buffers that are
+     * too small to be truncated within those bounds are passed through, as
+     * are a full_op_probability fraction of all other operations (up to
+     * max_size).
+     *
+     * Sizes are drawn according to the configured Policy.
+     *
+     * If a plan was supplied it replaces all of the above: each operation is
+     * limited to the next size in the plan, and passed through once the plan
+     * runs out.
+     */
+    fn size(&mut self, len: usize) -> Decision {
+        if let Some(plan) = &mut self.plan {
+            return match plan.pop_front() {
+                Some(size) => Decision::Limit(size),
+                None => Decision::Full,
+            };
+        }
+
+        if self.policy == Policy::OneByte {
+            return Decision::Limit(1);
+        }
+
+        let upper = self.max_size.min(len.saturating_sub(1));
+        if upper < self.min_size {
+            return Decision::Full;
+        }
+
+        if self.chance(self.full_op_probability) {
+            return Decision::Limit(self.max_size);
+        }
+
+        Decision::Limit(self.sample(self.min_size, upper))
+    }
+}
+
+impl<R: Rng> Drop for Injector<R> {
+    // See IncompleteOptions::on_unflushed_drop
+    fn drop(&mut self) {
+        if self.unflushed == 0 || std::thread::panicking() {
+            return;
+        }
+        let message = format!(
+            "incomplete_file: dropped with {} bytes written since the last flush (seed {:?})",
+            self.unflushed, self.seed
+        );
+        match self.on_unflushed_drop {
+            UnflushedDrop::Ignore => {}
+            UnflushedDrop::Warn => eprintln!("{}", message),
+            UnflushedDrop::Panic => panic!("{}", message),
+        }
+    }
+}
+
+impl<R: Rng> PartialPolicy for Injector<R> {
+    fn decide(&mut self, op: IoOp, len: usize) -> Decision {
+        let decision = self.choose(op, len);
+        if let (IoOp::Read | IoOp::Write, Some(recorder)) = (op, &self.recorder) {
+            recorder.lock().unwrap().push(decision);
+        }
+        self.stats.decided(op, len, decision);
+        let event = IoEvent::new(op, len, self.position, decision);
+        self.telemetry.decided(&event);
+        if let Some(events) = &mut self.events {
+            events.push(event);
+        }
+        decision
+    }
+
+    fn delay(&mut self, op: IoOp) -> Duration {
+        if let Some((_, injector)) = self.overrides.iter_mut().find(|(o, _)| *o == op) {
+            return injector.delay(op);
+        }
+
+        let mut delay = Duration::ZERO;
+        if let (IoOp::Read | IoOp::Write | IoOp::Flush, Some(latency)) = (op, &self.latency) {
+            delay = if latency.start() == latency.end() {
+                *latency.start()
+            } else {
+                self.rng.gen_range(latency.clone())
+            };
+        }
+
+        // wait until everything moved so far would have taken at the
+        // throttled rate, which the latency counts towards
+        if let (IoOp::Read | IoOp::Write, Some(bytes_per_second)) = (op, self.throttle) {
+            let start = *self.throttle_start.get_or_insert_with(Instant::now);
+            let due =
+                start + Duration::from_secs_f64(self.throttled as f64 / bytes_per_second as f64);
+            delay = delay.max(due.saturating_duration_since(Instant::now()));
+        }
+        delay
+    }
+
+    fn completed(&mut self, op: IoOp, transferred: usize) {
+        self.stats.completed(op, transferred);
+        self.telemetry.completed(op, transferred);
+        self.track(op, transferred);
+    }
+
+    fn observed(&mut self, op: IoOp, data: &[u8]) {
+        for (_, injector) in &mut self.overrides {
+            injector.observed(op, data);
+        }
+        if !self.pattern_triggers.iter().any(|(o, _, _)| *o == op) {
+            return;
+        }
+
+        let seen = self.seen.entry(op).or_default();
+        seen.extend_from_slice(data);
+        let mut i = 0;
+        while i < self.pattern_triggers.len() {
+            let (o, pattern, kind) = &self.pattern_triggers[i];
+            if *o == op && seen.windows(pattern.len()).any(|window| window == pattern) {
+                self.armed.push_back(*kind);
+                self.pattern_triggers.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        // keep just enough to find a match that ends in a later operation
+        let longest = self
+            .pattern_triggers
+            .iter()
+            .filter(|(o, _, _)| *o == op)
+            .map(|(_, pattern, _)| pattern.len())
+            .max()
+            .unwrap_or(1);
+        let drop = seen.len().saturating_sub(longest - 1);
+        seen.drain(..drop);
+    }
+
+    fn seeked(&mut self, position: u64) {
+        self.position = position;
+        for (_, injector) in &mut self.overrides {
+            injector.seeked(position);
+        }
+    }
+
+    fn released(&mut self) {
+        self.unflushed = 0;
+        for (_, injector) in &mut self.overrides {
+            injector.released();
+        }
+    }
+}
+
+impl<R: Rng> Injector<R> {
+    // Make the decision that PartialPolicy::decide returns
+    fn choose(&mut self, op: IoOp, len: usize) -> Decision {
+        if let Some((_, injector)) = self.overrides.iter_mut().find(|(o, _)| *o == op) {
+            return injector.choose(op, len);
+        }
+
+        if let (IoOp::Read | IoOp::Write, Some(script)) = (op, &mut self.script) {
+            return script.pop_front().unwrap_or(Decision::Full);
+        }
+        if let (IoOp::Read | IoOp::Write, Some(explorer)) = (op, &self.explorer) {
+            return explorer.lock().unwrap().decide(len);
+        }
+
+        if let IoOp::Read | IoOp::Write = op {
+            self.calm = self.is_calm(self.io_decisions);
+            self.io_decisions += 1;
+        }
+
+        match self.fault(op) {
+            Some(kind) => Decision::Fail(kind),
+            None if !matches!(op, IoOp::Read | IoOp::Write) => Decision::Full,
+            None if self.calm => self.cap(op, Decision::Full, len),
+            None if op == IoOp::Write && self.chance(self.write_zero_probability) => Decision::Zero,
+            None if op == IoOp::Read && self.chance(self.spurious_eof_probability) => {
+                Decision::Zero
+            }
+            None => {
+                let decision = self.size(len);
+                let decision = self.align(decision, len);
+                self.cap(op, decision, len)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Draw n sizes for operations of len bytes
+    fn sizes_i(options: &IncompleteOptions, len: usize, n: usize) -> Vec<usize> {
+        let mut injector = Injector::from_options(options);
+        (0..n)
+            .map(|_| match injector.decide(IoOp::Read, len) {
+                Decision::Limit(size) => size,
+                decision => panic!("unexpected {:?}", decision),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn policy_test() {
+        let mut options = IncompleteOptions::default();
+        options.min_size(4).max_size(1000);
+
+        for policy in [
+            Policy::Uniform,
+            Policy::Geometric,
+            Policy::HeavyTailed,
+            Policy::Bimodal,
+            Policy::Boundary,
+        ] {
+            options.policy(policy);
+            let sizes = sizes_i(&options, 4096, 1000);
+            assert!(sizes.iter().all(|n| (4..=1000).contains(n)), "{:?}", policy);
+        }
+
+        // geometric sizes are mostly tiny
+        options.policy(Policy::Geometric);
+        let sizes = sizes_i(&options, 4096, 1000);
+        assert!(sizes.iter().filter(|n| **n <= 8).count() > 900);
+
+        // bimodal sizes are only ever the smallest or largest allowed
+        options.policy(Policy::Bimodal);
+        let sizes = sizes_i(&options, 4096, 1000);
+        assert!(sizes.iter().all(|n| *n == 4 || *n == 1000));
+        assert!(sizes.contains(&4) && sizes.contains(&1000));
+
+        // boundary sizes are the extremes or next to a power of two
+        options
+            .min_size(1)
+            .max_size(usize::MAX)
+            .policy(Policy::Boundary);
+        let sizes = sizes_i(&options, 320, 1000);
+        assert!(sizes.iter().all(
+            |n| [1, 319].contains(n) || [n - 1, *n, n + 1].iter().any(|m| m.is_power_of_two())
+        ));
+        assert!(sizes.contains(&1) && sizes.contains(&319) && sizes.contains(&257));
+    }
+
+    #[test]
+    fn boundary_test() -> std::io::Result<()> {
+        use std::io::Read;
+
+        let data: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+        let mut reader = IncompleteOptions::default()
+            .seed(1)
+            .policy(Policy::Boundary)
+            .wrap_reader(&data[..]);
+
+        // reads into a 256 byte buffer stop one short of filling it, or
+        // next to some smaller power of two, but the data is all there
+        let mut sizes = vec![];
+        let mut buffer = [0; 256];
+        let mut actual = vec![];
+        loop {
+            match reader.read(&mut buffer)? {
+                0 => break,
+                n => {
+                    sizes.push(n);
+                    actual.extend_from_slice(&buffer[..n]);
+                }
+            }
+        }
+        assert_eq!(actual, data);
+        assert!(sizes.contains(&1) && sizes.contains(&255));
+        assert!(sizes.iter().any(|n| [3, 4, 5, 63, 64, 65].contains(n)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stats_test() -> std::io::Result<()> {
+        use std::io::Write;
+
+        let mut writer = IncompleteOptions::default()
+            .plan([2, 2])
+            .fail_on(IoOp::Flush, 1, ErrorKind::Other)
+            .wrap_writer(vec![]);
+        writer.write_all(b"hello")?;
+        assert!(writer.flush().is_err());
+        writer.flush()?;
+
+        let stats = writer.stats();
+        assert_eq!(stats.writes, 3);
+        assert_eq!(stats.flushes, 2);
+        assert_eq!(stats.bytes_requested, 5 + 3 + 1);
+        assert_eq!(stats.bytes_transferred, 5);
+        assert_eq!(stats.truncated, 2);
+        assert_eq!(stats.injected_errors, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn trace_test() -> std::io::Result<()> {
+        use std::io::Read;
+
+        let data = b"hello world";
+        let mut reader = IncompleteOptions::default()
+            .plan([5])
+            .fail_on(IoOp::Read, 2, ErrorKind::Interrupted)
+            .trace(true)
+            .wrap_reader(&data[..]);
+        let mut buffer = [0; 8];
+        assert_eq!(reader.read(&mut buffer)?, 5);
+        assert!(reader.read(&mut buffer).is_err());
+        assert_eq!(reader.read(&mut buffer)?, 6);
+
+        let events = reader.events();
+        assert_eq!(
+            events[0],
+            IoEvent::new(IoOp::Read, 8, 0, Decision::Limit(5))
+        );
+        assert_eq!(events[1].injected, Some(ErrorKind::Interrupted));
+        assert_eq!((events[2].offset, events[2].granted), (5, 8));
+
+        // tracing is off by default
+        assert!(IncompleteOptions::default()
+            .wrap_reader(&data[..])
+            .events()
+            .is_empty());
+
+        Ok(())
+    }
+}
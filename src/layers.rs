@@ -0,0 +1,398 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// Single faults as layers that stack around any I/O object in any order
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io::{ErrorKind, IoSlice, IoSliceMut, Read, Result, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+use crate::policy::{
+    flush_through, permit, read_through, read_vectored_through, write_through,
+    write_vectored_through,
+};
+use crate::{Decision, IoOp, PartialPolicy, Splitter, Splitting};
+
+/**
+ * Applies one PartialPolicy to whatever `T` supports of Read, Write, and
+ * Seek. Unlike the other wrappers it needs neither a Read nor a Write, so
+ * layers can be stacked in any order, see LayerExt.
+ */
+pub struct Layered<T, P: PartialPolicy> {
+    inner: T,
+    policy: P,
+}
+
+impl<T, P: PartialPolicy> Layered<T, P> {
+    pub fn new(inner: T, policy: P) -> Self {
+        Self { inner, policy }
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /**
+     * Consume the layer, returning what it was layered over.
+     */
+    pub fn into_inner(mut self) -> T {
+        self.policy.released();
+        self.inner
+    }
+}
+
+impl<T: Read, P: PartialPolicy> Read for Layered<T, P> {
+    // See IncompleteFile's Read implementation
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        read_through(&mut self.policy, buf, |buf| self.inner.read(buf))
+    }
+
+    // See IncompleteFile's Read implementation
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        read_vectored_through(&mut self.policy, bufs, |buf| self.inner.read(buf))
+    }
+}
+
+impl<T: Write, P: PartialPolicy> Write for Layered<T, P> {
+    // See IncompleteFile's Write implementation
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        write_through(&mut self.policy, buf, |buf| self.inner.write(buf))
+    }
+
+    // See IncompleteFile's Write implementation
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        write_vectored_through(&mut self.policy, bufs, |bufs| {
+            self.inner.write_vectored(bufs)
+        })
+    }
+
+    // Pass-through flush, unless the policy fails it
+    fn flush(&mut self) -> Result<()> {
+        flush_through(&mut self.policy, || self.inner.flush())
+    }
+}
+
+impl<T: Seek, P: PartialPolicy> Seek for Layered<T, P> {
+    // Pass-through seek, unless the policy fails it
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        permit(&mut self.policy, IoOp::Seek)?;
+        let position = self.inner.seek(pos)?;
+        self.policy.seeked(position);
+        Ok(position)
+    }
+}
+
+// A random size for a short operation on `requested` bytes
+fn short(rng: &mut StdRng, requested: usize) -> Decision {
+    match requested {
+        0 | 1 => Decision::Full,
+        _ => Decision::Limit(rng.gen_range(1..requested)),
+    }
+}
+
+/**
+ * A layer that truncates every read to a uniformly random size, and leaves
+ * everything else alone.
+ */
+pub struct ShortRead {
+    rng: StdRng,
+}
+
+impl ShortRead {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl PartialPolicy for ShortRead {
+    fn decide(&mut self, op: IoOp, requested: usize) -> Decision {
+        match op {
+            IoOp::Read => short(&mut self.rng, requested),
+            _ => Decision::Full,
+        }
+    }
+}
+
+/**
+ * A layer that truncates every write, see ShortRead.
+ */
+pub struct ShortWrite {
+    rng: StdRng,
+}
+
+impl ShortWrite {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl PartialPolicy for ShortWrite {
+    fn decide(&mut self, op: IoOp, requested: usize) -> Decision {
+        match op {
+            IoOp::Write => short(&mut self.rng, requested),
+            _ => Decision::Full,
+        }
+    }
+}
+
+/**
+ * A layer that fails a `probability` fraction of one kind of operation
+ * with an error of `kind`, and leaves everything else alone.
+ */
+pub struct ErrorInject {
+    rng: StdRng,
+    op: IoOp,
+    kind: ErrorKind,
+    probability: f64,
+}
+
+impl ErrorInject {
+    pub fn new(op: IoOp, kind: ErrorKind, probability: f64, seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            op,
+            kind,
+            probability: crate::options::check_probability(probability),
+        }
+    }
+}
+
+impl PartialPolicy for ErrorInject {
+    fn decide(&mut self, op: IoOp, _requested: usize) -> Decision {
+        match op == self.op && self.rng.gen_bool(self.probability) {
+            true => Decision::Fail(self.kind),
+            false => Decision::Full,
+        }
+    }
+}
+
+/**
+ * A layer that sleeps for between `min` and `max` before every read,
+ * write, and flush, and leaves them otherwise alone. See
+ * IncompleteOptions::latency.
+ */
+pub struct Delay {
+    rng: StdRng,
+    min: Duration,
+    max: Duration,
+}
+
+impl Delay {
+    pub fn new(min: Duration, max: Duration, seed: u64) -> Self {
+        assert!(min <= max, "latency {:?} is longer than {:?}", min, max);
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            min,
+            max,
+        }
+    }
+}
+
+impl PartialPolicy for Delay {
+    fn decide(&mut self, op: IoOp, _requested: usize) -> Decision {
+        if let IoOp::Read | IoOp::Write | IoOp::Flush = op {
+            std::thread::sleep(self.rng.gen_range(self.min..=self.max));
+        }
+        Decision::Full
+    }
+}
+
+/**
+ * A layer that flips a random bit in a `probability` fraction of the bytes
+ * read or written through it, for checking that checksums and parsers
+ * catch damaged data. Writes are corrupted on their way to the inner
+ * writer, and the caller's buffer is left alone.
+ */
+pub struct Corrupt<T> {
+    inner: T,
+    rng: StdRng,
+    probability: f64,
+}
+
+impl<T> Corrupt<T> {
+    pub fn new(inner: T, probability: f64, seed: u64) -> Self {
+        Self {
+            inner,
+            rng: StdRng::seed_from_u64(seed),
+            probability: crate::options::check_probability(probability),
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /**
+     * Consume the layer, returning what it was layered over.
+     */
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    // Flip a bit in some of `data`
+    fn corrupt(&mut self, data: &mut [u8]) {
+        if self.probability == 0.0 {
+            return;
+        }
+        for byte in data {
+            if self.rng.gen_bool(self.probability) {
+                *byte ^= 1 << self.rng.gen_range(0..8);
+            }
+        }
+    }
+}
+
+impl<T: Read> Read for Corrupt<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.corrupt(&mut buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Corrupt<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut corrupted = buf.to_vec();
+        self.corrupt(&mut corrupted);
+        self.inner.write(&corrupted)
+    }
+
+    // Pass-through
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Seek> Seek for Corrupt<T> {
+    // Pass-through
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/**
+ * Stack layers around any value, innermost first:
+ *
+ * ```
+ * # use incomplete_file::{Corrupt, ErrorInject, IoOp, LayerExt, ShortRead};
+ * # use std::io::ErrorKind;
+ * let reader = std::io::empty()
+ *     .corrupt(0.001, 1)
+ *     .layer(ShortRead::new(2))
+ *     .layer(ErrorInject::new(IoOp::Read, ErrorKind::Interrupted, 0.1, 3));
+ * ```
+ */
+pub trait LayerExt: Sized {
+    /**
+     * Apply `policy` to this value's operations, see Layered.
+     */
+    fn layer<P: PartialPolicy>(self, policy: P) -> Layered<Self, P> {
+        Layered::new(self, policy)
+    }
+
+    /**
+     * Corrupt the data read and written through this value, see Corrupt.
+     */
+    fn corrupt(self, probability: f64, seed: u64) -> Corrupt<Self> {
+        Corrupt::new(self, probability, seed)
+    }
+
+    /**
+     * Cut up the data read and written through this value as `splitter`
+     * decides, see Splitting.
+     */
+    fn split_by<S: Splitter>(self, splitter: S) -> Splitting<Self, S> {
+        Splitting::new(self, splitter)
+    }
+}
+
+impl<T> LayerExt for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::Instant;
+
+    #[test]
+    fn layers_test() -> Result<()> {
+        let data: Vec<u8> = (0..=255).collect();
+
+        // short reads under retried Interrupted errors
+        let mut reader = (&data[..]).layer(ShortRead::new(1)).layer(ErrorInject::new(
+            IoOp::Read,
+            ErrorKind::Interrupted,
+            0.5,
+            2,
+        ));
+        let mut buffer = vec![0; 256];
+        let n = loop {
+            match reader.read(&mut buffer) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+        assert!(n < 256);
+        buffer.truncate(n);
+        reader.read_to_end(&mut buffer)?;
+        assert_eq!(buffer, data);
+
+        // writes are split, and reads and seeks left alone
+        let mut file = Cursor::new(vec![]).layer(ShortWrite::new(3));
+        assert!(file.write(&data)? < 256);
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&data)?;
+        file.seek(SeekFrom::Start(0))?;
+        assert_eq!(file.read(&mut [0; 256])?, 256);
+
+        // a layer only fails the kind of operation it was made for
+        let mut writer = vec![].layer(ErrorInject::new(IoOp::Flush, ErrorKind::Other, 1.0, 4));
+        writer.write_all(b"hello")?;
+        assert_eq!(writer.flush().unwrap_err().kind(), ErrorKind::Other);
+
+        // every byte corrupted, or none
+        let mut reader = (&data[..]).corrupt(1.0, 5);
+        let mut corrupted = vec![];
+        reader.read_to_end(&mut corrupted)?;
+        assert!(corrupted
+            .iter()
+            .zip(&data)
+            .all(|(a, b)| (a ^ b).count_ones() == 1));
+        let mut writer = vec![].corrupt(0.0, 6);
+        writer.write_all(&data)?;
+        assert_eq!(writer.into_inner(), data);
+
+        let start = Instant::now();
+        let delay = Duration::from_millis(5);
+        let mut writer = vec![].corrupt(0.0, 7).layer(Delay::new(delay, delay, 8));
+        writer.write_all(b"hello")?;
+        writer.flush()?;
+        assert!(start.elapsed() >= delay * 2);
+
+        Ok(())
+    }
+}
@@ -9,46 +9,451 @@
 //! Code must be written to handle this case and this can go untested.
 //!
 //! This crate provides "IncompleteFile" that truncates the read and write size
-//! and allows testing of those code paths.
+//! and allows testing of those code paths. "IncompleteReader" and
+//! "IncompleteWriter" do the same for any other std::io::Read or
+//! std::io::Write implementation. "IncompleteStream" does both for sockets,
+//! and "IncompleteCursor" does the same over an in-memory buffer with no
+//! filesystem access. "ChunkedReader" serves an exact, hand-crafted sequence of
+//! partial reads, and "generated_reader" streams endless data from a closure.
+//! "incomplete_stdin", "incomplete_stdout", and "incomplete_stderr" lock and
+//! wrap the standard streams, and "IncompleteChild" does the same for a child
+//! process's stdin and stdout. "incomplete_pipe" wraps both ends of an OS pipe.
+//!
+//! "IncompleteOptions" configures how operations are truncated, and can open,
+//! create, or wrap any number of files, readers, and writers.
+//! IncompleteOptions::profile sets the truncation and error options to one of
+//! the "Profile" severity levels: Mild, Aggressive, or Pathological. Its
+//! op_options configures one kind of operation independently of the rest, and
+//! faithful passes one through untouched, for example to keep writes intact
+//! while reads are chaotic. Its warm_up passes the first few operations
+//! through, calm_after and calm_after_bytes pass the rest through once a test
+//! is far enough in, window aims the chaos at a range of offsets, block_size
+//! truncates only to whole blocks, and bursts alternates stretches of
+//! passed-through operations with stretches of chaos. Its fail_after_pattern
+//! fails the next operation once some bytes have been read or written, like a
+//! commit marker in a log, and flush_error_probability fails flushes at random.
+//! Its on_unflushed_drop warns or panics when a wrapper is dropped with writes
+//! that were never flushed, as an "UnflushedDrop" says. Its seed_guard returns
+//! a "SeedGuard" that prints the seed if the test panics, so the failure can be
+//! replayed. IncompleteOptions::new and the default constructors also read
+//! overrides from INCOMPLETE_SEED, INCOMPLETE_PROFILE, and the other variables
+//! apply_env lists, so CI can replay a seed or make tests more aggressive
+//! without code changes. "IncompleteOpenOptions" opens files in any
+//! std::fs::OpenOptions mode, and with the "direct" feature on Linux, with
+//! O_DIRECT, holding reads and writes to its alignment rules as
+//! IncompleteOptions::direct_io does. With the "tempfile" feature,
+//! IncompleteFile::tempfile and named_temp_in create throwaway files.
+//!
+//! IncompleteOptions::check_seeds runs a test over many seeds and appends any
+//! that fail to incomplete-regressions.txt, replaying them first on every later
+//! run. IncompleteOptions::shrink cuts a failing run down to the shortest
+//! script of decisions that still fails, which IncompleteOptions::script
+//! replays. With the "proptest" feature, plan_strategy, script_strategy,
+//! schedule_strategy, and options_strategy generate plans, scripts, error
+//! schedules, and options for property tests, and proptest shrinks them. The
+//! "quickcheck" feature implements quickcheck's Arbitrary for
+//! IncompleteOptions, Decision, Policy, IoOp, and FailureMode instead, and the
+//! "arbitrary" feature implements the arbitrary crate's, so cargo-fuzz targets
+//! can take the whole schedule from their input. With the "macros" feature, the
+//! #[incomplete_test] attribute turns a function taking &IncompleteOptions into
+//! a test that runs it under check_seeds. "check_reader" and "check_writer"
+//! certify in one call that a function handles partial I/O, by running it under
+//! increasingly hostile configurations and reporting the first one that changes
+//! what it reads or writes. "check_round_trip" does the same for an encoder and
+//! decoder pair, checking that sample values decode back to themselves, and
+//! "check_every_split" tries every way of splitting the first bytes of a stream
+//! instead of sampling them. IncompleteOptions::explore goes further, trying
+//! every combination of a few decisions for a test as loom does for thread
+//! interleavings, and IncompleteOptions::soak runs a test with new seeds on
+//! many threads for as long as a time budget allows, collecting the failing
+//! seeds in a "SoakReport".
+//!
+//! IncompleteFile can also fail set_len, sync_all, and sync_data. With
+//! IncompleteOptions::lose_unsynced_writes, a failed sync throws away
+//! everything written since the last successful one, as Linux can after fsync
+//! reports an error, and with simulate_crashes, IncompleteFile::crash leaves
+//! behind what a power loss would, for testing crash recovery.
+//! IncompleteOptions::crash_points checks recovery against a crash at every
+//! point of a workload, including part way through each write, and
+//! reorder_unsynced_writes lets a crash persist unsynced writes out of order.
+//! sector_size makes it tear writes only at sector boundaries.
+//!
+//! Every wrapper is generic over a "PartialPolicy" that decides how each
+//! operation is truncated. "Injector", built from IncompleteOptions, is the
+//! default; implement the trait (or pass a closure) for anything else. To
+//! combine only some behaviors, "LayerExt" stacks single faults around any I/O
+//! object in any order: "ShortRead", "ShortWrite", "ErrorInject", and "Delay"
+//! are policies that "Layered" applies, and "Corrupt" flips bits in the data.
+//! "Splitting" cuts reads and writes where a "Splitter" finds the data most
+//! fragile: "Delimiter" moves each delimiter byte on its own, and
+//! "LengthPrefix" splits the length prefixes of frames after every byte, and
+//! "Utf8Sequences" splits inside every multi-byte character. Wrappers using an
+//! Injector report "IoStats" counts of their operations, including a
+//! "SizeHistogram" of the sizes reads and writes were allowed and a
+//! "FaultCoverage" of the kinds of fault they delivered. With
+//! IncompleteOptions::trace, they also record an "IoEvent" for every operation,
+//! which "write_json_lines" exports. With the "tracing" feature, every Injector
+//! also opens a tracing span and emits an event for each decision in it. The
+//! "log" feature logs each decision instead, at a level set with
+//! IncompleteOptions::log_level. The "metrics" feature publishes counters and a
+//! histogram of granted sizes through the metrics crate, for observing
+//! long-running chaos tests and ChaosProxy.
+//!
+//! With the "tokio" feature, "IncompleteAsync" and "IncompleteAsyncFile" do the
+//! same for tokio's AsyncRead and AsyncWrite. With the "futures" feature,
+//! IncompleteAsync also implements the futures crate's AsyncRead and
+//! AsyncWrite, for use with any other runtime, and the "smol" feature adds
+//! "IncompleteSmolFile" for smol and async-std projects.
+//!
+//! "faulty_duplex" connects two in-memory endpoints with partial reads and
+//! writes on both ends, for testing clients and servers against each other.
+//! "ChaosProxy" relays TCP connections in fragments, so that unmodified
+//! clients and servers can be tested against partial network I/O.
+//!
+//! The "incomplete-cat" binary copies stdin to stdout with partial reads and
+//! writes, to chaos test the programs on either side of a shell pipeline, and
+//! can fail part way through or slow the copy down.
 
-use rand::rngs::ThreadRng;
 use rand::Rng;
 use std::fs::File;
 use std::fs::Metadata;
-use std::io::{Read, Result, Seek, SeekFrom, Write};
+use std::io::{IoSlice, IoSliceMut, Read, Result, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impls;
+#[cfg(any(feature = "tokio", feature = "futures"))]
+mod async_io;
+mod chunked;
+mod cursor;
+mod direct;
+mod duplex;
+mod explore;
+mod generator;
+mod growing;
+mod guard;
+mod harness;
+mod injector;
+mod layers;
+mod open_options;
+mod options;
+mod persistence;
+mod pipe;
+mod policy;
+mod process;
+mod proxy;
+#[cfg(feature = "quickcheck")]
+mod quickcheck_impls;
+mod reader;
+mod regressions;
+mod shrink;
+mod soak;
+mod split;
+mod stats;
+mod stdio;
+#[cfg(feature = "proptest")]
+mod strategy;
+mod stream;
+mod telemetry;
+mod writer;
+
+#[cfg(any(feature = "tokio", feature = "futures"))]
+pub use async_io::IncompleteAsync;
+#[cfg(feature = "tokio")]
+pub use async_io::IncompleteAsyncFile;
+#[cfg(feature = "smol")]
+pub use async_io::IncompleteSmolFile;
+pub use chunked::ChunkedReader;
+pub use cursor::IncompleteCursor;
+#[cfg(any(feature = "tokio", feature = "futures"))]
+pub use duplex::faulty_duplex_async;
+pub use duplex::{faulty_duplex, DuplexEnd, IncompleteDuplex};
+pub use generator::{generated_reader, GeneratedReader};
+pub use growing::{Appender, GrowingReader};
+pub use guard::SeedGuard;
+pub use harness::{check_every_split, check_reader, check_round_trip, check_writer};
+#[cfg(feature = "macros")]
+pub use incomplete_file_macros::incomplete_test;
+pub use injector::Injector;
+pub use layers::{Corrupt, Delay, ErrorInject, LayerExt, Layered, ShortRead, ShortWrite};
+pub use open_options::IncompleteOpenOptions;
+pub use options::IncompleteOptions;
+pub use pipe::incomplete_pipe;
+pub use policy::{Decision, FailureMode, IoOp, PartialPolicy, Policy, Profile, UnflushedDrop};
+pub use process::IncompleteChild;
+pub use proxy::ChaosProxy;
+pub use reader::IncompleteReader;
+pub use soak::SoakReport;
+pub use split::{Delimiter, LengthPrefix, Splitter, Splitting, Utf8Sequences};
+pub use stats::{write_json_lines, Fault, FaultCoverage, IoEvent, IoStats, SizeHistogram};
+pub use stdio::{
+    incomplete_stderr, incomplete_stdin, incomplete_stdout, IncompleteStderr, IncompleteStdin,
+    IncompleteStdout,
+};
+#[cfg(feature = "proptest")]
+pub use strategy::{options_strategy, plan_strategy, schedule_strategy, script_strategy};
+#[cfg(unix)]
+pub use stream::incomplete_unix_pair;
+pub use stream::IncompleteStream;
+pub use writer::IncompleteWriter;
 
-pub struct IncompleteFile {
+// lets the tests use incomplete_test, which names this crate
+#[cfg(all(test, feature = "macros"))]
+extern crate self as incomplete_file;
+
+use persistence::Persistence;
+#[cfg(any(unix, windows))]
+use policy::positional_through;
+use policy::{
+    flush_through, permit, read_through, read_vectored_through, write_through,
+    write_vectored_through,
+};
+
+/**
+ * The policy is kept behind a Mutex because positional reads and writes
+ * (FileExt on Unix) only borrow the file, but still need a decision. So is
+ * the model of what has been synced, when IncompleteOptions asks for one.
+ */
+pub struct IncompleteFile<P: PartialPolicy = Injector> {
     file: File,
-    rng: ThreadRng,
+    policy: Mutex<P>,
+    persistence: Mutex<Option<Persistence>>,
+    // what direct I/O must be aligned to, see IncompleteOptions::direct_io
+    alignment: Option<usize>,
 }
 
 impl IncompleteFile {
+    /**
+     * Wrap a file that is already open, for example one opened with
+     * std::fs::OpenOptions or made by tempfile::tempfile, keeping the flags
+     * it was opened with.
+     */
+    #[allow(dead_code)]
+    pub fn with_file(file: File) -> Self {
+        IncompleteOptions::new().wrap_file(file)
+    }
+
+    /**
+     * An anonymous temporary file, deleted when it is closed.
+     */
+    #[cfg(feature = "tempfile")]
+    pub fn tempfile() -> Result<Self> {
+        IncompleteOptions::from_env()?.tempfile()
+    }
+
+    /**
+     * A named temporary file in `dir`, see IncompleteOptions::named_temp_in.
+     */
+    #[cfg(feature = "tempfile")]
+    pub fn named_temp_in(dir: &dyn AsRef<Path>) -> Result<(Self, tempfile::TempPath)> {
+        IncompleteOptions::from_env()?.named_temp_in(dir)
+    }
+
     #[allow(dead_code)]
     pub fn create(path: &dyn AsRef<Path>) -> Result<Self> {
-        let rng = rand::thread_rng();
-        Ok(Self {
-            file: File::create(&path)?,
-            rng,
-        })
+        IncompleteOptions::from_env()?.create(path)
     }
 
     #[allow(dead_code)]
     pub fn open(path: &dyn AsRef<Path>) -> Result<Self> {
-        let rng = rand::thread_rng();
-        Ok(Self {
-            file: File::open(&path)?,
-            rng,
-        })
+        IncompleteOptions::from_env()?.open(path)
+    }
+
+    /**
+     * Like create, but truncation sizes are drawn from an RNG seeded with
+     * `seed` so that a failing run can be replayed exactly.
+     */
+    #[allow(dead_code)]
+    pub fn create_with_seed(path: &dyn AsRef<Path>, seed: u64) -> Result<Self> {
+        IncompleteOptions::from_env()?.seed(seed).create(path)
+    }
+
+    /**
+     * Like open, but truncation sizes are drawn from an RNG seeded with
+     * `seed` so that a failing run can be replayed exactly.
+     */
+    #[allow(dead_code)]
+    pub fn open_with_seed(path: &dyn AsRef<Path>, seed: u64) -> Result<Self> {
+        IncompleteOptions::from_env()?.seed(seed).open(path)
+    }
+}
+
+impl IncompleteFile {
+    /**
+     * Duplicate the file handle, see File::try_clone. The duplicate shares
+     * the file's cursor, and has its own RNG seeded deterministically from
+     * this file's seed, see Injector::fork.
+     */
+    #[allow(dead_code)]
+    pub fn try_clone(&self) -> Result<Self> {
+        let file = self.file.try_clone()?;
+        Ok(Self::with_policy(file, self.policy().fork()))
+    }
+}
+
+impl<R: Rng> IncompleteFile<Injector<R>> {
+    /**
+     * Wrap an already open file, drawing truncation sizes from `rng`. This
+     * gives complete control over size selection, for example with a seeded
+     * StdRng or a test specific fake RNG.
+     */
+    #[allow(dead_code)]
+    pub fn with_rng(file: File, rng: R) -> Self {
+        Self::with_policy(file, Injector::with_rng(rng))
+    }
+
+    /**
+     * The seed this file's RNG was created with. Files made with create or
+     * open pick a random seed: pass it to create_with_seed or open_with_seed
+     * to reproduce the same sequence of truncation sizes. Files made with
+     * with_rng have no known seed.
+     */
+    #[allow(dead_code)]
+    pub fn seed(&self) -> Option<u64> {
+        self.policy().seed()
+    }
+
+    /**
+     * Counts of this file's operations so far, see Injector::stats.
+     */
+    #[allow(dead_code)]
+    pub fn stats(&self) -> IoStats {
+        self.policy().stats()
+    }
+
+    /**
+     * Every operation on this file so far, see Injector::events.
+     */
+    #[allow(dead_code)]
+    pub fn events(&self) -> Vec<IoEvent> {
+        self.policy().events().to_vec()
+    }
+}
+
+impl<F: FnMut(IoOp, usize) -> usize> IncompleteFile<F> {
+    /**
+     * Wrap an already open file, calling `size_fn` with the kind and size of
+     * every operation to pick the size it is limited to. Sizes of 0 are
+     * treated as 1, and sizes larger than the operation are passed through.
+     */
+    #[allow(dead_code)]
+    pub fn with_size_fn(file: File, size_fn: F) -> Self {
+        Self::with_policy(file, size_fn)
+    }
+}
+
+impl<P: PartialPolicy> IncompleteFile<P> {
+    /**
+     * Wrap an already open file, letting `policy` decide how every operation
+     * is truncated.
+     */
+    #[allow(dead_code)]
+    pub fn with_policy(file: File, policy: P) -> Self {
+        Self {
+            file,
+            policy: Mutex::new(policy),
+            persistence: Mutex::new(None),
+            alignment: None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn policy(&self) -> MutexGuard<'_, P> {
+        self.policy.lock().unwrap()
+    }
+
+    #[allow(dead_code)]
+    pub fn policy_mut(&mut self) -> &mut P {
+        self.policy.get_mut().unwrap()
     }
 
     #[allow(dead_code)]
     pub fn metadata(&self) -> Result<Metadata> {
         self.file.metadata()
     }
+
+    /**
+     * Truncate or extend the file, see File::set_len, unless the policy
+     * fails the operation.
+     */
+    #[allow(dead_code)]
+    pub fn set_len(&mut self, size: u64) -> Result<()> {
+        self.prepare_change(Some(size), 0)?;
+        permit(self.policy.get_mut().unwrap(), IoOp::SetLen)?;
+        self.file.set_len(size)?;
+        self.record_set_len(size);
+        Ok(())
+    }
+
+    /**
+     * Flush data and metadata to disk, see File::sync_all, unless the
+     * policy fails the operation. With
+     * IncompleteOptions::lose_unsynced_writes, a failure also discards
+     * everything written since the last successful sync.
+     */
+    #[allow(dead_code)]
+    pub fn sync_all(&mut self) -> Result<()> {
+        self.sync_with(IoOp::SyncAll, File::sync_all)
+    }
+
+    /**
+     * Flush data to disk, see File::sync_data and sync_all.
+     */
+    #[allow(dead_code)]
+    pub fn sync_data(&mut self) -> Result<()> {
+        self.sync_with(IoOp::SyncData, File::sync_data)
+    }
+
+    /**
+     * Simulate losing power: replace the file's contents with what could
+     * plausibly have reached the disk, which is everything synced plus
+     * some prefix of the writes since, possibly ending part way through
+     * one. Every operation on this handle fails afterwards, so reopen the
+     * file to check that recovery copes with what was left. Which writes
+     * survive is drawn from the file's seed. The contents are rewritten in
+     * place through this handle, not atomically, so an error part way
+     * through leaves the file empty or partly written. The file must have
+     * been made with IncompleteOptions::simulate_crashes, or this returns
+     * an Unsupported error.
+     */
+    #[allow(dead_code)]
+    pub fn crash(&mut self) -> Result<()> {
+        self.crash_with_model()
+    }
+
+    #[allow(dead_code)]
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /**
+     * The wrapped file, for operations that should not be truncated.
+     * Reading, writing, or seeking through it moves the cursor without the
+     * policy finding out.
+     */
+    #[allow(dead_code)]
+    pub fn get_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /**
+     * Consume the wrapper, returning the wrapped file.
+     */
+    #[allow(dead_code)]
+    pub fn into_inner(self) -> File {
+        self.file
+    }
 }
 
-impl Read for IncompleteFile {
+impl From<File> for IncompleteFile {
+    // See IncompleteFile::with_file
+    fn from(file: File) -> Self {
+        Self::with_file(file)
+    }
+}
+
+impl<P: PartialPolicy> Read for IncompleteFile<P> {
     /**
      * Rust's std::io::Read trait documentation says:
      *
@@ -59,16 +464,27 @@ impl Read for IncompleteFile {
      * buf.len() (because 0 would be EOF).
      */
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if buf.len() == 1 {
-            return self.file.read(buf);
-        }
+        self.check_crashed()?;
+        self.check_aligned([&*buf], None)?;
+        read_through(self.policy.get_mut().unwrap(), buf, |buf| {
+            self.file.read(buf)
+        })
+    }
 
-        let truncated_size = self.rng.gen_range(1..buf.len());
-        self.file.read(&mut buf[0..truncated_size])
+    /**
+     * Vectored reads have their own short read bugs: only fill part of the
+     * first non-empty buffer, leaving the rest untouched.
+     */
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        self.check_crashed()?;
+        self.check_aligned(bufs.iter().map(|buf| &**buf), None)?;
+        read_vectored_through(self.policy.get_mut().unwrap(), bufs, |buf| {
+            self.file.read(buf)
+        })
     }
 }
 
-impl Write for IncompleteFile {
+impl<P: PartialPolicy> Write for IncompleteFile<P> {
     /**
      * Rust's std::io::Write trait documentation says:
      *
@@ -79,24 +495,140 @@ impl Write for IncompleteFile {
      * buf.len().
      */
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        if buf.len() == 1 {
-            return self.file.write(buf);
-        }
+        self.prepare_change(None, buf.len())?;
+        self.check_aligned([buf], None)?;
+        let n = write_through(self.policy.get_mut().unwrap(), buf, |buf| {
+            self.file.write(buf)
+        })?;
+        self.record_write(None, &buf[0..n])?;
+        Ok(n)
+    }
 
-        let truncated_size = self.rng.gen_range(1..buf.len());
-        self.file.write(&buf[0..truncated_size])
+    /**
+     * Only accept a prefix of the supplied buffers, which may end part way
+     * through one of them, so that callers have to advance their IoSlices
+     * correctly after a short vectored write.
+     */
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        self.prepare_change(None, bufs.iter().map(|buf| buf.len()).sum())?;
+        self.check_aligned(bufs.iter().map(|buf| &**buf), None)?;
+        let n = write_vectored_through(self.policy.get_mut().unwrap(), bufs, |bufs| {
+            self.file.write_vectored(bufs)
+        })?;
+        let written: Vec<u8> = bufs
+            .iter()
+            .flat_map(|buf| buf.iter())
+            .take(n)
+            .copied()
+            .collect();
+        self.record_write(None, &written)?;
+        Ok(n)
     }
 
-    // Pass-through flush
+    // Pass-through flush, unless the policy fails it
     fn flush(&mut self) -> Result<()> {
-        self.file.flush()
+        self.check_crashed()?;
+        flush_through(self.policy.get_mut().unwrap(), || self.file.flush())
     }
 }
 
-impl Seek for IncompleteFile {
-    // Pass-through seek
+impl<P: PartialPolicy> Seek for IncompleteFile<P> {
+    // Pass-through seek unless the policy fails it, reporting the new
+    // position to the policy
     fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
-        self.file.seek(pos)
+        self.check_crashed()?;
+        permit(self.policy.get_mut().unwrap(), IoOp::Seek)?;
+        let position = self.file.seek(pos)?;
+        self.policy.get_mut().unwrap().seeked(position);
+        Ok(position)
+    }
+}
+
+// Pass-through descriptors, so the file can be registered with poll or
+// inspected with fcntl without being unwrapped
+#[cfg(unix)]
+impl<P: PartialPolicy> std::os::fd::AsRawFd for IncompleteFile<P> {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+#[cfg(unix)]
+impl<P: PartialPolicy> std::os::fd::AsFd for IncompleteFile<P> {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+// Pass-through handles, see the Unix descriptors above
+#[cfg(windows)]
+impl<P: PartialPolicy> std::os::windows::io::AsRawHandle for IncompleteFile<P> {
+    fn as_raw_handle(&self) -> std::os::windows::io::RawHandle {
+        self.file.as_raw_handle()
+    }
+}
+
+#[cfg(windows)]
+impl<P: PartialPolicy> std::os::windows::io::AsHandle for IncompleteFile<P> {
+    fn as_handle(&self) -> std::os::windows::io::BorrowedHandle<'_> {
+        self.file.as_handle()
+    }
+}
+
+#[cfg(unix)]
+impl<P: PartialPolicy> std::os::unix::fs::FileExt for IncompleteFile<P> {
+    // Truncate positional reads the same way as Read::read
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.check_crashed()?;
+        self.check_aligned([&*buf], Some(offset))?;
+        let mut policy = self.policy();
+        positional_through(&mut *policy, IoOp::Read, buf.len(), |size| {
+            self.file.read_at(&mut buf[0..size], offset)
+        })
+    }
+
+    // Truncate positional writes the same way as Write::write
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        self.prepare_change(Some(offset), buf.len())?;
+        self.check_aligned([buf], Some(offset))?;
+        let mut policy = self.policy();
+        let n = positional_through(&mut *policy, IoOp::Write, buf.len(), |size| {
+            self.file.write_at(&buf[0..size], offset)
+        })?;
+        self.record_write(Some(offset), &buf[0..n])?;
+        Ok(n)
+    }
+}
+
+#[cfg(windows)]
+impl<P: PartialPolicy> std::os::windows::fs::FileExt for IncompleteFile<P> {
+    /**
+     * Truncate positional reads the same way as Read::read. Unlike on Unix,
+     * these move the file's cursor to the end of what was read, so the
+     * policy is told about the new position.
+     */
+    fn seek_read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.check_crashed()?;
+        self.check_aligned([&*buf], Some(offset))?;
+        let mut policy = self.policy();
+        let n = positional_through(&mut *policy, IoOp::Read, buf.len(), |size| {
+            self.file.seek_read(&mut buf[0..size], offset)
+        })?;
+        policy.seeked(offset + n as u64);
+        Ok(n)
+    }
+
+    // Truncate positional writes the same way as Write::write, see seek_read
+    fn seek_write(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        self.prepare_change(Some(offset), buf.len())?;
+        self.check_aligned([buf], Some(offset))?;
+        let mut policy = self.policy();
+        let n = positional_through(&mut *policy, IoOp::Write, buf.len(), |size| {
+            self.file.seek_write(&buf[0..size], offset)
+        })?;
+        policy.seeked(offset + n as u64);
+        self.record_write(Some(offset), &buf[0..n])?;
+        Ok(n)
     }
 }
 
@@ -104,6 +636,7 @@ impl Seek for IncompleteFile {
 mod tests {
     use super::*;
     use rand::RngCore;
+    use std::io::ErrorKind;
 
     fn read_test_i(sz: usize) -> Result<()> {
         // write out random data
@@ -190,4 +723,312 @@ mod tests {
 
         Ok(())
     }
+
+    // Record the size of every read made against a file of sz bytes
+    fn read_sizes_i<P: PartialPolicy>(
+        file: &mut IncompleteFile<P>,
+        sz: usize,
+    ) -> Result<Vec<usize>> {
+        let mut sizes = vec![];
+        let mut buffer = vec![0; sz];
+        let mut total_read = 0;
+
+        while total_read < sz {
+            let n = file.read(&mut buffer[total_read..])?;
+            assert!(n != 0);
+            sizes.push(n);
+            total_read += n;
+        }
+
+        Ok(sizes)
+    }
+
+    #[test]
+    fn seed_test() -> Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(&vec![0; 320 * 64])?;
+
+        let mut reader = IncompleteFile::open(&file.path())?;
+        let sizes = read_sizes_i(&mut reader, 320 * 64)?;
+
+        // replaying the seed must give the exact same sequence of sizes
+        let seed = reader.seed().unwrap();
+        let mut replay = IncompleteFile::open_with_seed(&file.path(), seed)?;
+        assert_eq!(replay.seed(), reader.seed());
+        assert_eq!(sizes, read_sizes_i(&mut replay, 320 * 64)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn with_rng_test() -> Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(&vec![0; 320 * 64])?;
+
+        // a fake RNG that always returns zero always picks the smallest size
+        let rng = rand::rngs::mock::StepRng::new(0, 0);
+        let mut reader = IncompleteFile::with_rng(File::open(file.path())?, rng);
+        assert_eq!(reader.seed(), None);
+
+        let sizes = read_sizes_i(&mut reader, 320 * 64)?;
+        assert!(sizes.iter().all(|n| *n == 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn size_fn_test() -> Result<()> {
+        let file = tempfile::tempfile()?;
+        let mut file = IncompleteFile::with_size_fn(file, |op, requested| match op {
+            IoOp::Read => requested - 1,
+            IoOp::Write => 3,
+            _ => 0,
+        });
+
+        assert_eq!(file.write(&[1; 320])?, 3);
+        assert_eq!(file.write(&[1; 2])?, 2);
+
+        file.seek(SeekFrom::Start(0))?;
+        let mut buffer = [0; 5];
+        assert_eq!(file.read(&mut buffer)?, 4);
+        assert_eq!(buffer, [1, 1, 1, 1, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fail_at_offset_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("random");
+
+        let mut writer = IncompleteOptions::default()
+            .fail_at_offset(1000, ErrorKind::Other)
+            .create(&path)?;
+
+        // the write stops exactly at the offset and then fails
+        let e = writer.write_all(&[0; 2000]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert_eq!(writer.metadata()?.len(), 1000);
+
+        // the fault was transient
+        writer.write_all(&[0; 1000])?;
+        assert_eq!(writer.metadata()?.len(), 2000);
+
+        // a trigger fires on the next operation after a seek passes it
+        let mut reader = IncompleteOptions::default()
+            .fail_at_offset(500, ErrorKind::UnexpectedEof)
+            .open(&path)?;
+        reader.seek(SeekFrom::Start(1500))?;
+        let e = reader.read(&mut [0; 10]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_ext_test() -> Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("random");
+        let file = IncompleteFile::create(&path)?;
+
+        // a short write must happen at least once for a buffer this size
+        let n = file.write_at(&random_data, 320)?;
+        assert!(n < random_data.len());
+        file.write_all_at(&random_data[n..], 320 + n as u64)?;
+
+        let file = IncompleteOptions::default().open(&path)?;
+        let mut buffer = vec![0; random_data.len()];
+        let n = file.read_at(&mut buffer, 320)?;
+        assert!(n < random_data.len());
+        file.read_exact_at(&mut buffer[n..], 320 + n as u64)?;
+        assert_eq!(buffer, random_data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn with_file_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("random");
+        std::fs::write(&path, b"hello ")?;
+
+        // the file keeps the append flag it was opened with
+        let file = std::fs::OpenOptions::new().append(true).open(&path)?;
+        let mut file = IncompleteFile::from(file);
+        file.write_all(b"world")?;
+        assert_eq!(std::fs::read(&path)?, b"hello world");
+
+        let mut file = IncompleteFile::with_file(tempfile::tempfile()?);
+        file.write_all(&[0; 320])?;
+        assert_eq!(file.metadata()?.len(), 320);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "tempfile")]
+    #[test]
+    fn tempfile_test() -> Result<()> {
+        let mut file = IncompleteFile::tempfile()?;
+        file.write_all(&[0; 320])?;
+        assert_eq!(file.metadata()?.len(), 320);
+
+        let dir = tempfile::tempdir()?;
+        let (mut file, path) = IncompleteFile::named_temp_in(&dir)?;
+        file.write_all(b"hello")?;
+        assert_eq!(std::fs::read(&path)?, b"hello");
+
+        // dropping the path deletes the file
+        let kept = path.to_path_buf();
+        drop(path);
+        assert!(!kept.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sync_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("wal");
+
+        let mut file = IncompleteOptions::default()
+            .fail_on(IoOp::SyncData, 2, ErrorKind::Other)
+            .fail_on(IoOp::SetLen, 1, ErrorKind::PermissionDenied)
+            .create(&path)?;
+        file.write_all(&[1; 320])?;
+        file.sync_data()?;
+        assert_eq!(file.sync_data().unwrap_err().kind(), ErrorKind::Other);
+        file.sync_all()?;
+
+        // the failed set_len did not change the file, the second did
+        let e = file.set_len(0).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(file.metadata()?.len(), 320);
+        file.set_len(160)?;
+        assert_eq!(file.metadata()?.len(), 160);
+
+        Ok(())
+    }
+
+    #[test]
+    fn into_inner_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("random");
+
+        let mut file = IncompleteFile::create(&path)?;
+        file.write_all(&[1; 320])?;
+        assert_eq!(file.get_ref().metadata()?.len(), 320);
+
+        // writes through get_mut are never truncated
+        assert_eq!(file.get_mut().write(&[2; 320])?, 320);
+
+        let file = file.into_inner();
+        file.sync_all()?;
+        assert_eq!(file.metadata()?.len(), 640);
+
+        Ok(())
+    }
+
+    #[test]
+    fn try_clone_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("random");
+
+        // clones of files with the same seed get the same derived seeds
+        let seeds = |seed| -> Result<Vec<Option<u64>>> {
+            let file = IncompleteFile::create_with_seed(&path, seed)?;
+            let first = file.try_clone()?;
+            let second = file.try_clone()?;
+            Ok(vec![file.seed(), first.seed(), second.seed()])
+        };
+        let expected = seeds(1234)?;
+        assert_eq!(seeds(1234)?, expected);
+        assert_ne!(expected[0], expected[1]);
+        assert_ne!(expected[1], expected[2]);
+
+        // both handles write to the same file
+        let mut file = IncompleteFile::create(&path)?;
+        let mut clone = file.try_clone()?;
+        file.write_all(b"hello ")?;
+        clone.write_all(b"world")?;
+        assert_eq!(std::fs::read(&path)?, b"hello world");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn as_fd_test() -> Result<()> {
+        use std::os::fd::{AsFd, AsRawFd};
+
+        let file = tempfile::tempfile()?;
+        let raw_fd = file.as_raw_fd();
+        let wrapped = IncompleteFile::with_policy(file, |_, requested| requested);
+        assert_eq!(wrapped.as_raw_fd(), raw_fd);
+
+        // the descriptor can be duplicated like the file's own
+        let duplicate = File::from(wrapped.as_fd().try_clone_to_owned()?);
+        assert_eq!(duplicate.metadata()?.len(), wrapped.metadata()?.len());
+
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn file_ext_test() -> Result<()> {
+        use std::os::windows::fs::FileExt;
+
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("random");
+        let file = IncompleteFile::create(&path)?;
+
+        // a short write must happen at least once for a buffer this size
+        let mut offset = 0;
+        let n = file.seek_write(&random_data, 320)?;
+        assert!(n < random_data.len());
+        offset += n;
+        while offset < random_data.len() {
+            offset += file.seek_write(&random_data[offset..], 320 + offset as u64)?;
+        }
+
+        let file = IncompleteOptions::default().open(&path)?;
+        let mut buffer = vec![0; random_data.len()];
+        let mut offset = 0;
+        while offset < buffer.len() {
+            offset += file.seek_read(&mut buffer[offset..], 320 + offset as u64)?;
+        }
+        assert_eq!(buffer, random_data);
+
+        Ok(())
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn send_sync_test() -> Result<()> {
+        assert_send_sync::<IncompleteFile>();
+        assert_send_sync::<IncompleteReader<std::io::Empty>>();
+        assert_send_sync::<IncompleteWriter<std::io::Sink>>();
+
+        // an IncompleteFile can be moved into a spawned thread
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("random");
+        let mut writer = IncompleteFile::create(&path)?;
+
+        std::thread::spawn(move || writer.write_all(&[0; 320]))
+            .join()
+            .unwrap()?;
+
+        assert_eq!(std::fs::read(&path)?, vec![0; 320]);
+
+        Ok(())
+    }
 }
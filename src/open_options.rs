@@ -0,0 +1,185 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::fs::OpenOptions;
+use std::io::Result;
+use std::path::Path;
+
+use crate::{IncompleteFile, IncompleteOptions};
+
+/**
+ * Opens an IncompleteFile in any mode std::fs::OpenOptions supports, such as
+ * read+write or append, instead of only create-for-write or open-for-read.
+ * Each setter mirrors the std::fs::OpenOptions one of the same name.
+ *
+ * ```
+ * # use incomplete_file::IncompleteOpenOptions;
+ * # fn main() -> std::io::Result<()> {
+ * # let dir = tempfile::tempdir()?;
+ * # let path = dir.path().join("file");
+ * let file = IncompleteOpenOptions::new()
+ *     .read(true)
+ *     .write(true)
+ *     .create(true)
+ *     .open(&path)?;
+ * # Ok(())
+ * # }
+ * ```
+ */
+#[derive(Clone, Debug)]
+pub struct IncompleteOpenOptions {
+    open_options: OpenOptions,
+    options: IncompleteOptions,
+    direct: Option<usize>,
+}
+
+impl IncompleteOpenOptions {
+    pub fn new() -> Self {
+        Self {
+            open_options: OpenOptions::new(),
+            options: IncompleteOptions::new(),
+            direct: None,
+        }
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.open_options.read(read);
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.open_options.write(write);
+        self
+    }
+
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.open_options.append(append);
+        self
+    }
+
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.open_options.truncate(truncate);
+        self
+    }
+
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.open_options.create(create);
+        self
+    }
+
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.open_options.create_new(create_new);
+        self
+    }
+
+    /**
+     * Open the file with O_DIRECT, bypassing the page cache, and hold every
+     * read and write on it to `alignment`, see IncompleteOptions::direct_io.
+     * Needs the "direct" feature, and only exists on Linux. Some file
+     * systems, like tmpfs, refuse to open files with O_DIRECT.
+     */
+    #[cfg(all(feature = "direct", target_os = "linux"))]
+    pub fn direct(&mut self, alignment: usize) -> &mut Self {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        self.open_options.custom_flags(libc::O_DIRECT);
+        self.direct = Some(alignment);
+        self
+    }
+
+    /**
+     * Inject incomplete operations as configured by `options` instead of
+     * the defaults.
+     */
+    pub fn options(&mut self, options: &IncompleteOptions) -> &mut Self {
+        self.options = options.clone();
+        self
+    }
+
+    /**
+     * Open the file at `path` with the options set so far.
+     */
+    pub fn open(&self, path: &dyn AsRef<Path>) -> Result<IncompleteFile> {
+        let mut options = self.options.clone();
+        if let Some(alignment) = self.direct {
+            options.direct_io(alignment);
+        }
+        Ok(options.wrap_file(self.open_options.open(path)?))
+    }
+}
+
+impl Default for IncompleteOpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+    #[test]
+    fn read_modify_write_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("counter");
+        std::fs::write(&path, b"41")?;
+
+        let mut file = IncompleteOpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let counter: u32 = contents.parse().unwrap();
+
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all((counter + 1).to_string().as_bytes())?;
+        assert_eq!(std::fs::read(&path)?, b"42");
+
+        // create_new refuses files that already exist
+        let result = IncompleteOpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path);
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(ErrorKind::AlreadyExists)
+        );
+
+        Ok(())
+    }
+
+    #[cfg(all(feature = "direct", target_os = "linux"))]
+    #[test]
+    fn direct_test() -> Result<()> {
+        // in the build directory, which is less likely than /tmp to be tmpfs
+        let dir = tempfile::tempdir_in(env!("CARGO_MANIFEST_DIR"))?;
+        let mut file = match IncompleteOpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .direct(4096)
+            .open(&dir.path().join("direct"))
+        {
+            Err(e) if e.kind() == ErrorKind::InvalidInput => return Ok(()),
+            result => result?,
+        };
+
+        let mut storage = vec![7; 3 * 4096];
+        let start = storage.as_ptr().align_offset(4096);
+        let buffer = &mut storage[start..start + 8192];
+        file.write_all(buffer)?;
+        assert!(file.write(&buffer[..100]).is_err());
+
+        file.seek(SeekFrom::Start(0))?;
+        buffer.fill(0);
+        file.read_exact(buffer)?;
+        assert_eq!(buffer, [7; 8192]);
+
+        Ok(())
+    }
+}
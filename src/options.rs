@@ -0,0 +1,1799 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use rand::Rng;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, PipeReader, PipeWriter, Read, Result, Write};
+use std::ops::{Range, RangeInclusive};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
+
+use crate::explore::{self, Explorer};
+use crate::persistence::{self, Persistence};
+use crate::regressions;
+use crate::shrink;
+use crate::soak;
+#[cfg(any(feature = "tokio", feature = "futures"))]
+use crate::IncompleteAsync;
+#[cfg(feature = "tokio")]
+use crate::IncompleteAsyncFile;
+#[cfg(feature = "smol")]
+use crate::IncompleteSmolFile;
+use crate::{
+    Decision, DuplexEnd, FailureMode, GeneratedReader, IncompleteChild, IncompleteCursor,
+    IncompleteDuplex, IncompleteFile, IncompleteReader, IncompleteStderr, IncompleteStdin,
+    IncompleteStdout, IncompleteStream, IncompleteWriter, Injector, IoOp, Policy, Profile,
+    SeedGuard, SoakReport, UnflushedDrop,
+};
+
+pub(crate) fn check_probability(probability: f64) -> f64 {
+    assert!(
+        (0.0..=1.0).contains(&probability),
+        "probability {} is not between 0.0 and 1.0",
+        probability,
+    );
+    probability
+}
+
+/**
+ * Configures how the wrappers in this crate inject incomplete operations.
+ * Like std::fs::OpenOptions, set options on a value and then use it to
+ * open, create, or wrap as many times as needed:
+ *
+ * ```
+ * # use incomplete_file::IncompleteOptions;
+ * # fn main() -> std::io::Result<()> {
+ * let reader = IncompleteOptions::new()
+ *     .seed(1234)
+ *     .wrap_reader(std::io::empty());
+ * # Ok(())
+ * # }
+ * ```
+ *
+ * warm_up, calm_after, calm_after_bytes, window, and bursts make calm
+ * stretches, where reads and writes are passed through instead of being
+ * truncated or failed at random. Scheduled and triggered errors, the
+ * storage quota, and throttling still apply in them.
+ */
+#[derive(Clone, Debug)]
+pub struct IncompleteOptions {
+    pub(crate) seed: Option<u64>,
+    pub(crate) min_size: usize,
+    pub(crate) max_size: usize,
+    pub(crate) buffer_size: usize,
+    pub(crate) block_size: Option<usize>,
+    pub(crate) direct_io: Option<usize>,
+    pub(crate) full_op_probability: f64,
+    pub(crate) interrupted_probability: f64,
+    pub(crate) write_zero_probability: f64,
+    pub(crate) spurious_eof_probability: f64,
+    pub(crate) would_block_probability: f64,
+    pub(crate) would_block_limit: Option<usize>,
+    pub(crate) flush_errors: Option<(f64, ErrorKind)>,
+    pub(crate) schedule: Vec<(IoOp, usize, ErrorKind)>,
+    pub(crate) failure_mode: FailureMode,
+    pub(crate) on_unflushed_drop: UnflushedDrop,
+    pub(crate) offset_triggers: Vec<(u64, ErrorKind)>,
+    pub(crate) op_triggers: Vec<(usize, ErrorKind)>,
+    pub(crate) pattern_triggers: Vec<(IoOp, Vec<u8>, ErrorKind)>,
+    pub(crate) storage_quota: Option<u64>,
+    pub(crate) read_only_after: Option<usize>,
+    pub(crate) latency: Option<RangeInclusive<Duration>>,
+    pub(crate) throttle: Option<u64>,
+    pub(crate) warm_up: usize,
+    pub(crate) calm_after: Option<usize>,
+    pub(crate) calm_after_bytes: Option<u64>,
+    pub(crate) window: Option<Range<u64>>,
+    pub(crate) bursts: Option<(usize, usize)>,
+    pub(crate) trace: bool,
+    #[cfg(feature = "log")]
+    pub(crate) log_level: log::Level,
+    pub(crate) policy: Policy,
+    pub(crate) op_options: Vec<(IoOp, IncompleteOptions)>,
+    pub(crate) plan: Option<Vec<usize>>,
+    pub(crate) script: Option<Vec<Decision>>,
+    // where every injector built from these options records its decisions
+    pub(crate) recorder: Option<Arc<Mutex<Vec<Decision>>>>,
+    // what every injector built from these options asks to decide instead
+    pub(crate) explorer: Option<Arc<Mutex<Explorer>>>,
+    pub(crate) pending_count: usize,
+    pub(crate) lose_unsynced_writes: bool,
+    pub(crate) simulate_crashes: bool,
+    pub(crate) reorder_unsynced_writes: bool,
+    pub(crate) sector_size: Option<u64>,
+    pub(crate) regression_file: PathBuf,
+}
+
+impl Default for IncompleteOptions {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            min_size: 1,
+            max_size: usize::MAX,
+            buffer_size: usize::MAX,
+            block_size: None,
+            direct_io: None,
+            full_op_probability: 0.0,
+            interrupted_probability: 0.0,
+            write_zero_probability: 0.0,
+            spurious_eof_probability: 0.0,
+            would_block_probability: 0.0,
+            would_block_limit: None,
+            flush_errors: None,
+            schedule: vec![],
+            failure_mode: FailureMode::Transient,
+            on_unflushed_drop: UnflushedDrop::Ignore,
+            offset_triggers: vec![],
+            op_triggers: vec![],
+            pattern_triggers: vec![],
+            storage_quota: None,
+            read_only_after: None,
+            latency: None,
+            throttle: None,
+            warm_up: 0,
+            calm_after: None,
+            calm_after_bytes: None,
+            window: None,
+            bursts: None,
+            trace: false,
+            #[cfg(feature = "log")]
+            log_level: log::Level::Trace,
+            policy: Policy::Uniform,
+            op_options: vec![],
+            plan: None,
+            script: None,
+            recorder: None,
+            explorer: None,
+            pending_count: 0,
+            lose_unsynced_writes: false,
+            simulate_crashes: false,
+            reorder_unsynced_writes: false,
+            sector_size: None,
+            regression_file: PathBuf::from("incomplete-regressions.txt"),
+        }
+    }
+}
+
+impl IncompleteOptions {
+    /**
+     * The default options, with any overrides from the environment applied,
+     * see apply_env. A variable that cannot be parsed is reported on stderr
+     * the first time and the defaults are used instead; from_env returns
+     * the error. Use default to leave the environment alone.
+     */
+    pub fn new() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    // See new, looking variables up with `var`
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        static REPORTED: Once = Once::new();
+
+        let mut options = Self::default();
+        if let Err(e) = options.apply_vars(var) {
+            REPORTED.call_once(|| eprintln!("incomplete_file: ignoring overrides: {}", e));
+            options = Self::default();
+        }
+        options
+    }
+
+    /**
+     * The default options with any overrides from the environment applied,
+     * as new, but returning InvalidInput for a variable that cannot be
+     * parsed.
+     */
+    pub fn from_env() -> Result<Self> {
+        let mut options = Self::default();
+        options.apply_env()?;
+        Ok(options)
+    }
+
+    /**
+     * Override options from environment variables, so that CI can replay a
+     * failing seed or make every test more aggressive without code changes:
+     *
+     * - INCOMPLETE_SEED, see seed
+     * - INCOMPLETE_PROFILE, one of mild, aggressive, or pathological, see
+     *   profile. The variables below override parts of it.
+     * - INCOMPLETE_POLICY, one of uniform, geometric, heavy_tailed,
+     *   bimodal, boundary, one_byte, or records:SIZE with a SIZE of at
+     *   least 1, see policy
+     * - INCOMPLETE_MIN_SIZE and INCOMPLETE_MAX_SIZE, see min_size and
+     *   max_size
+     * - INCOMPLETE_FULL_OP_PROBABILITY and
+     *   INCOMPLETE_INTERRUPTED_PROBABILITY, see full_op_probability and
+     *   interrupted_probability
+     *
+     * new and from_env apply these first, so options set in code take
+     * precedence. Returns InvalidInput if a variable is set to something
+     * that cannot be parsed, leaving the options partly applied.
+     */
+    pub fn apply_env(&mut self) -> Result<&mut Self> {
+        self.apply_vars(|name| std::env::var(name).ok())
+    }
+
+    // See apply_env, looking variables up with `var`
+    fn apply_vars(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<&mut Self> {
+        fn invalid(name: &str, value: &str, what: &str) -> Error {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is set to {:?}, which is not {}", name, value, what),
+            )
+        }
+        fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T> {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| invalid(name, value, "valid"))
+        }
+
+        if let Some(value) = var("INCOMPLETE_SEED") {
+            self.seed(parse("INCOMPLETE_SEED", &value)?);
+        }
+        if let Some(value) = var("INCOMPLETE_PROFILE") {
+            let profile = match value.trim() {
+                "mild" => Profile::Mild,
+                "aggressive" => Profile::Aggressive,
+                "pathological" => Profile::Pathological,
+                _ => return Err(invalid("INCOMPLETE_PROFILE", &value, "a profile")),
+            };
+            self.profile(profile);
+        }
+        if let Some(value) = var("INCOMPLETE_POLICY") {
+            let policy = match value.trim() {
+                "uniform" => Policy::Uniform,
+                "geometric" => Policy::Geometric,
+                "heavy_tailed" => Policy::HeavyTailed,
+                "bimodal" => Policy::Bimodal,
+                "boundary" => Policy::Boundary,
+                "one_byte" => Policy::OneByte,
+                other => match other.strip_prefix("records:").map(str::parse) {
+                    Some(Ok(size)) if size > 0 => Policy::Records(size),
+                    _ => return Err(invalid("INCOMPLETE_POLICY", &value, "a policy")),
+                },
+            };
+            self.policy(policy);
+        }
+        if let Some(value) = var("INCOMPLETE_MIN_SIZE") {
+            self.min_size(parse("INCOMPLETE_MIN_SIZE", &value)?);
+        }
+        if let Some(value) = var("INCOMPLETE_MAX_SIZE") {
+            self.max_size(parse("INCOMPLETE_MAX_SIZE", &value)?);
+        }
+        if let Some(value) = var("INCOMPLETE_FULL_OP_PROBABILITY") {
+            self.full_op_probability(parse("INCOMPLETE_FULL_OP_PROBABILITY", &value)?);
+        }
+        if let Some(value) = var("INCOMPLETE_INTERRUPTED_PROBABILITY") {
+            self.interrupted_probability(parse("INCOMPLETE_INTERRUPTED_PROBABILITY", &value)?);
+        }
+        Ok(self)
+    }
+
+    /**
+     * Draw truncation sizes from an RNG seeded with `seed` so that a failing
+     * run can be replayed exactly. Without this a random seed is picked for
+     * each wrapper.
+     */
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /**
+     * Pick a random seed for these options, unless one was already set,
+     * and return a guard that prints it (and the rest of the options) if
+     * the current test panics while the guard is alive. Every wrapper
+     * made from these options afterwards uses that seed.
+     */
+    pub fn seed_guard(&mut self) -> SeedGuard {
+        let seed = *self.seed.get_or_insert_with(|| rand::thread_rng().gen());
+        SeedGuard::new(seed, self)
+    }
+
+    /**
+     * Where check_seeds records the seeds that failed, and replays them
+     * from. Relative paths are relative to the current directory, which
+     * for cargo test is the package root. Defaults to
+     * `incomplete-regressions.txt`.
+     */
+    pub fn regression_file(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.regression_file = path.as_ref().to_path_buf();
+        self
+    }
+
+    /**
+     * Run `test` with these options once for every seed in the regression
+     * file, and then with `runs` new seeds, starting with the one set on
+     * these options if any. The first new seed that fails the test, by
+     * returning an error or panicking, is appended to the regression file
+     * so that every later run replays it first, turning a lucky catch into
+     * a permanent regression test. A seed whose test returns an error is
+     * also shrunk, see shrink, and the script it shrinks to is reported
+     * alongside it. Returns the number of seeds run, or the first error,
+     * labelled with its seed.
+     */
+    pub fn check_seeds(
+        &self,
+        runs: usize,
+        test: impl FnMut(&IncompleteOptions) -> Result<()>,
+    ) -> Result<usize> {
+        regressions::check_seeds(self, runs, test)
+    }
+
+    /**
+     * Never truncate an operation to fewer than `min_size` bytes. Operations
+     * on buffers smaller than this are passed through untouched. Defaults to
+     * 1, and 0 is treated as 1 because a zero byte read signals EOF.
+     */
+    pub fn min_size(&mut self, min_size: usize) -> &mut Self {
+        self.min_size = min_size.max(1);
+        self
+    }
+
+    /**
+     * Never truncate an operation to more than `max_size` bytes, which
+     * models transports like pipes or sockets that move data in bounded
+     * chunks. Defaults to no limit. This only bounds the sizes Injector
+     * picks, see buffer_size to cap every operation.
+     */
+    pub fn max_size(&mut self, max_size: usize) -> &mut Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /**
+     * Model a block device: truncate reads and writes only to multiples of
+     * `size` bytes, for example 512 or 4096, so that everything but the
+     * final remainder of a transfer moves whole blocks, for testing code
+     * that assumes sector-aligned transfers. Triggers, the storage quota,
+     * and throttling still cut operations short anywhere. Defaults to
+     * truncating at any byte.
+     */
+    pub fn block_size(&mut self, size: usize) -> &mut Self {
+        assert!(size > 0, "block size must not be 0");
+        self.block_size = Some(size);
+        self
+    }
+
+    /**
+     * Hold the files these options make to the rules of direct I/O, as
+     * Linux does for O_DIRECT: reads and writes fail with InvalidInput
+     * unless the address and length of every buffer and the file offset
+     * are multiples of `alignment`, usually 512 or 4096. Short transfers
+     * are whole blocks, as with block_size, since those are the ones
+     * direct I/O produces. This only checks; see
+     * IncompleteOpenOptions::direct to also open a file with O_DIRECT.
+     */
+    pub fn direct_io(&mut self, alignment: usize) -> &mut Self {
+        self.block_size(alignment);
+        self.direct_io = Some(alignment);
+        self
+    }
+
+    /**
+     * Model a transport with a tiny internal buffer of `buffer_size` bytes,
+     * like a small PIPE_BUF or SO_RCVBUF: no single read or write moves more
+     * than that, whatever the caller's buffer size and whether the operation
+     * was truncated, planned, or let through in full. Defaults to no limit,
+     * and 0 is treated as 1.
+     */
+    pub fn buffer_size(&mut self, buffer_size: usize) -> &mut Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    /**
+     * Choose what wrappers do when they are dropped with bytes written
+     * through write or write_vectored since their last successful flush or
+     * sync, to catch code that forgets to flush, which only loses data with
+     * a sink that really buffers. Shutting down or closing an async writer
+     * counts as a flush, and into_inner hands the unflushed bytes over to
+     * the caller without a check. IncompleteFile never checks, since its
+     * writes go straight to the file. Nothing happens while the thread is
+     * already panicking. Defaults to UnflushedDrop::Ignore.
+     */
+    pub fn on_unflushed_drop(&mut self, action: UnflushedDrop) -> &mut Self {
+        self.on_unflushed_drop = action;
+        self
+    }
+
+    /**
+     * Pass the first `n` reads and writes through, so that handshakes and
+     * headers go through normally and the chaos is aimed at the body of a
+     * transfer. This is a calm stretch, see IncompleteOptions, and bursts
+     * start counting after it. Defaults to 0.
+     */
+    pub fn warm_up(&mut self, n: usize) -> &mut Self {
+        self.warm_up = n;
+        self
+    }
+
+    /**
+     * Pass every read and write after the first `n` through, the inverse of
+     * warm_up, so that a huge test finishes quickly once its early phase has
+     * been through the chaos. This is a calm stretch, see IncompleteOptions.
+     * Defaults to never.
+     */
+    pub fn calm_after(&mut self, n: usize) -> &mut Self {
+        self.calm_after = Some(n);
+        self
+    }
+
+    /**
+     * Pass every read and write through once `bytes` bytes have been read
+     * and written, see calm_after. Defaults to never.
+     */
+    pub fn calm_after_bytes(&mut self, bytes: u64) -> &mut Self {
+        self.calm_after_bytes = Some(bytes);
+        self
+    }
+
+    /**
+     * Only truncate and fail reads and writes that start within `window` of
+     * stream offsets, to aim them at one part of a file format, like its
+     * index block. Those that start before it are cut short to stop at its
+     * start, and otherwise passed through like those that start after it,
+     * in a calm stretch, see IncompleteOptions. Defaults to the whole
+     * stream.
+     */
+    pub fn window(&mut self, window: Range<u64>) -> &mut Self {
+        self.window = Some(window);
+        self
+    }
+
+    /**
+     * Alternate between stretches of `calm` reads and writes that are
+     * passed through, and stretches of `stormy` ones that are truncated and
+     * failed as these options say, starting calm, see IncompleteOptions.
+     * Loops that cope with constant chaos can still break when conditions
+     * change part way through a stream. Defaults to always stormy. Returns
+     * InvalidInput, leaving the options as they were, if both stretches are
+     * empty or their total overflows.
+     */
+    pub fn bursts(&mut self, calm: usize, stormy: usize) -> Result<&mut Self> {
+        let error = match calm.checked_add(stormy) {
+            Some(0) => "bursts of no operations",
+            Some(_) => {
+                self.bursts = Some((calm, stormy));
+                return Ok(self);
+            }
+            None => "bursts of more than usize::MAX operations",
+        };
+        Err(Error::new(ErrorKind::InvalidInput, error))
+    }
+
+    /**
+     * Let this fraction of operations complete in full instead of being
+     * truncated. Defaults to 0.0, where every operation that can be truncated
+     * is. Panics unless `probability` is between 0.0 and 1.0.
+     */
+    pub fn full_op_probability(&mut self, probability: f64) -> &mut Self {
+        self.full_op_probability = check_probability(probability);
+        self
+    }
+
+    /**
+     * Fail this fraction of reads and writes with ErrorKind::Interrupted,
+     * which the Read and Write contracts say callers should retry. Defaults
+     * to 0.0. Panics unless `probability` is between 0.0 and 1.0.
+     */
+    pub fn interrupted_probability(&mut self, probability: f64) -> &mut Self {
+        self.interrupted_probability = check_probability(probability);
+        self
+    }
+
+    /**
+     * Make this fraction of writes return Ok(0) without writing anything.
+     * Write's contract allows this, and write_all turns it into an
+     * ErrorKind::WriteZero error, so this exercises callers' WriteZero
+     * handling. Defaults to 0.0. Panics unless `probability` is between 0.0
+     * and 1.0.
+     */
+    pub fn write_zero_probability(&mut self, probability: f64) -> &mut Self {
+        self.write_zero_probability = check_probability(probability);
+        self
+    }
+
+    /**
+     * Make this fraction of reads return Ok(0) even though more data
+     * follows. Read's documentation notes that a zero byte read does not
+     * mean EOF is permanent, and this catches code that treats the first
+     * zero as final. Defaults to 0.0. Panics unless `probability` is between
+     * 0.0 and 1.0.
+     */
+    pub fn spurious_eof_probability(&mut self, probability: f64) -> &mut Self {
+        self.spurious_eof_probability = check_probability(probability);
+        self
+    }
+
+    /**
+     * Fail this fraction of reads and writes with ErrorKind::WouldBlock, as
+     * a non-blocking socket would. Defaults to 0.0. Panics unless
+     * `probability` is between 0.0 and 1.0.
+     */
+    pub fn would_block_probability(&mut self, probability: f64) -> &mut Self {
+        self.would_block_probability = check_probability(probability);
+        self
+    }
+
+    /**
+     * Never fail more than `limit` operations in a row with WouldBlock, so
+     * that a caller that keeps retrying is guaranteed to make progress.
+     * Defaults to no limit.
+     */
+    pub fn would_block_limit(&mut self, limit: usize) -> &mut Self {
+        self.would_block_limit = Some(limit);
+        self
+    }
+
+    /**
+     * Fail this fraction of flushes with an error of kind `kind`, for the
+     * writers that only report errors when they are flushed. Together with
+     * fail_on(IoOp::Flush, ..) for a flush that fails once, and
+     * FailureMode::Sticky for one that fails forever, this covers the ways
+     * a flush goes wrong. Defaults to 0.0. Panics unless `probability` is
+     * between 0.0 and 1.0.
+     */
+    pub fn flush_error_probability(&mut self, probability: f64, kind: ErrorKind) -> &mut Self {
+        self.flush_errors = Some((check_probability(probability), kind));
+        self
+    }
+
+    /**
+     * Fail the `n`th operation of kind `op` (counting from 1) with an error
+     * of kind `kind`, for example `fail_on(IoOp::Flush, 2, ErrorKind::Other)`
+     * to fail the second flush. Call this repeatedly to build up a schedule.
+     * Scheduled errors take priority over all other behavior. Panics if `n`
+     * is 0.
+     */
+    pub fn fail_on(&mut self, op: IoOp, n: usize, kind: ErrorKind) -> &mut Self {
+        assert!(n > 0, "operations are counted from 1");
+        self.schedule.push((op, n, kind));
+        self
+    }
+
+    /**
+     * Fail with an error of kind `kind` exactly when the stream position
+     * reaches `offset`: a read or write that would cross it is cut short to
+     * stop at `offset`, and the next read or write fails. The trigger also
+     * fires on the next read or write after a seek moves the position past
+     * `offset`. Call this repeatedly for several triggers, each of which
+     * fires once.
+     */
+    pub fn fail_at_offset(&mut self, offset: u64, kind: ErrorKind) -> &mut Self {
+        self.offset_triggers.push((offset, kind));
+        self
+    }
+
+    /**
+     * Fail the next read or write with an error of kind `kind` once
+     * `pattern` has passed through an `op` operation, either Read or Write,
+     * even split across several of them. Unlike fail_at_offset this finds a
+     * record however big the ones before it were, for example failing just
+     * after `b"COMMIT"` is written. Only the blocking wrappers see what is
+     * read and written, apart from BufRead and positional operations. Call
+     * this repeatedly for several triggers, each of which fires once.
+     */
+    pub fn fail_after_pattern(
+        &mut self,
+        op: IoOp,
+        pattern: impl Into<Vec<u8>>,
+        kind: ErrorKind,
+    ) -> &mut Self {
+        let pattern = pattern.into();
+        assert!(
+            matches!(op, IoOp::Read | IoOp::Write),
+            "only reads and writes move data, not {:?}",
+            op
+        );
+        assert!(!pattern.is_empty(), "an empty pattern would always match");
+        self.pattern_triggers.push((op, pattern, kind));
+        self
+    }
+
+    /**
+     * Let `n` reads and writes (counted together) go ahead, then fail the
+     * next one with an error of kind `kind`. Sweeping `n` from 0 upwards
+     * moves the failure across every call site in turn. Call this
+     * repeatedly for several triggers.
+     */
+    pub fn fail_after_ops(&mut self, n: usize, kind: ErrorKind) -> &mut Self {
+        self.op_triggers.push((n, kind));
+        self
+    }
+
+    /**
+     * Simulate a disk that fills up after `bytes` bytes have been written:
+     * the write that reaches the quota is cut short to fit, and every
+     * write after it fails with StorageFull. Each wrapper has its own
+     * quota, counting the bytes written through it (positional writes are
+     * cut short but not counted). Defaults to no quota.
+     */
+    pub fn storage_quota(&mut self, bytes: u64) -> &mut Self {
+        self.storage_quota = Some(bytes);
+        self
+    }
+
+    /**
+     * Simulate the file system being remounted read-only part way through a
+     * run: once `n` writes have been attempted, every later write and
+     * set_len fails with PermissionDenied, while reads, seeks, and syncs
+     * keep working. Defaults to never.
+     */
+    pub fn read_only_after(&mut self, n: usize) -> &mut Self {
+        self.read_only_after = Some(n);
+        self
+    }
+
+    /**
+     * Sleep for a random time between `min` and `max` (inclusive, so pass
+     * the same value twice for a fixed delay) before every read, write, and
+     * flush, to test timeouts and progress reporting against a slow device.
+     * The blocking wrappers sleep on the calling thread, and the async ones
+     * return Poll::Pending until the time has passed, without blocking the
+     * executor. Defaults to no delay.
+     */
+    pub fn latency(&mut self, min: Duration, max: Duration) -> &mut Self {
+        assert!(
+            min <= max,
+            "min latency {:?} is larger than max {:?}",
+            min,
+            max
+        );
+        self.latency = Some(min..=max);
+        self
+    }
+
+    /**
+     * Limit reads and writes to `bytes_per_second` on average, like a
+     * slow device, to test progress bars and adaptive buffering against
+     * sustained throughput. Each operation moves at most a tenth of a
+     * second's worth of bytes, on top of any other truncation, and waits
+     * until the bytes before it would have been moved at that rate, the
+     * same way latency waits. Defaults to no limit.
+     */
+    pub fn throttle(&mut self, bytes_per_second: u64) -> &mut Self {
+        assert!(
+            bytes_per_second > 0,
+            "throttle must be above 0 bytes per second"
+        );
+        self.throttle = Some(bytes_per_second);
+        self
+    }
+
+    /**
+     * Record an IoEvent for every operation, to see exactly which sequence
+     * of partial operations and errors code under test was fed when it
+     * fails. Wrappers keep every event in memory until they are dropped,
+     * see Injector::events. Defaults to false.
+     */
+    pub fn trace(&mut self, trace: bool) -> &mut Self {
+        self.trace = trace;
+        self
+    }
+
+    /**
+     * Log every decision an Injector makes at `level`, under the
+     * "incomplete_file" target. Defaults to Level::Trace, so the decisions
+     * stay out of the way unless asked for.
+     */
+    #[cfg(feature = "log")]
+    pub fn log_level(&mut self, level: log::Level) -> &mut Self {
+        self.log_level = level;
+        self
+    }
+
+    /**
+     * Choose whether operations after a scheduled or triggered error succeed
+     * again (FailureMode::Transient, the default) or keep failing with the
+     * same error forever (FailureMode::Sticky). Interrupted and WouldBlock
+     * errors are always transient, because callers are meant to retry them.
+     */
+    pub fn failure_mode(&mut self, mode: FailureMode) -> &mut Self {
+        self.failure_mode = mode;
+        self
+    }
+
+    /**
+     * Set the truncation bounds, size policy, error rates, and Ok(0)
+     * injection to one of the named severity levels, so that a team can
+     * standardize on them instead of tuning each option. This replaces
+     * min_size, max_size, policy, full_op_probability,
+     * interrupted_probability, write_zero_probability, and
+     * spurious_eof_probability, which can be adjusted afterwards.
+     */
+    pub fn profile(&mut self, profile: Profile) -> &mut Self {
+        let (policy, full, interrupted, write_zero, spurious_eof) = match profile {
+            Profile::Mild => (Policy::Uniform, 0.5, 0.01, 0.0, 0.0),
+            Profile::Aggressive => (Policy::Geometric, 0.1, 0.1, 0.01, 0.0),
+            Profile::Pathological => (Policy::OneByte, 0.0, 0.25, 0.05, 0.01),
+        };
+        self.min_size(1)
+            .max_size(usize::MAX)
+            .policy(policy)
+            .full_op_probability(full)
+            .interrupted_probability(interrupted)
+            .write_zero_probability(write_zero)
+            .spurious_eof_probability(spurious_eof)
+    }
+
+    /**
+     * Decide `op` operations with `options` instead of these, so that each
+     * kind of operation can be configured independently, for example to
+     * find out which side of some duplex code is at fault by making only
+     * its reads chaotic. `options` replace these entirely for `op`,
+     * including their latency, scripts, and errors, but not the size of
+     * the buffer or their stats, which stay with these options. Setting
+     * an operation again replaces its options.
+     */
+    pub fn op_options(&mut self, op: IoOp, options: &IncompleteOptions) -> &mut Self {
+        self.op_options.retain(|(o, _)| *o != op);
+        self.op_options.push((op, options.clone()));
+        self
+    }
+
+    /**
+     * Pass `op` operations through untouched, see op_options.
+     */
+    pub fn faithful(&mut self, op: IoOp) -> &mut Self {
+        self.op_options(op, IncompleteOptions::default().full_op_probability(1.0))
+    }
+
+    /**
+     * Choose how truncation sizes are distributed. Defaults to
+     * Policy::Uniform. Panics on Policy::Records(0).
+     */
+    pub fn policy(&mut self, policy: Policy) -> &mut Self {
+        assert!(policy != Policy::Records(0), "records of no bytes");
+        self.policy = policy;
+        self
+    }
+
+    /**
+     * Instead of picking sizes at random, limit each successive read or
+     * write to the next size in `plan`, for example `[3, 1]` to read 3
+     * bytes, then 1, then the rest. Once the plan runs out operations are
+     * passed through. Sizes of 0 are treated as 1, and size bounds and
+     * probabilities do not apply to planned operations.
+     */
+    pub fn plan(&mut self, plan: impl IntoIterator<Item = usize>) -> &mut Self {
+        self.plan = Some(plan.into_iter().collect());
+        self
+    }
+
+    /**
+     * Run `test` with these options and a new seed over and over on
+     * `threads` threads until `budget` has passed, for soaking code in
+     * chaos overnight. The seeds are derived from the one set on these
+     * options, if any, so `INCOMPLETE_SEED` makes a soak repeatable. A
+     * run fails by returning an error or panicking, and the report counts
+     * the failures and keeps the seed of the first hundred to replay them
+     * with, but the soak carries on either way.
+     */
+    pub fn soak(
+        &self,
+        threads: usize,
+        budget: Duration,
+        test: impl Fn(&IncompleteOptions) -> Result<()> + Sync,
+    ) -> SoakReport {
+        soak::soak(self, threads, budget, test)
+    }
+
+    /**
+     * Like plan, but give each successive read or write the next decision
+     * in `script`, which can also fail it or return zero bytes. Scripted
+     * reads and writes are not sized, capped, or failed in any other way,
+     * and once the script runs out they are passed through. This is how
+     * shrink reports a failure: `[Decision::Limit(3), Decision::Full,
+     * Decision::Fail(ErrorKind::Interrupted)]` reads 3 bytes, then as much
+     * as was asked for, and then fails with Interrupted.
+     */
+    pub fn script(&mut self, script: impl IntoIterator<Item = Decision>) -> &mut Self {
+        self.script = Some(script.into_iter().collect());
+        self
+    }
+
+    /**
+     * Run `test` with these options, and if it fails, by returning an error
+     * or panicking, search for the simplest script that still fails it:
+     * one with as few decisions as possible, each letting through as many
+     * bytes as possible. A randomized failure found in thousands of
+     * operations usually comes down to a handful of them. Pass the result
+     * to script to replay it. Returns None if the test passes, or if it
+     * does not fail again when its own decisions are replayed as a script,
+     * which can happen when it makes more than one wrapper.
+     */
+    pub fn shrink(
+        &self,
+        test: impl FnMut(&IncompleteOptions) -> Result<()>,
+    ) -> Option<Vec<Decision>> {
+        shrink::shrink(self, test)
+    }
+
+    /**
+     * Run `test` once for every combination of decisions for its first
+     * `depth` reads and writes, so that a rare combination of a few of them
+     * is found for certain instead of by luck, as loom does for thread
+     * interleavings. Each decision is a branch point: the operation is
+     * passed through, limited to 1 byte, limited to all but 1 byte, or
+     * failed with Interrupted. Operations past the first `depth` are passed
+     * through. The decisions are shared by every wrapper made from the
+     * options the test is given, in the order they are made, so the test
+     * must make them in the same order every time. Runs grow as 4^depth,
+     * so keep it small. Returns the number of runs, or the first error,
+     * labelled with the decisions that caused it.
+     */
+    pub fn explore(
+        &self,
+        depth: usize,
+        test: impl FnMut(&IncompleteOptions) -> Result<()>,
+    ) -> Result<usize> {
+        explore::explore(self, depth, test)
+    }
+
+    /**
+     * Make every operation on an async wrapper return Poll::Pending `count`
+     * times before it goes ahead, waking the task each time, to check that
+     * futures survive spurious pending states without losing their wakers.
+     * Has no effect on the blocking wrappers. Defaults to 0.
+     */
+    pub fn pending_count(&mut self, count: usize) -> &mut Self {
+        self.pending_count = count;
+        self
+    }
+
+    /**
+     * When sync_all or sync_data fails on a file, throw away everything
+     * written to it since the last successful sync, the way Linux can drop
+     * dirty pages after fsync reports an error. Code that retries the sync
+     * and carries on as if the data were safe then reads back stale
+     * contents. This keeps a copy of the file's synced contents in memory,
+     * so files must be readable: create opens them for reading and writing
+     * when this is set. Changes that would take a file past 1 GiB fail
+     * with Unsupported. Defaults to false.
+     */
+    pub fn lose_unsynced_writes(&mut self, lose: bool) -> &mut Self {
+        self.lose_unsynced_writes = lose;
+        self
+    }
+
+    /**
+     * Keep track of what has been synced to each file, so that
+     * IncompleteFile::crash can leave behind what a power loss would. Like
+     * lose_unsynced_writes, this keeps a copy of the file's contents in
+     * memory, up to 1 GiB. Defaults to false.
+     */
+    pub fn simulate_crashes(&mut self, simulate: bool) -> &mut Self {
+        self.simulate_crashes = simulate;
+        self
+    }
+
+    /**
+     * Check that recovery copes with a crash at any point in `workload`.
+     * The workload is run once against a file created at `path`, recording
+     * every change it makes. Then, for each prefix of those changes, from
+     * none to all of them, the file is left holding what a crash at that
+     * point would leave, and `recover` is called to reopen and verify it.
+     * If the next change is a write, recovery is also checked with that
+     * write torn half way through, or at the middle sector boundary inside
+     * it with sector_size. Returns the number of crash points checked, or
+     * the first error from the workload or from recovery, labelled with
+     * its crash point.
+     */
+    pub fn crash_points(
+        &self,
+        path: &dyn AsRef<Path>,
+        workload: impl FnOnce(&mut IncompleteFile) -> Result<()>,
+        recover: impl FnMut(&Path) -> Result<()>,
+    ) -> Result<usize> {
+        persistence::crash_points(self, path.as_ref(), workload, recover)
+    }
+
+    /**
+     * Let IncompleteFile::crash persist any subset of the changes made
+     * since the last sync, in any order, as a page cache writing back dirty
+     * pages would, instead of only a prefix of them. This catches code that
+     * assumes one write is on disk because a later one is, without a
+     * sync_data in between. Implies simulate_crashes. crash_points still
+     * only checks prefixes. Defaults to false.
+     */
+    pub fn reorder_unsynced_writes(&mut self, reorder: bool) -> &mut Self {
+        self.reorder_unsynced_writes = reorder;
+        self
+    }
+
+    /**
+     * Tear the last write of a crash at a multiple of `size` bytes, for
+     * example 512 or 4096, so that only whole sectors of it are persisted,
+     * as a disk writing sectors atomically would. This is the failure that
+     * checksummed page formats have to detect. By default writes can be
+     * torn at any byte.
+     */
+    pub fn sector_size(&mut self, size: u64) -> &mut Self {
+        assert!(size > 0, "sector size must not be 0");
+        self.sector_size = Some(size);
+        self
+    }
+
+    // Whether wrapped files need a model of what has been synced
+    fn models_persistence(&self) -> bool {
+        self.lose_unsynced_writes || self.simulate_crashes || self.reorder_unsynced_writes
+    }
+
+    /**
+     * Open the file at `path` for reading, see std::fs::File::open.
+     */
+    pub fn open(&self, path: &dyn AsRef<Path>) -> Result<IncompleteFile> {
+        Ok(self.wrap_file(File::open(path)?))
+    }
+
+    /**
+     * Create the file at `path` for writing, see std::fs::File::create.
+     */
+    pub fn create(&self, path: &dyn AsRef<Path>) -> Result<IncompleteFile> {
+        if self.models_persistence() {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+            return Ok(self.wrap_file(file));
+        }
+        Ok(self.wrap_file(File::create(path)?))
+    }
+
+    /**
+     * Create an anonymous temporary file, see tempfile::tempfile.
+     */
+    #[cfg(feature = "tempfile")]
+    pub fn tempfile(&self) -> Result<IncompleteFile> {
+        Ok(self.wrap_file(tempfile::tempfile()?))
+    }
+
+    /**
+     * Create a named temporary file in `dir`, returning it along with its
+     * path. The file is deleted when the TempPath is dropped.
+     */
+    #[cfg(feature = "tempfile")]
+    pub fn named_temp_in(
+        &self,
+        dir: &dyn AsRef<Path>,
+    ) -> Result<(IncompleteFile, tempfile::TempPath)> {
+        let (file, path) = tempfile::NamedTempFile::new_in(dir)?.into_parts();
+        Ok((self.wrap_file(file), path))
+    }
+
+    /**
+     * Wrap a file that is already open, see IncompleteFile::with_file.
+     */
+    pub fn wrap_file(&self, file: File) -> IncompleteFile {
+        // a File has no buffer, so dropping it loses nothing written
+        let injector = Injector::from_options(self).unbuffered();
+        let seed = injector.seed();
+        let mut file = IncompleteFile::with_policy(file, injector);
+        if let Some(alignment) = self.direct_io {
+            file = file.with_alignment(alignment);
+        }
+        if let (true, Some(seed)) = (self.models_persistence(), seed) {
+            return file.with_persistence(Persistence::new(self, seed));
+        }
+        file
+    }
+
+    pub fn wrap_reader<R: Read>(&self, inner: R) -> IncompleteReader<R> {
+        IncompleteReader::with_policy(inner, Injector::from_options(self))
+    }
+
+    pub fn wrap_writer<W: Write>(&self, inner: W) -> IncompleteWriter<W> {
+        IncompleteWriter::with_policy(inner, Injector::from_options(self))
+    }
+
+    /**
+     * Lock the standard input, and truncate reads from it.
+     */
+    pub fn wrap_stdin(&self) -> IncompleteStdin {
+        self.wrap_reader(std::io::stdin().lock())
+    }
+
+    /**
+     * Lock the standard output, and truncate writes to it.
+     */
+    pub fn wrap_stdout(&self) -> IncompleteStdout {
+        self.wrap_writer(std::io::stdout().lock())
+    }
+
+    /**
+     * Lock the standard error, and truncate writes to it.
+     */
+    pub fn wrap_stderr(&self) -> IncompleteStderr {
+        self.wrap_writer(std::io::stderr().lock())
+    }
+
+    /**
+     * Wrap a bidirectional stream such as a TcpStream, with separate
+     * injectors built from these options for its reads and its writes.
+     */
+    pub fn wrap_stream<T: Read + Write>(&self, inner: T) -> IncompleteStream<T> {
+        IncompleteStream::with_policies(
+            inner,
+            Injector::from_options(self),
+            Injector::from_options(self),
+        )
+    }
+
+    /**
+     * Two connected UnixStreams, see UnixStream::pair, each wrapped as by
+     * wrap_stream.
+     */
+    #[cfg(unix)]
+    pub fn unix_pair(
+        &self,
+    ) -> Result<(IncompleteStream<UnixStream>, IncompleteStream<UnixStream>)> {
+        let (a, b) = UnixStream::pair()?;
+        Ok((self.wrap_stream(a), self.wrap_stream(b)))
+    }
+
+    /**
+     * An anonymous OS pipe with both ends wrapped, returned as (writer,
+     * reader). Unlike a file, writes block once the pipe's buffer is full,
+     * and fail with BrokenPipe once the reader is dropped.
+     */
+    pub fn pipe(&self) -> Result<(IncompleteWriter<PipeWriter>, IncompleteReader<PipeReader>)> {
+        let (reader, writer) = std::io::pipe()?;
+        Ok((self.wrap_writer(writer), self.wrap_reader(reader)))
+    }
+
+    /**
+     * Spawn `command` with its stdin and stdout piped, and wrapped in an
+     * IncompleteWriter and IncompleteReader built from these options.
+     * The child's stderr is inherited.
+     */
+    pub fn spawn(&self, command: &mut Command) -> Result<IncompleteChild> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        Ok(IncompleteChild {
+            stdin: child.stdin.take().map(|stdin| self.wrap_writer(stdin)),
+            stdout: child.stdout.take().map(|stdout| self.wrap_reader(stdout)),
+            child,
+        })
+    }
+
+    /**
+     * Read data produced by `generate`, see GeneratedReader.
+     */
+    pub fn wrap_generator<F: FnMut(&mut [u8]) -> usize>(
+        &self,
+        generate: F,
+    ) -> IncompleteReader<GeneratedReader<F>> {
+        self.wrap_reader(GeneratedReader::new(generate))
+    }
+
+    /**
+     * Read, write, and seek over `data` in memory, starting at the
+     * beginning.
+     */
+    pub fn wrap_cursor(&self, data: Vec<u8>) -> IncompleteCursor {
+        IncompleteCursor::with_policy(data, Injector::from_options(self))
+    }
+
+    /**
+     * Connect two in-memory endpoints with `capacity` bytes of buffering in
+     * each direction, see DuplexEnd::pair. Each end truncates its own reads
+     * and writes with a separate injector built from these options.
+     */
+    pub fn duplex(&self, capacity: usize) -> (IncompleteDuplex, IncompleteDuplex) {
+        let (a, b) = DuplexEnd::pair(capacity);
+        (
+            IncompleteDuplex::with_policy(a, Injector::from_options(self)),
+            IncompleteDuplex::with_policy(b, Injector::from_options(self)),
+        )
+    }
+
+    /**
+     * Like duplex, but the endpoints implement the async traits instead.
+     */
+    #[cfg(any(feature = "tokio", feature = "futures"))]
+    pub fn duplex_async(
+        &self,
+        capacity: usize,
+    ) -> (IncompleteAsync<DuplexEnd>, IncompleteAsync<DuplexEnd>) {
+        let (a, b) = DuplexEnd::pair(capacity);
+        (self.wrap_async(a), self.wrap_async(b))
+    }
+
+    /**
+     * Open the file at `path` for reading as a tokio::fs::File.
+     */
+    #[cfg(feature = "tokio")]
+    pub async fn open_async(&self, path: &dyn AsRef<Path>) -> Result<IncompleteAsyncFile> {
+        Ok(self.wrap_async(tokio::fs::File::open(path).await?))
+    }
+
+    /**
+     * Create the file at `path` for writing as a tokio::fs::File.
+     */
+    #[cfg(feature = "tokio")]
+    pub async fn create_async(&self, path: &dyn AsRef<Path>) -> Result<IncompleteAsyncFile> {
+        Ok(self.wrap_async(tokio::fs::File::create(path).await?))
+    }
+
+    /**
+     * Open the file at `path` for reading as an async_fs::File.
+     */
+    #[cfg(feature = "smol")]
+    pub async fn open_smol(&self, path: &dyn AsRef<Path>) -> Result<IncompleteSmolFile> {
+        Ok(self.wrap_async(async_fs::File::open(path).await?))
+    }
+
+    /**
+     * Create the file at `path` for writing as an async_fs::File.
+     */
+    #[cfg(feature = "smol")]
+    pub async fn create_smol(&self, path: &dyn AsRef<Path>) -> Result<IncompleteSmolFile> {
+        Ok(self.wrap_async(async_fs::File::create(path).await?))
+    }
+
+    /**
+     * Wrap a tokio or futures AsyncRead and/or AsyncWrite.
+     */
+    #[cfg(any(feature = "tokio", feature = "futures"))]
+    pub fn wrap_async<T>(&self, inner: T) -> IncompleteAsync<T> {
+        IncompleteAsync::with_policy(inner, Injector::from_options(self))
+            .with_pending_count(self.pending_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, SeekFrom};
+
+    // Record the size of every read it takes to drain a reader
+    fn read_sizes_i(reader: &mut impl Read) -> Result<Vec<usize>> {
+        let mut sizes = vec![];
+        let mut buffer = vec![0; 320];
+
+        loop {
+            let n = reader.read(&mut buffer)?;
+            if n == 0 {
+                return Ok(sizes);
+            }
+            sizes.push(n);
+        }
+    }
+
+    #[test]
+    fn op_options_test() -> Result<()> {
+        // chaotic reads, faithful writes, and flushes that always fail
+        let mut cursor = IncompleteOptions::default()
+            .policy(Policy::OneByte)
+            .faithful(IoOp::Write)
+            .op_options(
+                IoOp::Flush,
+                IncompleteOptions::default().fail_on(IoOp::Flush, 1, ErrorKind::Other),
+            )
+            .wrap_cursor(vec![]);
+        assert_eq!(cursor.write(b"hello")?, 5);
+        cursor.seek(SeekFrom::Start(0))?;
+        assert_eq!(cursor.read(&mut [0; 5])?, 1);
+        assert_eq!(cursor.flush().unwrap_err().kind(), ErrorKind::Other);
+
+        Ok(())
+    }
+
+    #[test]
+    fn profile_test() -> Result<()> {
+        let data = vec![7; 4096];
+
+        // most code survives the milder profiles
+        for profile in [Profile::Mild, Profile::Aggressive] {
+            let mut options = IncompleteOptions::default();
+            let mut reader = options.profile(profile).wrap_reader(&data[..]);
+            let mut buffer = vec![];
+            loop {
+                match reader.read_to_end(&mut buffer) {
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    result => result?,
+                };
+                break;
+            }
+            assert_eq!(buffer, data);
+        }
+
+        // every operation is a single byte, and writes can return Ok(0)
+        let mut writer = IncompleteOptions::default()
+            .profile(Profile::Pathological)
+            .interrupted_probability(0.0)
+            .seed(1)
+            .wrap_writer(vec![]);
+        let mut zeros = 0;
+        for _ in 0..200 {
+            match writer.write(b"hello")? {
+                0 => zeros += 1,
+                n => assert_eq!(n, 1),
+            }
+        }
+        assert!(zeros > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn env_test() {
+        let vars = [
+            ("INCOMPLETE_SEED", "1234"),
+            ("INCOMPLETE_POLICY", "one_byte"),
+            ("INCOMPLETE_MAX_SIZE", " 64 "),
+            ("INCOMPLETE_INTERRUPTED_PROBABILITY", "0.25"),
+        ];
+        let lookup = |name: &str| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        };
+
+        let mut options = IncompleteOptions::default();
+        options.apply_vars(lookup).unwrap();
+        assert_eq!(options.seed, Some(1234));
+        assert_eq!(options.policy, Policy::OneByte);
+        assert_eq!((options.min_size, options.max_size), (1, 64));
+        assert_eq!(options.interrupted_probability, 0.25);
+
+        // options set in code afterwards win
+        options.seed(1);
+        assert_eq!(options.seed, Some(1));
+
+        let mut options = IncompleteOptions::default();
+        options
+            .apply_vars(|name| (name == "INCOMPLETE_POLICY").then(|| "records:512".to_string()))
+            .unwrap();
+        assert_eq!(options.policy, Policy::Records(512));
+        let e = IncompleteOptions::default()
+            .apply_vars(|name| (name == "INCOMPLETE_POLICY").then(|| "records:0".to_string()))
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+        let e = IncompleteOptions::default()
+            .apply_vars(|_| Some("lots".to_string()))
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        assert!(e.to_string().contains("INCOMPLETE_SEED"));
+
+        // new applies good overrides and ignores bad ones
+        assert_eq!(IncompleteOptions::from_vars(lookup).seed, Some(1234));
+        let options = IncompleteOptions::from_vars(|name| {
+            (name == "INCOMPLETE_PROFILE").then(|| "none".to_string())
+        });
+        assert_eq!(options.policy, Policy::Uniform);
+    }
+
+    #[test]
+    fn reuse_test() -> Result<()> {
+        let data = vec![0; 320 * 64];
+        let mut options = IncompleteOptions::default();
+        options.seed(5);
+
+        // every wrapper made from the same options sees the same sizes
+        let first = read_sizes_i(&mut options.wrap_reader(&data[..]))?;
+        let second = read_sizes_i(&mut options.wrap_reader(&data[..]))?;
+        assert_eq!(first, second);
+        assert_eq!(options.wrap_writer(Vec::new()).seed(), Some(5));
+
+        Ok(())
+    }
+
+    #[test]
+    fn bounds_test() -> Result<()> {
+        let data = vec![0; 320 * 64];
+
+        let mut reader = IncompleteOptions::default()
+            .min_size(16)
+            .max_size(64)
+            .wrap_reader(&data[..]);
+        let sizes = read_sizes_i(&mut reader)?;
+
+        // only the last read at EOF may be short of min_size
+        let (_, sizes) = sizes.split_last().unwrap();
+        assert!(sizes.iter().all(|n| (16..=64).contains(n)));
+
+        // buffers smaller than min_size are passed through
+        let mut reader = IncompleteOptions::default()
+            .min_size(16)
+            .wrap_reader(&data[..]);
+        let mut buffer = [0; 16];
+        assert_eq!(reader.read(&mut buffer)?, 16);
+
+        let mut writer = IncompleteOptions::default()
+            .max_size(64)
+            .wrap_writer(vec![]);
+        assert!(writer.write(&data)? <= 64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn full_op_probability_test() -> Result<()> {
+        let data = vec![0; 320 * 64];
+
+        let mut reader = IncompleteOptions::default()
+            .full_op_probability(1.0)
+            .wrap_reader(&data[..]);
+        assert!(read_sizes_i(&mut reader)?.iter().all(|n| *n == 320));
+
+        // full operations still respect max_size
+        let mut writer = IncompleteOptions::default()
+            .full_op_probability(1.0)
+            .max_size(64)
+            .wrap_writer(vec![]);
+        assert_eq!(writer.write(&data)?, 64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn records_test() -> Result<()> {
+        let data = [0; 100];
+        let mut reader = IncompleteOptions::default()
+            .policy(Policy::Records(10))
+            .wrap_reader(&data[..]);
+
+        // every read but the last ends next to a boundary
+        let sizes = read_sizes_i(&mut reader)?;
+        let mut position = 0;
+        for size in &sizes[..sizes.len() - 1] {
+            position += size;
+            assert!([9, 0, 1].contains(&(position % 10)), "{:?}", sizes);
+        }
+        assert_eq!(sizes.iter().sum::<usize>(), 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn block_size_test() -> Result<()> {
+        let data = vec![7; 10000];
+        let mut reader = IncompleteOptions::default()
+            .block_size(512)
+            .wrap_reader(&data[..]);
+
+        // whole blocks, then whatever is left with the 272 extra bytes
+        let mut buffer = vec![0; 4096];
+        let mut sizes = vec![];
+        loop {
+            match reader.read(&mut buffer)? {
+                0 => break,
+                n => sizes.push(n),
+            }
+        }
+        let (last, blocks) = sizes.split_last().unwrap();
+        assert!(blocks.iter().all(|n| n % 512 == 0), "{:?}", sizes);
+        assert_eq!(last % 512, 272);
+
+        Ok(())
+    }
+
+    #[test]
+    fn one_byte_test() -> Result<()> {
+        let data = vec![0; 320];
+
+        let mut options = IncompleteOptions::default();
+        options.policy(Policy::OneByte).min_size(16);
+
+        let mut reader = options.wrap_reader(&data[..]);
+        assert_eq!(read_sizes_i(&mut reader)?, vec![1; 320]);
+
+        let mut writer = options.wrap_writer(vec![]);
+        assert_eq!(writer.write(&data)?, 1);
+        writer.write_all(&data[1..])?;
+        assert_eq!(writer.into_inner(), data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn interrupted_test() -> Result<()> {
+        let data = vec![0; 320 * 64];
+
+        let mut reader = IncompleteOptions::default()
+            .interrupted_probability(0.5)
+            .wrap_reader(&data[..]);
+
+        // read_exact retries interrupted reads, but a plain read fails
+        let mut buffer = vec![0; 320 * 64];
+        reader.read_exact(&mut buffer)?;
+
+        let mut reader = IncompleteOptions::default()
+            .interrupted_probability(1.0)
+            .wrap_reader(&data[..]);
+        let e = reader.read(&mut buffer).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::Interrupted);
+
+        Ok(())
+    }
+
+    #[test]
+    fn would_block_test() -> Result<()> {
+        let data = vec![0; 320 * 64];
+
+        let mut reader = IncompleteOptions::default()
+            .would_block_probability(1.0)
+            .would_block_limit(3)
+            .wrap_reader(&data[..]);
+
+        // every fourth read is guaranteed to make progress
+        let mut buffer = vec![0; 320];
+        for _ in 0..3 {
+            for _ in 0..3 {
+                let e = reader.read(&mut buffer).unwrap_err();
+                assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+            }
+            assert!(reader.read(&mut buffer)? > 0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn flush_error_probability_test() -> Result<()> {
+        let mut writer = IncompleteOptions::default()
+            .seed(1)
+            .flush_error_probability(0.5, ErrorKind::Other)
+            .wrap_writer(vec![]);
+        let results: Vec<bool> = (0..100).map(|_| writer.flush().is_ok()).collect();
+        assert!(results.contains(&true) && results.contains(&false));
+
+        // a sticky failure takes later writes down with it
+        let mut writer = IncompleteOptions::default()
+            .flush_error_probability(1.0, ErrorKind::BrokenPipe)
+            .failure_mode(FailureMode::Sticky)
+            .wrap_writer(vec![]);
+        writer.write_all(b"hello")?;
+        assert_eq!(writer.flush().unwrap_err().kind(), ErrorKind::BrokenPipe);
+        assert_eq!(
+            writer.write(b"!").unwrap_err().kind(),
+            ErrorKind::BrokenPipe
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn on_unflushed_drop_test() -> Result<()> {
+        let mut options = IncompleteOptions::default();
+        options.on_unflushed_drop(UnflushedDrop::Panic);
+
+        let mut writer = options.wrap_writer(vec![]);
+        writer.write_all(b"hello")?;
+        assert_eq!(writer.policy().unflushed(), 5);
+        writer.flush()?;
+        assert_eq!(writer.policy().unflushed(), 0);
+        drop(writer);
+
+        // a failed flush leaves the bytes unflushed
+        let result = std::panic::catch_unwind(|| {
+            let mut writer = options
+                .clone()
+                .fail_on(IoOp::Flush, 1, ErrorKind::Other)
+                .wrap_writer(vec![]);
+            writer.write_all(b"hello").unwrap();
+            assert!(writer.flush().is_err());
+        });
+        assert!(result.is_err());
+
+        // into_inner hands the unflushed bytes to the caller
+        let mut writer = options.wrap_writer(vec![]);
+        writer.write_all(b"hello")?;
+        assert_eq!(writer.into_inner(), b"hello");
+
+        // and a file has nothing to flush
+        let dir = tempfile::tempdir()?;
+        let mut file = options.create(&dir.path().join("unflushed"))?;
+        file.write_all(b"hello")?;
+        drop(file);
+
+        Ok(())
+    }
+
+    #[test]
+    fn schedule_test() -> Result<()> {
+        let mut writer = IncompleteOptions::default()
+            .plan([1, 1])
+            .fail_on(IoOp::Write, 2, ErrorKind::StorageFull)
+            .fail_on(IoOp::Flush, 1, ErrorKind::TimedOut)
+            .wrap_writer(vec![]);
+
+        assert_eq!(writer.write(b"abc")?, 1);
+        let e = writer.write(b"bc").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::StorageFull);
+        assert_eq!(writer.flush().unwrap_err().kind(), ErrorKind::TimedOut);
+        writer.flush()?;
+
+        // neither the failed write nor the flushes used up the plan
+        assert_eq!(writer.write(b"bc")?, 1);
+        assert_eq!(writer.write(b"c")?, 1);
+        assert_eq!(writer.into_inner(), b"abc");
+
+        Ok(())
+    }
+
+    #[test]
+    fn sticky_test() -> Result<()> {
+        let mut writer = IncompleteOptions::default()
+            .fail_on(IoOp::Write, 2, ErrorKind::Other)
+            .failure_mode(FailureMode::Sticky)
+            .wrap_writer(vec![]);
+
+        writer.write_all(b"a")?;
+        for _ in 0..3 {
+            assert_eq!(writer.write(b"b").unwrap_err().kind(), ErrorKind::Other);
+            assert_eq!(writer.flush().unwrap_err().kind(), ErrorKind::Other);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn fail_after_ops_test() -> Result<()> {
+        // sweep the failure across every write that write_all makes
+        for n in 0..3 {
+            let mut writer = IncompleteOptions::default()
+                .plan([1, 1])
+                .fail_after_ops(n, ErrorKind::Other)
+                .wrap_writer(vec![]);
+
+            let e = writer.write_all(b"abc").unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::Other);
+            assert_eq!(writer.into_inner().len(), n);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn fail_after_pattern_test() -> Result<()> {
+        let mut writer = IncompleteOptions::default()
+            .policy(Policy::OneByte)
+            .fail_after_pattern(IoOp::Write, &b"COMMIT"[..], ErrorKind::Other)
+            .wrap_writer(vec![]);
+
+        // the pattern only matches once all of it has gone through
+        writer.write_all(b"BEGIN data COMMIT")?;
+        assert_eq!(writer.write(b"x").unwrap_err().kind(), ErrorKind::Other);
+        writer.write_all(b"COMMIT")?;
+        assert_eq!(writer.into_inner(), b"BEGIN data COMMITCOMMIT");
+
+        Ok(())
+    }
+
+    #[test]
+    fn storage_quota_test() -> Result<()> {
+        let mut writer = IncompleteOptions::default()
+            .plan([])
+            .storage_quota(10)
+            .wrap_writer(vec![]);
+
+        // the last write that fits is short, then the disk is full
+        assert_eq!(writer.write(b"backup")?, 6);
+        assert_eq!(writer.write(b"backup")?, 4);
+        let e = writer.write_all(b"backup").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::StorageFull);
+        assert_eq!(writer.into_inner(), b"backupback");
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_only_test() -> Result<()> {
+        let mut file = IncompleteOptions::default()
+            .plan([])
+            .read_only_after(1)
+            .wrap_cursor(vec![]);
+
+        file.write_all(b"log line\n")?;
+        let e = file.write_all(b"another\n").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+
+        // what was written can still be read back
+        file.seek(SeekFrom::Start(0))?;
+        let mut contents = vec![];
+        file.read_to_end(&mut contents)?;
+        assert_eq!(contents, b"log line\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn bursts_test() -> Result<()> {
+        let data = vec![0; 1000];
+        let mut reader = IncompleteOptions::default()
+            .policy(Policy::OneByte)
+            .bursts(2, 3)?
+            .wrap_reader(&data[..]);
+
+        assert_eq!(read_sizes_i(&mut reader)?, [320, 320, 1, 1, 1, 320, 37]);
+
+        // the longest bursts there can be
+        let mut reader = IncompleteOptions::default()
+            .bursts(usize::MAX - 1, 1)?
+            .wrap_reader(&data[..]);
+        assert_eq!(read_sizes_i(&mut reader)?, [320, 320, 320, 40]);
+
+        // and ones there cannot be
+        let mut options = IncompleteOptions::default();
+        for (calm, stormy) in [(0, 0), (usize::MAX, 1)] {
+            let e = options.bursts(calm, stormy).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        }
+        assert_eq!(options.bursts, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn warm_up_test() -> Result<()> {
+        let data = vec![0; 700];
+        let mut reader = IncompleteOptions::default()
+            .policy(Policy::OneByte)
+            .warm_up(2)
+            .wrap_reader(&data[..]);
+
+        // the last 60 bytes one at a time
+        let sizes = read_sizes_i(&mut reader)?;
+        assert_eq!(sizes[..3], [320, 320, 1]);
+        assert_eq!(sizes.len(), 62);
+
+        Ok(())
+    }
+
+    #[test]
+    fn calm_after_test() -> Result<()> {
+        let data = vec![0; 1000];
+        let mut reader = IncompleteOptions::default()
+            .policy(Policy::OneByte)
+            .calm_after(2)
+            .wrap_reader(&data[..]);
+        assert_eq!(read_sizes_i(&mut reader)?, [1, 1, 320, 320, 320, 38]);
+
+        let mut reader = IncompleteOptions::default()
+            .policy(Policy::OneByte)
+            .calm_after_bytes(3)
+            .wrap_reader(&data[..]);
+        assert_eq!(read_sizes_i(&mut reader)?, [1, 1, 1, 320, 320, 320, 37]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn window_test() -> Result<()> {
+        let data = vec![0; 1000];
+        let mut reader = IncompleteOptions::default()
+            .policy(Policy::OneByte)
+            .window(500..503)
+            .wrap_reader(&data[..]);
+        assert_eq!(read_sizes_i(&mut reader)?, [320, 180, 1, 1, 1, 320, 177]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn latency_test() -> Result<()> {
+        let delay = Duration::from_millis(5);
+        let mut writer = IncompleteOptions::default()
+            .plan([2])
+            .latency(delay, delay)
+            .wrap_writer(vec![]);
+
+        // two writes and a flush
+        let start = std::time::Instant::now();
+        writer.write_all(b"abc")?;
+        writer.flush()?;
+        assert!(start.elapsed() >= delay * 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn throttle_test() -> Result<()> {
+        let data = vec![0; 480];
+        let mut reader = IncompleteOptions::default()
+            .full_op_probability(1.0)
+            .throttle(1600)
+            .wrap_reader(&data[..]);
+
+        // 480 bytes at 1600 bytes a second, 160 at a time
+        let start = std::time::Instant::now();
+        assert_eq!(read_sizes_i(&mut reader)?, [160, 160, 160]);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_zero_test() -> Result<()> {
+        let mut writer = IncompleteOptions::default()
+            .write_zero_probability(1.0)
+            .wrap_writer(vec![]);
+
+        assert_eq!(writer.write(b"abc")?, 0);
+        let e = writer.write_all(b"abc").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::WriteZero);
+        assert!(writer.into_inner().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn spurious_eof_test() -> Result<()> {
+        let data = vec![1; 320 * 64];
+        let mut reader = IncompleteOptions::default()
+            .spurious_eof_probability(0.5)
+            .wrap_reader(&data[..]);
+
+        // code that stops at the first zero byte read comes up short, but
+        // the rest of the data is still there
+        let mut buffer = vec![];
+        reader.read_to_end(&mut buffer)?;
+        assert!(buffer.len() < data.len());
+
+        while buffer.len() < data.len() {
+            reader.read_to_end(&mut buffer)?;
+        }
+        assert_eq!(buffer, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn buffer_size_test() -> Result<()> {
+        let data = vec![0; 320 * 64];
+
+        // even full and planned operations are capped
+        let mut reader = IncompleteOptions::default()
+            .full_op_probability(1.0)
+            .buffer_size(64)
+            .wrap_reader(&data[..]);
+        assert!(read_sizes_i(&mut reader)?.iter().all(|n| *n == 64));
+
+        let mut writer = IncompleteOptions::default()
+            .plan([1000, 10])
+            .buffer_size(64)
+            .wrap_writer(vec![]);
+        assert_eq!(writer.write(&data)?, 64);
+        assert_eq!(writer.write(&data)?, 10);
+        assert_eq!(writer.write(&data)?, 64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn plan_test() -> Result<()> {
+        let data = vec![0; 320];
+
+        let mut reader = IncompleteOptions::default()
+            .plan([3, 1])
+            .wrap_reader(&data[..]);
+        assert_eq!(read_sizes_i(&mut reader)?, vec![3, 1, 316]);
+
+        // each operation consumes one entry, even if it is already short
+        let mut writer = IncompleteOptions::default()
+            .plan([64, 0, 500])
+            .wrap_writer(vec![]);
+        assert_eq!(writer.write(&data[..10])?, 10);
+        assert_eq!(writer.write(&data)?, 1);
+        assert_eq!(writer.write(&data)?, 320);
+        assert_eq!(writer.write(&data)?, 320);
+
+        Ok(())
+    }
+}
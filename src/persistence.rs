@@ -0,0 +1,670 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::injector::derive_seed;
+use crate::policy::permit;
+use crate::{IncompleteFile, IncompleteOptions, IoOp, PartialPolicy};
+
+// The largest file persistence is modelled for, 1 GiB, since the model keeps
+// whole copies of it in memory
+const MAX_IMAGE_LEN: u64 = 1 << 30;
+
+// A change made to a file since it was last synced, which ends within
+// MAX_IMAGE_LEN
+#[derive(Clone)]
+enum Change {
+    Write { offset: u64, data: Vec<u8> },
+    SetLen(u64),
+}
+
+impl Change {
+    fn apply(&self, image: &mut Vec<u8>) {
+        match self {
+            Change::Write { offset, data } => {
+                let offset = *offset as usize;
+                if image.len() < offset + data.len() {
+                    image.resize(offset + data.len(), 0);
+                }
+                image[offset..offset + data.len()].copy_from_slice(data);
+            }
+            Change::SetLen(size) => image.resize(*size as usize, 0),
+        }
+    }
+}
+
+/**
+ * A model of what has made it to disk: the contents the file had when it
+ * was last successfully synced, and every change made since.
+ */
+pub(crate) struct Persistence {
+    durable: Option<Vec<u8>>,
+    pending: Vec<Change>,
+    lose_unsynced_writes: bool,
+    reorder_unsynced_writes: bool,
+    sector_size: Option<u64>,
+    rng: StdRng,
+    crashed: bool,
+    // Every change ever made, after the contents the file started with
+    history: Option<(Vec<u8>, Vec<Change>)>,
+    recording: bool,
+}
+
+// The fork number crash RNGs are derived with, which Injector::fork never uses
+const CRASH_FORK: u64 = u64::MAX;
+
+impl Persistence {
+    /**
+     * `seed` is the seed of the file's injector, so that which writes
+     * survive a crash is reproduced along with everything else.
+     */
+    pub(crate) fn new(options: &IncompleteOptions, seed: u64) -> Self {
+        Self {
+            durable: None,
+            pending: vec![],
+            lose_unsynced_writes: options.lose_unsynced_writes,
+            reorder_unsynced_writes: options.reorder_unsynced_writes,
+            sector_size: options.sector_size,
+            rng: StdRng::seed_from_u64(derive_seed(seed, CRASH_FORK)),
+            crashed: false,
+            history: None,
+            recording: false,
+        }
+    }
+
+    /**
+     * Called before every change, to take a copy of the file's contents the
+     * first time. Until then they are taken to be durable.
+     */
+    fn prepare(&mut self, file: &File) -> Result<()> {
+        if self.durable.is_none() {
+            let image = read_image(file)?;
+            if self.recording {
+                self.history = Some((image.clone(), vec![]));
+            }
+            self.durable = Some(image);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, change: Change) {
+        if let Some((_, changes)) = &mut self.history {
+            changes.push(change.clone());
+        }
+        self.pending.push(change);
+    }
+
+    fn synced(&mut self) {
+        if let Some(durable) = &mut self.durable {
+            for change in self.pending.drain(..) {
+                change.apply(durable);
+            }
+        }
+    }
+
+    /**
+     * What the file might hold after losing power now: everything synced,
+     * then a random prefix of the changes since, the last of which may
+     * only have been partly written, up to a sector boundary if a sector
+     * size is set. When reordering, any subset of the
+     * changes survives instead, applied in any order. None if nothing ever
+     * changed.
+     */
+    fn crash_image(&mut self) -> Option<Vec<u8>> {
+        let mut image = self.durable.clone()?;
+        if self.reorder_unsynced_writes {
+            let mut survivors: Vec<&Change> = self
+                .pending
+                .iter()
+                .filter(|_| self.rng.gen_bool(0.5))
+                .collect();
+            survivors.shuffle(&mut self.rng);
+            for change in survivors {
+                change.apply(&mut image);
+            }
+            return Some(image);
+        }
+
+        let survivors = self.rng.gen_range(0..=self.pending.len());
+        for change in &self.pending[0..survivors] {
+            change.apply(&mut image);
+        }
+
+        if let Some(Change::Write { offset, data }) = self.pending.get(survivors) {
+            if self.rng.gen_bool(0.5) {
+                let torn = match self.sector_size {
+                    None => self.rng.gen_range(0..data.len()),
+                    Some(sector) => {
+                        match sector_boundaries(*offset, data.len(), sector).choose(&mut self.rng) {
+                            Some(boundary) => (boundary - offset) as usize,
+                            None => 0,
+                        }
+                    }
+                };
+                Change::Write {
+                    offset: *offset,
+                    data: data[0..torn].to_vec(),
+                }
+                .apply(&mut image);
+            }
+        }
+
+        Some(image)
+    }
+
+    // Throw away everything since the last sync, if configured to
+    fn sync_failed(&mut self, file: &File) -> Result<()> {
+        if !self.lose_unsynced_writes || self.pending.is_empty() {
+            return Ok(());
+        }
+
+        self.pending.clear();
+        let Some(durable) = &self.durable else {
+            return Ok(());
+        };
+        if let Some((_, changes)) = &mut self.history {
+            changes.push(Change::SetLen(0));
+            changes.push(Change::Write {
+                offset: 0,
+                data: durable.clone(),
+            });
+        }
+        write_image(file, durable)
+    }
+}
+
+/**
+ * Read the whole of `file` without moving its cursor.
+ */
+fn read_image(mut file: &File) -> Result<Vec<u8>> {
+    let position = file.stream_position()?;
+    file.seek(SeekFrom::Start(0))?;
+    let mut image = vec![];
+    let result = file.read_to_end(&mut image);
+    file.seek(SeekFrom::Start(position))?;
+
+    match result {
+        Ok(_) => Ok(image),
+        Err(e) => Err(Error::new(
+            e.kind(),
+            format!("modelling persistence needs a readable file: {}", e),
+        )),
+    }
+}
+
+/**
+ * Replace the contents of `file` with `image` without moving its cursor.
+ * The file is emptied first so that this also works in append mode, which
+ * means this is not atomic: an error leaves it empty or partly written.
+ * There is no path to write a copy to and rename over it.
+ */
+fn write_image(mut file: &File, image: &[u8]) -> Result<()> {
+    let position = file.stream_position()?;
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(image)?;
+    file.seek(SeekFrom::Start(position))?;
+    Ok(())
+}
+
+impl<P: PartialPolicy> IncompleteFile<P> {
+    pub(crate) fn with_persistence(mut self, persistence: Persistence) -> Self {
+        *self.persistence.get_mut().unwrap() = Some(persistence);
+        self
+    }
+
+    // Fail every operation once the file has crashed
+    pub(crate) fn check_crashed(&self) -> Result<()> {
+        match self.persistence.lock().unwrap().as_ref() {
+            Some(persistence) if persistence.crashed => Err(Error::other(
+                "the file cannot be used after a simulated crash",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * See Persistence::prepare, for a change of `len` bytes at `offset`, or
+     * if `offset` is None, at the file's cursor. Fails with Unsupported,
+     * before anything is changed, if the model could not hold the result.
+     */
+    pub(crate) fn prepare_change(&self, offset: Option<u64>, len: usize) -> Result<()> {
+        self.check_crashed()?;
+        let mut persistence = self.persistence.lock().unwrap();
+        let Some(persistence) = persistence.as_mut() else {
+            return Ok(());
+        };
+
+        let offset = match offset {
+            Some(offset) => offset,
+            None => (&self.file).stream_position()?,
+        };
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= MAX_IMAGE_LEN => persistence.prepare(&self.file),
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "modelling persistence only works for files of up to {} bytes, \
+                     not a change of {} bytes at offset {}",
+                    MAX_IMAGE_LEN, len, offset
+                ),
+            )),
+        }
+    }
+
+    /**
+     * Record that `data` was just written at `offset`, or if `offset` is
+     * None, just before the file's cursor.
+     */
+    pub(crate) fn record_write(&self, offset: Option<u64>, data: &[u8]) -> Result<()> {
+        let mut persistence = self.persistence.lock().unwrap();
+        let Some(persistence) = persistence.as_mut() else {
+            return Ok(());
+        };
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let offset = match offset {
+            Some(offset) => offset,
+            None => (&self.file).stream_position()? - data.len() as u64,
+        };
+        persistence.push(Change::Write {
+            offset,
+            data: data.to_vec(),
+        });
+        Ok(())
+    }
+
+    pub(crate) fn record_set_len(&self, size: u64) {
+        if let Some(persistence) = self.persistence.lock().unwrap().as_mut() {
+            persistence.push(Change::SetLen(size));
+        }
+    }
+
+    /**
+     * Sync the file with `sync` unless the policy fails `op`, keeping the
+     * model of what is persisted up to date.
+     */
+    pub(crate) fn sync_with(&mut self, op: IoOp, sync: fn(&File) -> Result<()>) -> Result<()> {
+        self.check_crashed()?;
+        let result = permit(self.policy.get_mut().unwrap(), op).and_then(|()| sync(&self.file));
+        if result.is_ok() {
+            self.policy.get_mut().unwrap().completed(op, 0);
+        }
+
+        if let Some(persistence) = self.persistence.get_mut().unwrap() {
+            match &result {
+                Ok(()) => persistence.synced(),
+                Err(e) => {
+                    // the sync's error is the one to report, rewrite or not
+                    if let Err(rewrite) = persistence.sync_failed(&self.file) {
+                        return Err(Error::new(
+                            e.kind(),
+                            format!(
+                                "{}, and throwing away the unsynced writes failed too: {}",
+                                e, rewrite
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    // See IncompleteFile::crash
+    pub(crate) fn crash_with_model(&mut self) -> Result<()> {
+        self.check_crashed()?;
+        let Some(persistence) = self.persistence.get_mut().unwrap() else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "crash needs IncompleteOptions::simulate_crashes",
+            ));
+        };
+
+        if let Some(image) = persistence.crash_image() {
+            write_image(&self.file, &image)?;
+        }
+        persistence.pending.clear();
+        persistence.crashed = true;
+        Ok(())
+    }
+}
+
+/**
+ * Where to tear a write of `len` bytes at `offset` for a crash point: at
+ * the middle sector boundary inside it, or half way through without a
+ * sector size. None if it cannot be torn.
+ */
+fn tear(offset: u64, len: usize, sector_size: Option<u64>) -> Option<usize> {
+    let torn = match sector_size {
+        None => len / 2,
+        Some(sector) => {
+            let boundaries = sector_boundaries(offset, len, sector);
+            (*boundaries.get(boundaries.len() / 2)? - offset) as usize
+        }
+    };
+    (torn > 0).then_some(torn)
+}
+
+/**
+ * The multiples of `sector` strictly inside a write of `len` bytes at
+ * `offset`, where it could be torn.
+ */
+fn sector_boundaries(offset: u64, len: usize, sector: u64) -> Vec<u64> {
+    let end = offset + len as u64;
+    ((offset / sector + 1) * sector..end)
+        .step_by(sector as usize)
+        .collect()
+}
+
+/**
+ * See IncompleteOptions::crash_points.
+ */
+pub(crate) fn crash_points(
+    options: &IncompleteOptions,
+    path: &Path,
+    workload: impl FnOnce(&mut IncompleteFile) -> Result<()>,
+    mut recover: impl FnMut(&Path) -> Result<()>,
+) -> Result<usize> {
+    let mut options = options.clone();
+    let mut file = options.simulate_crashes(true).create(&path)?;
+    if let Some(persistence) = file.persistence.get_mut().unwrap() {
+        persistence.recording = true;
+    }
+    let result = workload(&mut file);
+
+    let history = file
+        .persistence
+        .get_mut()
+        .unwrap()
+        .as_mut()
+        .and_then(|persistence| persistence.history.take());
+    drop(file);
+    let (mut image, changes) = history.unwrap_or_default();
+    if let Err(e) = result {
+        return Err(Error::new(
+            e.kind(),
+            format!(
+                "the workload failed at crash point {}: {}",
+                changes.len(),
+                e
+            ),
+        ));
+    }
+
+    // Crashing after the first k changes, for every k, and part way
+    // through change k + 1 if it is a write that can be torn
+    let torn = |k: usize| match changes.get(k) {
+        Some(Change::Write { offset, data }) => {
+            let torn = tear(*offset, data.len(), options.sector_size)?;
+            let label = format!(", tearing change {} after {} bytes", k + 1, torn);
+            let change = Change::Write {
+                offset: *offset,
+                data: data[..torn].to_vec(),
+            };
+            Some((change, label))
+        }
+        _ => None,
+    };
+    let points = (0..=changes.len())
+        .map(|k| 1 + torn(k).is_some() as usize)
+        .sum();
+    let mut point = 0;
+    for k in 0..=changes.len() {
+        if k > 0 {
+            changes[k - 1].apply(&mut image);
+        }
+        let mut crashes = vec![(image.clone(), String::new())];
+        if let Some((change, label)) = torn(k) {
+            let mut crashed = image.clone();
+            change.apply(&mut crashed);
+            crashes.push((crashed, label));
+        }
+
+        for (crashed, label) in crashes {
+            std::fs::write(path, &crashed)?;
+            if let Err(e) = recover(path) {
+                return Err(Error::new(
+                    e.kind(),
+                    format!(
+                        "recovery failed at crash point {} of {}{}: {}",
+                        point, points, label, e
+                    ),
+                ));
+            }
+            point += 1;
+        }
+    }
+
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn lose_unsynced_writes_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("wal");
+
+        let mut file = IncompleteOptions::default()
+            .lose_unsynced_writes(true)
+            .fail_on(IoOp::SyncAll, 2, ErrorKind::Other)
+            .create(&path)?;
+        file.write_all(b"first ")?;
+        file.sync_all()?;
+
+        // the second sync fails, and takes the unsynced write with it
+        file.write_all(b"second ")?;
+        file.set_len(3)?;
+        assert_eq!(file.sync_all().unwrap_err().kind(), ErrorKind::Other);
+        assert_eq!(std::fs::read(&path)?, b"first ");
+
+        // writes carry on from where the cursor was
+        file.write_all(b"third")?;
+        file.sync_all()?;
+        assert_eq!(std::fs::read(&path)?, b"first \0\0\0\0\0\0\0third");
+
+        Ok(())
+    }
+
+    #[test]
+    fn model_bound_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sparse");
+        let mut file = IncompleteOptions::default()
+            .lose_unsynced_writes(true)
+            .create(&path)?;
+
+        // changes too far out to model fail before they are made
+        let e = file.set_len(1 << 40).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unsupported);
+        file.seek(SeekFrom::Start(1 << 40))?;
+        let e = file.write(b"xyz").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unsupported);
+        assert_eq!(file.metadata()?.len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn crash_points_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("db");
+
+        // write a record, then a commit marker once the record is synced
+        let workload = |file: &mut IncompleteFile| -> Result<()> {
+            file.write_all(b"record")?;
+            file.sync_data()?;
+            file.write_all(b"!")?;
+            file.sync_data()
+        };
+
+        // recovery that trusts the record whenever the marker is present
+        let mut seen = vec![];
+        let points =
+            IncompleteOptions::default()
+                .seed(1)
+                .crash_points(&path, workload, |path| {
+                    let contents = std::fs::read(path)?;
+                    if contents.ends_with(b"!") && contents != b"record!" {
+                        return Err(Error::other("committed a partial record"));
+                    }
+                    seen.push(contents);
+                    Ok(())
+                })?;
+
+        // the file is created empty, and each write is a crash point
+        assert!(points > 2);
+        assert_eq!(seen.len(), points);
+        assert_eq!(seen[0], b"");
+        assert_eq!(seen[points - 1], b"record!");
+        assert!(seen.windows(2).all(|w| w[1].starts_with(&w[0])));
+
+        // recovery that assumes a write reaches the disk all at once, even
+        // when it is written in one go
+        let atomic = |path: &Path| match &std::fs::read(path)?[..] {
+            b"" | b"record!" => Ok(()),
+            _ => Err(Error::other("torn record")),
+        };
+        let result = IncompleteOptions::default().plan([]).crash_points(
+            &path,
+            |file| file.write_all(b"record!"),
+            atomic,
+        );
+        let message = result.unwrap_err().to_string();
+        assert!(
+            message.contains("crash point 1 of 3, tearing change 1 after 3 bytes"),
+            "{}",
+            message
+        );
+
+        // torn only where a sector ends
+        let result = IncompleteOptions::default()
+            .plan([])
+            .sector_size(4)
+            .crash_points(&path, |file| file.write_all(b"record!"), atomic);
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("after 4 bytes"), "{}", message);
+
+        // a workload that fails says how far it got
+        let result = IncompleteOptions::default().plan([]).crash_points(
+            &path,
+            |file| {
+                file.write_all(b"record")?;
+                Err(Error::other("out of records"))
+            },
+            |_| Ok(()),
+        );
+        let message = result.unwrap_err().to_string();
+        assert!(
+            message.contains("workload failed at crash point 1"),
+            "{}",
+            message
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reorder_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("pages");
+
+        // two pages written in order, with no barrier between them
+        let mut outcomes = vec![];
+        for seed in 0..16 {
+            let mut file = IncompleteOptions::default()
+                .seed(seed)
+                .reorder_unsynced_writes(true)
+                .create(&path)?;
+            file.set_len(2)?;
+            file.sync_data()?;
+            file.write_all(b"a")?;
+            file.write_all(b"b")?;
+            file.crash()?;
+            outcomes.push(std::fs::read(&path)?);
+        }
+
+        // the second page can survive without the first
+        assert!(outcomes.contains(&b"\0b".to_vec()));
+        assert!(outcomes.contains(&b"ab".to_vec()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn sector_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("pages");
+
+        // writes pass through whole, so there is one write to tear
+        for seed in 0..16 {
+            let mut file = IncompleteOptions::default()
+                .seed(seed)
+                .plan([])
+                .simulate_crashes(true)
+                .sector_size(4)
+                .create(&path)?;
+            file.write_all(b"ab")?;
+            file.sync_data()?;
+            file.write_all(&[b'x'; 10])?;
+            file.crash()?;
+
+            // the unsynced write is torn only where a sector ends
+            let contents = std::fs::read(&path)?;
+            assert!(contents.starts_with(b"ab"));
+            assert!([2, 4, 8, 12].contains(&contents.len()), "{:?}", contents);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn crash_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("wal");
+
+        for seed in 0..8 {
+            let mut file = IncompleteOptions::default()
+                .seed(seed)
+                .simulate_crashes(true)
+                .create(&path)?;
+            file.write_all(b"synced")?;
+            file.sync_data()?;
+            file.write_all(b" and then some")?;
+            file.crash()?;
+
+            // everything synced survives, and some prefix of the rest
+            let contents = std::fs::read(&path)?;
+            assert!(contents.starts_with(b"synced"));
+            assert!(b"synced and then some".starts_with(&contents[..]));
+
+            // the handle is unusable afterwards
+            assert!(file.write(b"more").is_err());
+            let mut buffer = [0; 4];
+            assert!(file.read(&mut buffer).is_err());
+            assert!(file.sync_all().is_err());
+        }
+
+        // without a model of what was synced, there is nothing to crash to
+        let mut file = IncompleteOptions::default().create(&path)?;
+        assert_eq!(file.crash().unwrap_err().kind(), ErrorKind::Unsupported);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::io::{PipeReader, PipeWriter, Result};
+
+use crate::{IncompleteOptions, IncompleteReader, IncompleteWriter};
+
+/**
+ * An anonymous OS pipe, see std::io::pipe, with partial writes on the
+ * writing end and partial reads on the reading end injected with default
+ * options. See IncompleteOptions::pipe.
+ */
+pub fn incomplete_pipe() -> Result<(IncompleteWriter<PipeWriter>, IncompleteReader<PipeReader>)> {
+    IncompleteOptions::new().pipe()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+    use std::io::{ErrorKind, Read, Write};
+
+    #[test]
+    fn pipe_test() -> Result<()> {
+        // more than a pipe buffers, so the writer has to wait on the reader
+        let mut random_data = vec![0; 1024 * 1024];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        let (mut writer, mut reader) = incomplete_pipe()?;
+        let expected = random_data.clone();
+        let thread = std::thread::spawn(move || -> Result<_> {
+            writer.write_all(&random_data)?;
+            Ok(writer)
+        });
+
+        let mut received = vec![0; expected.len()];
+        reader.read_exact(&mut received)?;
+        assert_eq!(received, expected);
+
+        // the reader going away breaks the pipe
+        let mut writer = thread.join().unwrap()?;
+        drop(reader);
+        let e = writer.write_all(b"hello").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::BrokenPipe);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::io::{Error, ErrorKind, IoSlice, IoSliceMut, Result};
+use std::time::Duration;
+
+/**
+ * The kind of operation a policy is making a decision for.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum IoOp {
+    Read,
+    Write,
+    /// Flushes, seeks, and the file operations below transfer no bytes:
+    /// they are decided with a size of 0, and only Decision::Fail has any
+    /// effect on them.
+    Flush,
+    Seek,
+    SetLen,
+    SyncAll,
+    SyncData,
+}
+
+/**
+ * The built-in ways Injector picks a truncation size. Every size is drawn
+ * from between the configured minimum and maximum, and is always less than
+ * the operation's size.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Policy {
+    /// Every size is equally likely.
+    #[default]
+    Uniform,
+    /// Sizes are geometrically distributed, so most operations are tiny and
+    /// each extra byte is half as likely as the last.
+    Geometric,
+    /// Sizes follow a Pareto distribution: usually small, but with a long
+    /// tail of large operations.
+    HeavyTailed,
+    /// Operations are either as small as possible or as large as possible.
+    Bimodal,
+    /// Sizes are picked from the values most likely to trip off-by-one bugs
+    /// in chunking loops: the smallest and largest allowed (usually 1 and
+    /// one less than the operation), powers of two, and powers of two plus
+    /// or minus one.
+    Boundary,
+    /// Given the size of the records in a stream, operations end one byte
+    /// before, exactly at, or one byte after the next record boundary, so
+    /// that records straddle operations far more often than at random.
+    /// Records start at stream offset 0.
+    Records(usize),
+    /// Every operation transfers exactly one byte, ignoring the configured
+    /// bounds and full_op_probability. This is the most hostile behavior
+    /// the Read and Write contracts allow.
+    OneByte,
+}
+
+/**
+ * Named severity levels, each a combination of truncation bounds, error
+ * rates, and Ok(0) injection, set with IncompleteOptions::profile.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Profile {
+    /// Half of all operations are truncated, uniformly, and 1% of reads
+    /// and writes are Interrupted. Code that loops on short operations at
+    /// all should pass this.
+    Mild,
+    /// Operations are truncated geometrically, mostly to a few bytes, 10%
+    /// of them are Interrupted, and 1% of writes return Ok(0).
+    Aggressive,
+    /// Every operation moves a single byte, a quarter of them are
+    /// Interrupted, 5% of writes return Ok(0), and 1% of reads return a
+    /// spurious Ok(0) before the end. Only code written for hostile
+    /// streams survives this.
+    Pathological,
+}
+
+/**
+ * What happens to the operations after Injector injects an error.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FailureMode {
+    /// The fault is transient, and later operations behave as usual.
+    #[default]
+    Transient,
+    /// The device is dead: every later operation fails with the same error.
+    Sticky,
+}
+
+/**
+ * What an Injector does if it is dropped, along with the wrapper using it,
+ * while some of what was written through it has not been flushed or
+ * synced since, see IncompleteOptions::on_unflushed_drop.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UnflushedDrop {
+    /// Nothing.
+    #[default]
+    Ignore,
+    /// Print how many bytes were never flushed to stderr.
+    Warn,
+    /// Panic, failing the test that forgot to flush.
+    Panic,
+}
+
+/**
+ * What a wrapper should do with an operation.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Decision {
+    /// Pass the operation through untouched.
+    Full,
+    /// Limit the operation to at most this many bytes. Limits of 0 are
+    /// treated as 1, because a zero byte read signals EOF.
+    Limit(usize),
+    /// Fail the operation with this kind of error without performing it.
+    Fail(ErrorKind),
+    /// Return Ok(0) without performing the operation. Write's contract
+    /// allows this (write_all turns it into ErrorKind::WriteZero), and for
+    /// reads it looks like EOF.
+    Zero,
+}
+
+/**
+ * Decides how every operation made through a wrapper is truncated. Injector
+ * is the implementation built from IncompleteOptions; implement this to
+ * plug in a different strategy. Any `FnMut(IoOp, usize) -> usize` closure is
+ * also a policy that limits each operation to the size it returns.
+ */
+pub trait PartialPolicy {
+    /**
+     * Decide what to do with an operation of kind `op` on a buffer of
+     * `requested` bytes.
+     */
+    fn decide(&mut self, op: IoOp, requested: usize) -> Decision;
+
+    /**
+     * How long to hold up an operation of kind `op` before deciding it, to
+     * simulate a slow device. The blocking wrappers sleep for it, and the
+     * async ones return Poll::Pending until it has passed.
+     */
+    fn delay(&mut self, _op: IoOp) -> Duration {
+        Duration::ZERO
+    }
+
+    /**
+     * Called after an operation goes ahead and succeeds, with the number of
+     * bytes it transferred, which is 0 for flushes and syncs. Together with
+     * seeked this lets a policy track the stream position.
+     */
+    fn completed(&mut self, _op: IoOp, _transferred: usize) {}
+
+    /**
+     * Called just before completed with the bytes a successful read or
+     * write moved, by the wrappers that can see them: the blocking ones,
+     * apart from BufRead and positional operations.
+     */
+    fn observed(&mut self, _op: IoOp, _data: &[u8]) {}
+
+    /**
+     * Called after a successful seek, with the new stream position.
+     */
+    fn seeked(&mut self, _position: u64) {}
+
+    /**
+     * Called when into_inner hands the wrapped object back, after which
+     * flushing what was written through the wrapper is up to the caller.
+     */
+    fn released(&mut self) {}
+}
+
+impl<F: FnMut(IoOp, usize) -> usize> PartialPolicy for F {
+    fn decide(&mut self, op: IoOp, requested: usize) -> Decision {
+        Decision::Limit(self(op, requested))
+    }
+}
+
+/**
+ * Ask `policy` what to do with an operation on `len` bytes, returning how
+ * many of those bytes the wrapper should pass to the inner operation, or the
+ * error it should fail with instead. Sleeps for the policy's delay first.
+ */
+pub(crate) fn granted_len(policy: &mut impl PartialPolicy, op: IoOp, len: usize) -> Result<usize> {
+    let delay = policy.delay(op);
+    if !delay.is_zero() {
+        std::thread::sleep(delay);
+    }
+    decided_len(policy, op, len)
+}
+
+/**
+ * granted_len without the delay, for wrappers that wait for it themselves.
+ */
+pub(crate) fn decided_len(policy: &mut impl PartialPolicy, op: IoOp, len: usize) -> Result<usize> {
+    match policy.decide(op, len) {
+        Decision::Full => Ok(len),
+        Decision::Limit(size) => Ok(size.max(1).min(len)),
+        Decision::Fail(kind) => Err(Error::new(kind, "injected by incomplete_file")),
+        Decision::Zero => Ok(0),
+    }
+}
+
+/**
+ * Ask `policy` whether an operation that transfers no bytes may go ahead.
+ */
+pub(crate) fn permit(policy: &mut impl PartialPolicy, op: IoOp) -> Result<()> {
+    granted_len(policy, op, 0).map(|_| ())
+}
+
+/**
+ * Perform a flush through `policy`, unless it fails it, and report success
+ * back to it.
+ */
+pub(crate) fn flush_through(
+    policy: &mut impl PartialPolicy,
+    flush: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    permit(policy, IoOp::Flush)?;
+    flush()?;
+    policy.completed(IoOp::Flush, 0);
+    Ok(())
+}
+
+/**
+ * Perform a read through `policy`: truncate `buf` (or fail) as it decides,
+ * call `read` with what is left, and report the result back to it. Nothing
+ * is read if there is nothing left.
+ */
+pub(crate) fn read_through(
+    policy: &mut impl PartialPolicy,
+    buf: &mut [u8],
+    read: impl FnOnce(&mut [u8]) -> Result<usize>,
+) -> Result<usize> {
+    let truncated_size = granted_len(policy, IoOp::Read, buf.len())?;
+    let n = match truncated_size {
+        0 => 0,
+        _ => read(&mut buf[0..truncated_size])?,
+    };
+    policy.observed(IoOp::Read, &buf[..n]);
+    policy.completed(IoOp::Read, n);
+    Ok(n)
+}
+
+/**
+ * Perform a positional read or write of `len` bytes through `policy`,
+ * calling `io` with the truncated size. Positional operations do not move
+ * the stream position, so the policy is not told when they complete.
+ */
+#[cfg(any(unix, windows))]
+pub(crate) fn positional_through(
+    policy: &mut impl PartialPolicy,
+    op: IoOp,
+    len: usize,
+    io: impl FnOnce(usize) -> Result<usize>,
+) -> Result<usize> {
+    match granted_len(policy, op, len)? {
+        0 => Ok(0),
+        truncated_size => io(truncated_size),
+    }
+}
+
+/**
+ * Perform a vectored read through `policy`. Only the first non-empty buffer
+ * is read into, truncated as read_through would, so callers see both short
+ * reads and untouched trailing buffers.
+ */
+pub(crate) fn read_vectored_through(
+    policy: &mut impl PartialPolicy,
+    bufs: &mut [IoSliceMut<'_>],
+    read: impl FnOnce(&mut [u8]) -> Result<usize>,
+) -> Result<usize> {
+    let buf = match bufs.iter_mut().find(|buf| !buf.is_empty()) {
+        Some(buf) => &mut **buf,
+        None => &mut [],
+    };
+    read_through(policy, buf, read)
+}
+
+/**
+ * Perform a write through `policy`, see read_through.
+ */
+pub(crate) fn write_through(
+    policy: &mut impl PartialPolicy,
+    buf: &[u8],
+    write: impl FnOnce(&[u8]) -> Result<usize>,
+) -> Result<usize> {
+    let truncated_size = granted_len(policy, IoOp::Write, buf.len())?;
+    let n = match truncated_size {
+        0 => 0,
+        _ => write(&buf[0..truncated_size])?,
+    };
+    policy.observed(IoOp::Write, &buf[..n]);
+    policy.completed(IoOp::Write, n);
+    Ok(n)
+}
+
+/**
+ * Perform a vectored write through `policy`: the decision is made for the
+ * total size of `bufs`, and only a prefix of them that size is written,
+ * which can end part way through a buffer.
+ */
+pub(crate) fn write_vectored_through(
+    policy: &mut impl PartialPolicy,
+    bufs: &[IoSlice<'_>],
+    write: impl FnOnce(&[IoSlice<'_>]) -> Result<usize>,
+) -> Result<usize> {
+    let total = bufs.iter().map(|buf| buf.len()).sum();
+    let truncated_size = granted_len(policy, IoOp::Write, total)?;
+
+    let mut prefix = Vec::with_capacity(bufs.len());
+    let mut remaining = truncated_size;
+    for buf in bufs {
+        if remaining == 0 {
+            break;
+        }
+        let take = buf.len().min(remaining);
+        prefix.push(IoSlice::new(&buf[0..take]));
+        remaining -= take;
+    }
+
+    let n = match truncated_size {
+        0 => 0,
+        _ => write(&prefix)?,
+    };
+    let mut remaining = n;
+    for buf in &prefix {
+        let take = buf.len().min(remaining);
+        policy.observed(IoOp::Write, &buf[..take]);
+        remaining -= take;
+    }
+    policy.completed(IoOp::Write, n);
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IncompleteReader;
+    use std::io::Read;
+
+    // Halves every read, and counts how many decisions it made
+    struct Halve {
+        decisions: usize,
+    }
+
+    impl PartialPolicy for Halve {
+        fn decide(&mut self, _op: IoOp, requested: usize) -> Decision {
+            self.decisions += 1;
+            Decision::Limit(requested / 2)
+        }
+    }
+
+    #[test]
+    fn custom_policy_test() -> Result<()> {
+        let data = vec![0; 320];
+        let mut reader = IncompleteReader::with_policy(&data[..], Halve { decisions: 0 });
+
+        let mut buffer = vec![0; 320];
+        assert_eq!(reader.read(&mut buffer)?, 160);
+        assert_eq!(reader.read(&mut buffer[..1])?, 1);
+        assert_eq!(reader.policy().decisions, 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::io::Result;
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitStatus};
+
+use crate::{IncompleteOptions, IncompleteReader, IncompleteWriter};
+
+/**
+ * A child process whose stdin and stdout are pipes wrapped in
+ * IncompleteWriter and IncompleteReader, so that the parent's code that
+ * feeds and drains it is tested against partial pipe I/O. Like
+ * std::process::Child, take the pipes out of the public fields to use them
+ * from separate threads, and drop stdin (or call close_stdin) to send the
+ * child EOF. See IncompleteOptions::spawn.
+ */
+pub struct IncompleteChild {
+    pub stdin: Option<IncompleteWriter<ChildStdin>>,
+    pub stdout: Option<IncompleteReader<ChildStdout>>,
+    pub(crate) child: Child,
+}
+
+impl IncompleteChild {
+    /**
+     * Spawn `command` with default options.
+     */
+    pub fn spawn(command: &mut Command) -> Result<Self> {
+        IncompleteOptions::new().spawn(command)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /**
+     * Close the child's stdin, if it is still open here.
+     */
+    pub fn close_stdin(&mut self) {
+        self.stdin = None;
+    }
+
+    pub fn kill(&mut self) -> Result<()> {
+        self.child.kill()
+    }
+
+    /**
+     * Close stdin, then wait for the child to exit, see Child::wait.
+     */
+    pub fn wait(&mut self) -> Result<ExitStatus> {
+        self.close_stdin();
+        self.child.wait()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+    use std::io::{ErrorKind, Read, Write};
+
+    #[test]
+    fn child_test() -> Result<()> {
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        // feed cat from another thread while draining it here
+        let mut child = IncompleteChild::spawn(&mut Command::new("cat"))?;
+        let mut stdin = child.stdin.take().unwrap();
+        let expected = random_data.clone();
+        let thread = std::thread::spawn(move || stdin.write_all(&random_data));
+
+        let mut output = vec![];
+        child.stdout.as_mut().unwrap().read_to_end(&mut output)?;
+        thread.join().unwrap()?;
+        assert!(child.wait()?.success());
+        assert_eq!(output, expected);
+
+        // a child that exits early closes the pipe under the parent
+        let mut child = IncompleteChild::spawn(Command::new("head").args(["-c", "1"]))?;
+        let stdin = child.stdin.as_mut().unwrap();
+        let e = loop {
+            if let Err(e) = stdin.write_all(&expected) {
+                break e;
+            }
+        };
+        assert_eq!(e.kind(), ErrorKind::BrokenPipe);
+        child.wait()?;
+
+        Ok(())
+    }
+}
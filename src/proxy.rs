@@ -0,0 +1,365 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::injector::derive_seed;
+use crate::options::check_probability;
+use crate::{IncompleteOptions, IncompleteWriter, Injector, UnflushedDrop};
+
+// The fork number delays and disconnections are drawn with, which
+// Injector::fork never uses
+const RELAY_FORK: u64 = u64::MAX;
+
+/**
+ * A TCP proxy that relays bytes between its clients and an upstream server,
+ * writing them on in fragments truncated the same way IncompleteWriter
+ * truncates writes, with optional delays and dropped connections. Point an
+ * unmodified client at it to test it (and the server) against partial
+ * network I/O:
+ *
+ * ```no_run
+ * # use incomplete_file::ChaosProxy;
+ * # fn main() -> std::io::Result<()> {
+ * let proxy = ChaosProxy::bind("127.0.0.1:0")?;
+ * let address = proxy.local_addr()?;
+ * proxy.forward("127.0.0.1:8080")?;
+ * // connect the client under test to address
+ * # Ok(())
+ * # }
+ * ```
+ *
+ * Every fragment is sent with TCP_NODELAY, so the peer sees it as a
+ * separate segment. Errors injected into a fragment's write or the flush
+ * after each relayed read (other than Interrupted and WouldBlock, which
+ * are retried) drop the connection.
+ *
+ * Each direction of each connection is fragmented, delayed, and dropped
+ * with its own seed, derived from the options' seed and the order the
+ * connections were accepted in, so set a seed to replay a run.
+ */
+pub struct ChaosProxy {
+    listener: TcpListener,
+    options: IncompleteOptions,
+    max_delay: Duration,
+    disconnect_probability: f64,
+    #[cfg(unix)]
+    reset_probability: f64,
+}
+
+// The configuration shared by every relay thread
+struct Relay {
+    options: IncompleteOptions,
+    max_delay: Duration,
+    disconnect_probability: f64,
+    #[cfg(unix)]
+    reset_probability: f64,
+}
+
+impl ChaosProxy {
+    /**
+     * Listen for clients on `address`. Nothing is relayed until forward is
+     * called.
+     */
+    pub fn bind(address: impl ToSocketAddrs) -> Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            options: IncompleteOptions::new(),
+            max_delay: Duration::ZERO,
+            disconnect_probability: 0.0,
+            #[cfg(unix)]
+            reset_probability: 0.0,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /**
+     * Fragment relayed bytes according to `options` instead of the
+     * defaults.
+     */
+    pub fn options(mut self, options: &IncompleteOptions) -> Self {
+        self.options = options.clone();
+        self
+    }
+
+    /**
+     * Wait for a random time of up to `max_delay` before sending each
+     * fragment. Defaults to no delay.
+     */
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /**
+     * Drop a `probability` fraction of connections before each fragment,
+     * shutting down both sides. The client and server see an orderly end
+     * of stream, not a reset as with reset_probability. Defaults to 0.0.
+     */
+    pub fn disconnect_probability(mut self, probability: f64) -> Self {
+        self.disconnect_probability = check_probability(probability);
+        self
+    }
+
+    /**
+     * Reset a `probability` fraction of connections before each fragment,
+     * closing both sides with a zero linger time so that the client and
+     * server see ConnectionReset, like a crashed peer, instead of an
+     * orderly end of stream. Defaults to 0.0.
+     */
+    #[cfg(unix)]
+    pub fn reset_probability(mut self, probability: f64) -> Self {
+        self.reset_probability = check_probability(probability);
+        self
+    }
+
+    /**
+     * Start relaying every client that connects to `upstream`, on
+     * background threads. There is no way to stop them: the proxy keeps
+     * accepting and relaying until the process exits.
+     */
+    pub fn forward(self, upstream: impl ToSocketAddrs) -> Result<()> {
+        let upstream: Vec<SocketAddr> = upstream.to_socket_addrs()?.collect();
+        let seed = self
+            .options
+            .seed
+            .unwrap_or_else(|| rand::thread_rng().gen());
+        let mut options = self.options;
+        // dropping connections on purpose leaves writes unflushed
+        options.on_unflushed_drop(UnflushedDrop::Ignore);
+        let relay = Arc::new(Relay {
+            options,
+            max_delay: self.max_delay,
+            disconnect_probability: self.disconnect_probability,
+            #[cfg(unix)]
+            reset_probability: self.reset_probability,
+        });
+        let listener = self.listener;
+
+        std::thread::spawn(move || {
+            for (connection, client) in (0..).zip(listener.incoming()) {
+                let Ok(client) = client else { continue };
+                let relay = relay.clone();
+                let upstream = upstream.clone();
+                let seed = derive_seed(seed, connection);
+                std::thread::spawn(move || relay.connect(client, &upstream[..], seed));
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl Relay {
+    /**
+     * Connect a client to upstream and relay in both directions until
+     * done, each with a seed derived from this connection's `seed`.
+     */
+    fn connect(
+        self: Arc<Self>,
+        client: TcpStream,
+        upstream: &[SocketAddr],
+        seed: u64,
+    ) -> Result<()> {
+        let server = TcpStream::connect(upstream)?;
+        client.set_nodelay(true)?;
+        server.set_nodelay(true)?;
+
+        let (client_read, server_read) = (client.try_clone()?, server.try_clone()?);
+        let relay = self.clone();
+        let reset = Arc::new(AtomicBool::new(false));
+        let thread_reset = reset.clone();
+        let thread = std::thread::spawn(move || {
+            relay.relay(client_read, server, derive_seed(seed, 0), &thread_reset)
+        });
+        let result = self.relay(server_read, client, derive_seed(seed, 1), &reset);
+        let _ = thread.join();
+        result
+    }
+
+    /**
+     * Copy everything from `from` to `to` in fragments, passing EOF on as a
+     * write shutdown unless either direction set `reset`.
+     */
+    fn relay(&self, from: TcpStream, to: TcpStream, seed: u64, reset: &AtomicBool) -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(derive_seed(seed, RELAY_FORK));
+        let mut options = self.options.clone();
+        let injector = Injector::from_options(options.seed(seed));
+        let mut writer = IncompleteWriter::with_policy(to.try_clone()?, injector);
+
+        // Drop the connection, returning `e`
+        let disconnect = |e: Error| {
+            let _ = from.shutdown(Shutdown::Both);
+            let _ = to.shutdown(Shutdown::Both);
+            Err(e)
+        };
+        let retried =
+            |e: &Error| matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock);
+
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let n = (&from).read(&mut buffer)?;
+            if n == 0 {
+                if reset.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+                return to.shutdown(Shutdown::Write);
+            }
+
+            let mut data = &buffer[0..n];
+            while !data.is_empty() {
+                if !self.max_delay.is_zero() {
+                    std::thread::sleep(rng.gen_range(Duration::ZERO..=self.max_delay));
+                }
+
+                if self.disconnect_probability > 0.0 && rng.gen_bool(self.disconnect_probability) {
+                    let _ = from.shutdown(Shutdown::Both);
+                    return to.shutdown(Shutdown::Both);
+                }
+
+                #[cfg(unix)]
+                if self.reset_probability > 0.0 && rng.gen_bool(self.reset_probability) {
+                    // The sockets are only closed, and the resets sent, once
+                    // the other direction has dropped its clones of them too,
+                    // so wake it up from its read without sending a FIN
+                    reset.store(true, Ordering::SeqCst);
+                    for socket in [&from, &to] {
+                        abort(socket)?;
+                        let _ = socket.shutdown(Shutdown::Read);
+                    }
+                    return Ok(());
+                }
+
+                match writer.write(data) {
+                    Ok(n) => data = &data[n..],
+                    Err(e) if retried(&e) => {}
+                    Err(e) => return disconnect(e),
+                }
+            }
+
+            loop {
+                match writer.flush() {
+                    Ok(()) => break,
+                    Err(e) if retried(&e) => {}
+                    Err(e) => return disconnect(e),
+                }
+            }
+        }
+    }
+}
+
+// Make closing `socket` send a reset, by setting a zero linger time
+#[cfg(unix)]
+fn abort(socket: &TcpStream) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let linger = libc::linger {
+        l_onoff: 1,
+        l_linger: 0,
+    };
+    // SAFETY: the descriptor is open for as long as `socket` is, and
+    // `linger` is the size setsockopt is told it is
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_LINGER,
+            &linger as *const libc::linger as *const libc::c_void,
+            std::mem::size_of::<libc::linger>() as libc::socklen_t,
+        )
+    };
+    match ret {
+        0 => Ok(()),
+        _ => Err(Error::last_os_error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    // An upstream server that echoes every connection back until EOF
+    fn echo_server_i() -> Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        std::thread::spawn(move || {
+            for socket in listener.incoming() {
+                let Ok(mut socket) = socket else { continue };
+                std::thread::spawn(move || -> Result<()> {
+                    let mut reader = socket.try_clone()?;
+                    std::io::copy(&mut reader, &mut socket)?;
+                    socket.shutdown(Shutdown::Write)
+                });
+            }
+        });
+        Ok(address)
+    }
+
+    #[test]
+    fn proxy_test() -> Result<()> {
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        // the relay does not trip over options meant for the code under test
+        let mut options = IncompleteOptions::default();
+        options.seed(1).on_unflushed_drop(UnflushedDrop::Panic);
+        let proxy = ChaosProxy::bind("127.0.0.1:0")?.options(&options);
+        let address = proxy.local_addr()?;
+        proxy.forward(echo_server_i()?)?;
+
+        let mut client = TcpStream::connect(address)?;
+        let mut writer = client.try_clone()?;
+        let expected = random_data.clone();
+        let thread = std::thread::spawn(move || -> Result<()> {
+            writer.write_all(&random_data)?;
+            writer.shutdown(Shutdown::Write)
+        });
+
+        let mut echoed = vec![];
+        client.read_to_end(&mut echoed)?;
+        thread.join().unwrap()?;
+        assert_eq!(echoed, expected);
+
+        // every connection is dropped before anything is relayed
+        let proxy = ChaosProxy::bind("127.0.0.1:0")?.disconnect_probability(1.0);
+        let address = proxy.local_addr()?;
+        proxy.forward(echo_server_i()?)?;
+
+        let mut client = TcpStream::connect(address)?;
+        client.write_all(b"hello")?;
+        let mut echoed = vec![];
+        let _ = client.read_to_end(&mut echoed);
+        assert!(echoed.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reset_test() -> Result<()> {
+        // every connection is reset before anything is relayed
+        let proxy = ChaosProxy::bind("127.0.0.1:0")?.reset_probability(1.0);
+        let address = proxy.local_addr()?;
+        proxy.forward(echo_server_i()?)?;
+
+        let mut client = TcpStream::connect(address)?;
+        client.write_all(b"hello")?;
+        let e = client.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ConnectionReset);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// quickcheck Arbitrary implementations for the plan and policy types
+
+use quickcheck::{Arbitrary, Gen};
+use std::io::ErrorKind;
+
+use crate::{Decision, FailureMode, IncompleteOptions, IoOp, Policy};
+
+/**
+ * Arbitrary policies include records of between 1 byte and the
+ * generator's size, never of none. They shrink towards Policy::Uniform.
+ */
+impl Arbitrary for Policy {
+    fn arbitrary(g: &mut Gen) -> Self {
+        match g.choose(&[0, 1, 2, 3, 4, 5, 6]).unwrap() {
+            0 => Policy::Uniform,
+            1 => Policy::Geometric,
+            2 => Policy::HeavyTailed,
+            3 => Policy::Bimodal,
+            4 => Policy::Boundary,
+            5 => Policy::Records(usize::arbitrary(g) % g.size().max(1) + 1),
+            _ => Policy::OneByte,
+        }
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        match self {
+            Policy::Uniform => quickcheck::empty_shrinker(),
+            _ => quickcheck::single_shrinker(Policy::Uniform),
+        }
+    }
+}
+
+impl Arbitrary for FailureMode {
+    fn arbitrary(g: &mut Gen) -> Self {
+        *g.choose(&[FailureMode::Transient, FailureMode::Sticky])
+            .unwrap()
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        match self {
+            FailureMode::Transient => quickcheck::empty_shrinker(),
+            FailureMode::Sticky => quickcheck::single_shrinker(FailureMode::Transient),
+        }
+    }
+}
+
+impl Arbitrary for IoOp {
+    fn arbitrary(g: &mut Gen) -> Self {
+        *g.choose(&[
+            IoOp::Read,
+            IoOp::Write,
+            IoOp::Flush,
+            IoOp::Seek,
+            IoOp::SetLen,
+            IoOp::SyncAll,
+            IoOp::SyncData,
+        ])
+        .unwrap()
+    }
+}
+
+/**
+ * Arbitrary decisions are the ones a correct read_exact or write_all loop
+ * must survive: passing an operation through, limiting it to between 1 and
+ * the generator's size, or failing it with Interrupted. They shrink
+ * towards passing the operation through.
+ */
+impl Arbitrary for Decision {
+    fn arbitrary(g: &mut Gen) -> Self {
+        match g.choose(&[0, 1, 2]).unwrap() {
+            0 => Decision::Full,
+            1 => Decision::Limit(usize::arbitrary(g) % g.size().max(1) + 1),
+            _ => Decision::Fail(ErrorKind::Interrupted),
+        }
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        match self {
+            Decision::Full => quickcheck::empty_shrinker(),
+            _ => quickcheck::single_shrinker(Decision::Full),
+        }
+    }
+}
+
+/**
+ * Arbitrary options follow a script of arbitrary decisions, see
+ * IncompleteOptions::script, and shrink to shorter and gentler scripts.
+ */
+impl Arbitrary for IncompleteOptions {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let mut options = IncompleteOptions::default();
+        options.script(Vec::<Decision>::arbitrary(g));
+        options
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let options = self.clone();
+        let script = self.script.clone().unwrap_or_default();
+        Box::new(script.shrink().map(move |script| {
+            let mut options = options.clone();
+            options.script(script);
+            options
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::{quickcheck, TestResult};
+    use std::io::{Read, Result};
+
+    #[test]
+    fn read_to_end_test() {
+        fn property(options: IncompleteOptions, data: Vec<u8>) -> TestResult {
+            let mut reader = options.wrap_reader(&data[..]);
+            let mut buffer = vec![];
+            match reader.read_to_end(&mut buffer) {
+                Ok(_) => TestResult::from_bool(buffer == data),
+                Err(e) => TestResult::error(e.to_string()),
+            }
+        }
+        quickcheck(property as fn(IncompleteOptions, Vec<u8>) -> TestResult);
+    }
+
+    #[test]
+    fn shrink_test() -> Result<()> {
+        let mut options = IncompleteOptions::default();
+        options.script([Decision::Limit(3), Decision::Full]);
+
+        // every shrink is a shorter or gentler script
+        for shrunk in Arbitrary::shrink(&options) {
+            let script = shrunk.script.unwrap();
+            assert!(script.len() < 2 || script[0] == Decision::Full);
+        }
+        assert_eq!(
+            Policy::Bimodal.shrink().collect::<Vec<_>>(),
+            [Policy::Uniform]
+        );
+
+        // records are generated too, and never empty
+        let mut g = Gen::new(64);
+        let records: Vec<_> = (0..256)
+            .filter_map(|_| match Policy::arbitrary(&mut g) {
+                Policy::Records(size) => Some(size),
+                _ => None,
+            })
+            .collect();
+        assert!(!records.is_empty());
+        assert!(records.iter().all(|size| (1..=64).contains(size)));
+
+        Ok(())
+    }
+}
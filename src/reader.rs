@@ -0,0 +1,182 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use rand::Rng;
+use std::io::{BufRead, IoSliceMut, Read, Result};
+
+use crate::policy::{granted_len, read_through, read_vectored_through};
+use crate::{IncompleteOptions, Injector, IoEvent, IoOp, IoStats, PartialPolicy};
+
+/**
+ * Wraps any std::io::Read and truncates each read the same way
+ * IncompleteFile does, so that short-read handling can be tested in code
+ * that consumes sockets, stdin, or in-memory readers. If the wrapped reader
+ * implements BufRead, so does the wrapper.
+ */
+pub struct IncompleteReader<R: Read, P: PartialPolicy = Injector> {
+    inner: R,
+    policy: P,
+}
+
+impl<R: Read> IncompleteReader<R> {
+    pub fn new(inner: R) -> Self {
+        IncompleteOptions::new().wrap_reader(inner)
+    }
+}
+
+impl<R: Read, G: Rng> IncompleteReader<R, Injector<G>> {
+    /**
+     * The seed this reader's RNG was created with, see IncompleteOptions::seed.
+     */
+    pub fn seed(&self) -> Option<u64> {
+        self.policy.seed()
+    }
+
+    /**
+     * Counts of this reader's operations so far, see Injector::stats.
+     */
+    pub fn stats(&self) -> IoStats {
+        self.policy.stats()
+    }
+
+    /**
+     * Every operation on this reader so far, see Injector::events.
+     */
+    pub fn events(&self) -> &[IoEvent] {
+        self.policy.events()
+    }
+}
+
+impl<R: Read, F: FnMut(IoOp, usize) -> usize> IncompleteReader<R, F> {
+    /**
+     * Wrap `inner`, calling `size_fn` to pick the size every read is limited
+     * to, see IncompleteFile::with_size_fn.
+     */
+    pub fn with_size_fn(inner: R, size_fn: F) -> Self {
+        Self::with_policy(inner, size_fn)
+    }
+}
+
+impl<R: Read, P: PartialPolicy> IncompleteReader<R, P> {
+    /**
+     * Wrap `inner`, letting `policy` decide how every read is truncated.
+     */
+    pub fn with_policy(inner: R, policy: P) -> Self {
+        Self { inner, policy }
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+
+    /**
+     * Consume the wrapper, returning the wrapped reader.
+     */
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read, P: PartialPolicy> Read for IncompleteReader<R, P> {
+    // See IncompleteFile's Read implementation
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        read_through(&mut self.policy, buf, |buf| self.inner.read(buf))
+    }
+
+    // See IncompleteFile's Read implementation
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        read_vectored_through(&mut self.policy, bufs, |buf| self.inner.read(buf))
+    }
+}
+
+impl<R: BufRead, P: PartialPolicy> BufRead for IncompleteReader<R, P> {
+    /**
+     * Truncate the inner reader's buffer the same way a read would be, so
+     * that code built around fill_buf and consume sees short fills down to
+     * a single byte.
+     */
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        let available = self.inner.fill_buf()?;
+        let truncated_size = granted_len(&mut self.policy, IoOp::Read, available.len())?;
+        Ok(&available[0..truncated_size])
+    }
+
+    // Pass-through consume, reporting the bytes read to the policy
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.policy.completed(IoOp::Read, amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    #[test]
+    fn read_test() -> Result<()> {
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        let mut reader = IncompleteReader::new(&random_data[..]);
+
+        // a short read must happen at least once for a buffer this size
+        let mut buffer = vec![0; random_data.len()];
+        let n = reader.read(&mut buffer)?;
+        assert!(n < random_data.len());
+
+        reader.read_exact(&mut buffer[n..])?;
+        assert_eq!(random_data, buffer);
+
+        // the wrapped reader is positioned at EOF
+        let inner = reader.into_inner();
+        assert!(inner.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_vectored_test() -> Result<()> {
+        let data = vec![1; 320 * 64];
+        let mut reader = IncompleteReader::new(&data[..]);
+
+        let mut first = vec![0; 320];
+        let mut second = vec![0; 320];
+        let n = reader.read_vectored(&mut [
+            IoSliceMut::new(&mut []),
+            IoSliceMut::new(&mut first),
+            IoSliceMut::new(&mut second),
+        ])?;
+
+        assert!(n < 320);
+        assert!(first[..n].iter().all(|b| *b == 1));
+        assert!(first[n..].iter().all(|b| *b == 0));
+        assert!(second.iter().all(|b| *b == 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fill_buf_test() -> Result<()> {
+        let lines = "first line\nsecond line\n".repeat(64);
+        let mut reader = IncompleteReader::new(lines.as_bytes());
+
+        // fills are short of what the inner reader has buffered
+        let n = reader.fill_buf()?.len();
+        assert!(n < lines.len());
+        reader.consume(n);
+
+        let mut buffer = lines[..n].to_string();
+        while reader.read_line(&mut buffer)? != 0 {}
+        assert_eq!(buffer, lines);
+
+        Ok(())
+    }
+}
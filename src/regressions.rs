@@ -0,0 +1,235 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// Running a test over many seeds, remembering the ones that failed
+
+use rand::Rng;
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use crate::injector::derive_seed;
+use crate::{IncompleteOptions, SeedGuard};
+
+// Written at the top of a new regression file
+const HEADER: &str = "\
+# Seeds that failed under IncompleteOptions::check_seeds. They are replayed
+# before any new seeds on every run, so check this file in.
+";
+
+/**
+ * The seeds recorded in a regression file, in order. A missing file holds
+ * none. Blank lines and everything after a `#` are ignored, and every
+ * other line is `seed N`.
+ */
+pub(crate) fn read_seeds(path: &Path) -> Result<Vec<u64>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut seeds = vec![];
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let seed = line
+            .strip_prefix("seed ")
+            .and_then(|seed| seed.trim().parse().ok())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{}:{}: expected `seed N`, found {:?}",
+                        path.display(),
+                        number + 1,
+                        line
+                    ),
+                )
+            })?;
+        seeds.push(seed);
+    }
+    Ok(seeds)
+}
+
+// Append `seed` to the regression file at `path`, creating it if needed,
+// with a comment saying what its failure shrank to
+fn record_seed(path: &Path, seed: u64, shrunk: &str) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        file.write_all(HEADER.as_bytes())?;
+    }
+    writeln!(file, "seed {}{}", seed, shrunk)
+}
+
+// Run `test` with `seed`, printing the seed if it panics
+fn run_seed(
+    options: &IncompleteOptions,
+    seed: u64,
+    test: &mut impl FnMut(&IncompleteOptions) -> Result<()>,
+) -> std::thread::Result<Result<()>> {
+    let mut options = options.clone();
+    options.seed(seed);
+    let _guard = SeedGuard::new(seed, &options);
+    panic::catch_unwind(AssertUnwindSafe(|| test(&options)))
+}
+
+pub(crate) fn check_seeds(
+    options: &IncompleteOptions,
+    runs: usize,
+    mut test: impl FnMut(&IncompleteOptions) -> Result<()>,
+) -> Result<usize> {
+    let path = options.regression_file.as_path();
+    let recorded = read_seeds(path)?;
+
+    for &seed in &recorded {
+        match run_seed(options, seed, &mut test) {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                return Err(Error::new(
+                    e.kind(),
+                    format!("regression seed {} failed again: {}", seed, e),
+                ))
+            }
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    // starting from the seed set on the options, so a reported one replays
+    let base = options.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    for run in 0..runs {
+        let seed = match run {
+            0 => base,
+            _ => derive_seed(base, run as u64),
+        };
+        match run_seed(options, seed, &mut test) {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                // panics are not shrunk, as every failed attempt would print
+                let shrunk = match options.clone().seed(seed).shrink(&mut test) {
+                    Some(script) => format!(" # shrinks to script({:?})", script),
+                    None => String::new(),
+                };
+                if !recorded.contains(&seed) {
+                    record_seed(path, seed, &shrunk)?;
+                }
+                return Err(Error::new(
+                    e.kind(),
+                    format!(
+                        "failed with seed {}, recorded in {}{}: {}",
+                        seed,
+                        path.display(),
+                        shrunk,
+                        e
+                    ),
+                ));
+            }
+            Err(payload) => {
+                if !recorded.contains(&seed) {
+                    record_seed(path, seed, "")?;
+                }
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    Ok(recorded.len() + runs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn check_seeds_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("regressions.txt");
+        let mut options = IncompleteOptions::default();
+        options.regression_file(&path);
+
+        // a test that only fails on its third seed
+        let mut seeds = vec![];
+        let error = check_seeds(&options, 8, |options| {
+            seeds.push(options.seed.unwrap());
+            match seeds.len() {
+                3 => Err(Error::other("unlucky")),
+                _ => Ok(()),
+            }
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("unlucky"));
+        assert_eq!(read_seeds(&path)?, [seeds[2]]);
+
+        // the next run replays it before anything else
+        let mut replayed = vec![];
+        let runs = check_seeds(&options, 2, |options| {
+            let mut reader = options.wrap_reader(&b"abc"[..]);
+            reader.read_to_end(&mut vec![])?;
+            replayed.push(reader.seed().unwrap());
+            Ok(())
+        })?;
+        assert_eq!(runs, 3);
+        assert_eq!(replayed[0], seeds[2]);
+
+        // panics on new seeds are recorded too, and still fail the test
+        let result = panic::catch_unwind(|| {
+            let _ = check_seeds(&options, 1, |options| {
+                assert_eq!(options.seed, Some(seeds[2]));
+                Ok(())
+            });
+        });
+        assert!(result.is_err());
+        assert_eq!(read_seeds(&path)?.len(), 2);
+
+        // and failures the options cause are shrunk for the report
+        options.regression_file(dir.path().join("shrunk.txt"));
+        let error = check_seeds(&options, 1, |options| {
+            let mut reader = options.wrap_reader(&[7; 100][..]);
+            match reader.read(&mut [0; 100])? {
+                100 => Ok(()),
+                _ => Err(Error::other("short read")),
+            }
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("shrinks to script([Limit("));
+        let contents = std::fs::read_to_string(dir.path().join("shrunk.txt"))?;
+        assert!(contents.contains("# shrinks to script([Limit("));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "macros")]
+    #[crate::incomplete_test(runs = 4)]
+    fn incomplete_test_test(options: &IncompleteOptions) -> Result<()> {
+        let mut reader = options.wrap_reader(&b"hello"[..]);
+        let mut buffer = vec![];
+        reader.read_to_end(&mut buffer)?;
+        assert_eq!(buffer, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn read_seeds_test() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("regressions.txt");
+        assert_eq!(read_seeds(&path)?, []);
+
+        std::fs::write(&path, "# comment\n\nseed 1\nseed 2 # flaky\n")?;
+        assert_eq!(read_seeds(&path)?, [1, 2]);
+
+        std::fs::write(&path, "seed one\n")?;
+        assert_eq!(
+            read_seeds(&path).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// Searching for the simplest script of decisions that still fails a test
+
+use std::io::Result;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use crate::{Decision, IncompleteOptions};
+
+// How many times shrinking may run the test before settling for what it has
+const MAX_RUNS: usize = 1000;
+
+// Whether `test` succeeds with `options`, counting a panic as a failure
+fn passes(
+    options: &IncompleteOptions,
+    test: &mut impl FnMut(&IncompleteOptions) -> Result<()>,
+) -> bool {
+    matches!(
+        panic::catch_unwind(AssertUnwindSafe(|| test(options))),
+        Ok(Ok(()))
+    )
+}
+
+// Decisions that let through more than `decision` does, most first
+fn larger(decision: Decision) -> Vec<Decision> {
+    match decision {
+        Decision::Full => vec![],
+        Decision::Limit(size) => vec![Decision::Full, Decision::Limit(size.saturating_mul(2))],
+        _ => vec![Decision::Full],
+    }
+}
+
+pub(crate) fn shrink(
+    options: &IncompleteOptions,
+    mut test: impl FnMut(&IncompleteOptions) -> Result<()>,
+) -> Option<Vec<Decision>> {
+    let recorder = Arc::new(Mutex::new(vec![]));
+    let mut recording = options.clone();
+    recording.recorder = Some(recorder.clone());
+    if passes(&recording, &mut test) {
+        return None;
+    }
+    let mut script = std::mem::take(&mut *recorder.lock().unwrap());
+
+    // Whether the test still fails with `candidate`, within the budget
+    let mut runs = 0;
+    let mut fails = |candidate: &mut Vec<Decision>| {
+        // the script runs out into Full anyway
+        while candidate.last() == Some(&Decision::Full) {
+            candidate.pop();
+        }
+        if runs == MAX_RUNS {
+            return false;
+        }
+        runs += 1;
+        let mut options = options.clone();
+        options.script(candidate.iter().copied());
+        !passes(&options, &mut test)
+    };
+
+    if !fails(&mut script) {
+        return None;
+    }
+
+    loop {
+        let mut progress = false;
+
+        // drop runs of decisions, halving the length of the run each pass
+        let mut chunk = script.len();
+        while chunk > 0 {
+            let mut start = 0;
+            while start < script.len() {
+                let end = (start + chunk).min(script.len());
+                let mut candidate = [&script[..start], &script[end..]].concat();
+                if fails(&mut candidate) {
+                    script = candidate;
+                    progress = true;
+                } else {
+                    start += chunk;
+                }
+            }
+            chunk /= 2;
+        }
+
+        // then let each remaining decision through as far as possible
+        let mut i = 0;
+        while i < script.len() {
+            for decision in larger(script[i]) {
+                let mut candidate = script.clone();
+                candidate[i] = decision;
+                if fails(&mut candidate) {
+                    script = candidate;
+                    progress = true;
+                    break;
+                }
+            }
+            i += 1;
+        }
+
+        if !progress {
+            return Some(script);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind, Read};
+
+    #[test]
+    fn shrink_test() -> Result<()> {
+        // assumes one read fills the buffer
+        let sloppy = |options: &IncompleteOptions| -> Result<()> {
+            let mut reader = options.wrap_reader(&[7; 100][..]);
+            let mut buffer = [0; 100];
+            match reader.read(&mut buffer)? {
+                100 => Ok(()),
+                n => Err(Error::other(format!("only read {}", n))),
+            }
+        };
+
+        let script = IncompleteOptions::default()
+            .max_size(10)
+            .shrink(sloppy)
+            .unwrap();
+        assert_eq!(script.len(), 1);
+        assert!(matches!(script[0], Decision::Limit(50..=99)));
+        assert!(sloppy(IncompleteOptions::default().script(script)).is_err());
+
+        // gives up on Interrupted, after several thousand reads of a byte
+        let impatient = |options: &IncompleteOptions| -> Result<()> {
+            let mut reader = options.wrap_reader(&[7; 5000][..]);
+            let mut buffer = [0; 5000];
+            while reader.read(&mut buffer)? > 0 {}
+            Ok(())
+        };
+        let script = IncompleteOptions::default()
+            .policy(crate::Policy::OneByte)
+            .interrupted_probability(0.01)
+            .shrink(impatient);
+        assert_eq!(script, Some(vec![Decision::Fail(ErrorKind::Interrupted)]));
+
+        // passing tests have nothing to shrink
+        assert_eq!(IncompleteOptions::default().shrink(|_| Ok(())), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// Running a test over as many seeds as fit in a time budget, on many threads
+
+use rand::Rng;
+use std::fmt;
+use std::io::{Error, Result};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::injector::derive_seed;
+use crate::IncompleteOptions;
+
+// How many failures a report keeps the errors of
+const KEPT_FAILURES: usize = 100;
+
+/**
+ * What a soak run found, see IncompleteOptions::soak.
+ */
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct SoakReport {
+    /// How many seeds the test was run with.
+    pub runs: usize,
+    /// How many of those runs failed.
+    pub failed: usize,
+    /// The seed and error of the first failures, in the order they were
+    /// found. Panics are reported as errors carrying the panic message.
+    pub failures: Vec<(u64, Error)>,
+    /// How long the runs took.
+    pub elapsed: Duration,
+}
+
+impl SoakReport {
+    /**
+     * Panic, listing the failing seeds, if any run failed.
+     */
+    pub fn assert_passed(&self) {
+        assert!(self.failed == 0, "{}", self);
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} runs failed in {:?}",
+            self.failed, self.runs, self.elapsed
+        )?;
+        for (seed, e) in &self.failures {
+            write!(f, "\n  seed {}: {}", seed, e)?;
+        }
+        if self.failed > self.failures.len() {
+            write!(f, "\n  and {} more", self.failed - self.failures.len())?;
+        }
+        Ok(())
+    }
+}
+
+// Describe a panic with the message it was raised with, if it has one
+fn panic_error(payload: Box<dyn std::any::Any + Send>) -> Error {
+    let message = match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "a non-string payload".to_string(),
+        },
+    };
+    Error::other(format!("panicked with {}", message))
+}
+
+pub(crate) fn soak(
+    options: &IncompleteOptions,
+    threads: usize,
+    budget: Duration,
+    test: impl Fn(&IncompleteOptions) -> Result<()> + Sync,
+) -> SoakReport {
+    let start = Instant::now();
+    let base = options.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let next = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    let failures = Mutex::new(vec![]);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                while start.elapsed() < budget {
+                    let run = next.fetch_add(1, Ordering::Relaxed);
+                    let seed = derive_seed(base, run as u64);
+                    let mut options = options.clone();
+                    options.seed(seed);
+
+                    let error = match panic::catch_unwind(AssertUnwindSafe(|| test(&options))) {
+                        Ok(Ok(())) => continue,
+                        Ok(Err(e)) => e,
+                        Err(payload) => panic_error(payload),
+                    };
+                    failed.fetch_add(1, Ordering::Relaxed);
+                    let mut failures = failures.lock().unwrap();
+                    if failures.len() < KEPT_FAILURES {
+                        failures.push((seed, error));
+                    }
+                }
+            });
+        }
+    });
+
+    SoakReport {
+        runs: next.into_inner(),
+        failed: failed.into_inner(),
+        failures: failures.into_inner().unwrap(),
+        elapsed: start.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn soak_test() -> Result<()> {
+        let report = IncompleteOptions::default().soak(4, Duration::from_millis(50), |options| {
+            let mut reader = options.wrap_reader(&[7; 64][..]);
+            let mut buffer = [0; 64];
+            match reader.read(&mut buffer)? {
+                n if n < 8 => panic!("only {} bytes", n),
+                n if n < 16 => Err(Error::other(format!("only {} bytes", n))),
+                _ => Ok(()),
+            }
+        });
+        assert!(report.runs > 100);
+        assert!(report.failed > 0 && report.failed < report.runs);
+
+        // every failure replays with its seed
+        for (seed, e) in &report.failures {
+            let mut reader = IncompleteOptions::default()
+                .seed(*seed)
+                .wrap_reader(&[7; 64][..]);
+            let n = reader.read(&mut [0; 64])?;
+            assert!(e.to_string().ends_with(&format!("only {} bytes", n)));
+        }
+        assert!(report.to_string().contains("runs failed in"));
+
+        Ok(())
+    }
+}
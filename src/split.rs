@@ -0,0 +1,343 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// Splitting reads and writes where the data in them is most likely to trip
+// up the code handling it
+
+use std::io::{Read, Result, Write};
+
+// How much a Splitting reads ahead at a time
+const READ_AHEAD: usize = 8 * 1024;
+
+/**
+ * Decides where a Splitting cuts up the data going through it, from the
+ * data itself. A closure taking the upcoming bytes is a Splitter too.
+ */
+pub trait Splitter {
+    /**
+     * How many of the `upcoming` bytes, of which there is always at least
+     * one, the next read or write should move. Anything outside 1 to
+     * `upcoming.len()` is clamped to it.
+     */
+    fn split(&mut self, upcoming: &[u8]) -> usize;
+
+    /**
+     * Called with the bytes each read or write actually moved, for
+     * splitters that follow the structure of a stream.
+     */
+    fn advanced(&mut self, _moved: &[u8]) {}
+}
+
+impl<F: FnMut(&[u8]) -> usize> Splitter for F {
+    fn split(&mut self, upcoming: &[u8]) -> usize {
+        self(upcoming)
+    }
+}
+
+/**
+ * Cuts reads and writes as a Splitter decides. Reads look ahead into a
+ * buffer filled by one inner read at a time, so the splitter sees what is
+ * coming before any of it is returned, and only sees as far as the inner
+ * reader has returned. Because of that buffer, don't mix reads and writes
+ * on the same stream. Stack a Splitting with layers through
+ * LayerExt::split_by.
+ */
+pub struct Splitting<T, S: Splitter> {
+    inner: T,
+    splitter: S,
+    buffer: Vec<u8>,
+    start: usize,
+}
+
+impl<T, S: Splitter> Splitting<T, S> {
+    pub fn new(inner: T, splitter: S) -> Self {
+        Self {
+            inner,
+            splitter,
+            buffer: vec![],
+            start: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /**
+     * Consume the wrapper, returning what it wrapped. Anything read ahead
+     * and not yet returned is lost.
+     */
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Read, S: Splitter> Read for Splitting<T, S> {
+    // Serve the splitter's share of what was read ahead, reading more once
+    // it is all gone
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.start == self.buffer.len() {
+            self.buffer.resize(READ_AHEAD, 0);
+            self.start = 0;
+            match self.inner.read(&mut self.buffer) {
+                Ok(n) => self.buffer.truncate(n),
+                Err(e) => {
+                    self.buffer.clear();
+                    return Err(e);
+                }
+            }
+            if self.buffer.is_empty() {
+                return Ok(0);
+            }
+        }
+
+        let upcoming = &self.buffer[self.start..];
+        let n = self
+            .splitter
+            .split(upcoming)
+            .clamp(1, upcoming.len())
+            .min(buf.len());
+        buf[..n].copy_from_slice(&upcoming[..n]);
+        self.splitter.advanced(&upcoming[..n]);
+        self.start += n;
+        Ok(n)
+    }
+}
+
+impl<T: Write, S: Splitter> Write for Splitting<T, S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return self.inner.write(buf);
+        }
+        let n = self.splitter.split(buf).clamp(1, buf.len());
+        let written = self.inner.write(&buf[..n])?;
+        self.splitter.advanced(&buf[..written]);
+        Ok(written)
+    }
+
+    // Pass-through
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+/**
+ * A Splitter that cuts just before and just after every occurrence of a
+ * delimiter byte, so that each one is moved on its own, to hammer the
+ * boundary handling of line-oriented and other delimited readers.
+ */
+pub struct Delimiter {
+    delimiter: u8,
+}
+
+impl Delimiter {
+    pub fn new(delimiter: u8) -> Self {
+        Self { delimiter }
+    }
+}
+
+impl Splitter for Delimiter {
+    fn split(&mut self, upcoming: &[u8]) -> usize {
+        match upcoming.iter().position(|byte| *byte == self.delimiter) {
+            Some(0) => 1,
+            Some(index) => index,
+            None => upcoming.len(),
+        }
+    }
+}
+
+/**
+ * A Splitter for length-prefixed frames: it moves a frame's `width` byte
+ * length prefix one byte at a time, so the prefix itself is split after
+ * every byte, and then splits the payload once in the middle. The prefix
+ * counts the bytes of the payload after it.
+ */
+pub struct LengthPrefix {
+    width: usize,
+    little_endian: bool,
+    // the prefix of the current frame so far, the length it gave, and how
+    // much of that payload has gone through
+    prefix: Vec<u8>,
+    payload: u64,
+    moved: u64,
+}
+
+impl LengthPrefix {
+    /**
+     * Frames with a big-endian prefix of `width` bytes, from 1 to 8.
+     */
+    pub fn big_endian(width: usize) -> Self {
+        Self::new(width, false)
+    }
+
+    /**
+     * Frames with a little-endian prefix of `width` bytes, from 1 to 8.
+     */
+    pub fn little_endian(width: usize) -> Self {
+        Self::new(width, true)
+    }
+
+    fn new(width: usize, little_endian: bool) -> Self {
+        assert!(
+            (1..=8).contains(&width),
+            "a length prefix of {} bytes",
+            width
+        );
+        Self {
+            width,
+            little_endian,
+            prefix: vec![],
+            payload: 0,
+            moved: 0,
+        }
+    }
+}
+
+impl Splitter for LengthPrefix {
+    fn split(&mut self, _upcoming: &[u8]) -> usize {
+        if self.prefix.len() < self.width {
+            return 1;
+        }
+        let middle = self.payload / 2;
+        let next = match self.moved < middle {
+            true => middle - self.moved,
+            false => self.payload - self.moved,
+        };
+        usize::try_from(next).unwrap_or(usize::MAX)
+    }
+
+    fn advanced(&mut self, mut moved: &[u8]) {
+        while !moved.is_empty() {
+            if self.prefix.len() < self.width {
+                self.prefix.push(moved[0]);
+                moved = &moved[1..];
+                if self.prefix.len() == self.width {
+                    let mut bytes = [0; 8];
+                    match self.little_endian {
+                        true => bytes[..self.width].copy_from_slice(&self.prefix),
+                        false => bytes[8 - self.width..].copy_from_slice(&self.prefix),
+                    }
+                    self.payload = match self.little_endian {
+                        true => u64::from_le_bytes(bytes),
+                        false => u64::from_be_bytes(bytes),
+                    };
+                    self.moved = 0;
+                }
+            } else {
+                let take = (self.payload - self.moved).min(moved.len() as u64);
+                self.moved += take;
+                moved = &moved[take as usize..];
+            }
+
+            if self.prefix.len() == self.width && self.moved == self.payload {
+                self.prefix.clear();
+            }
+        }
+    }
+}
+
+/**
+ * A Splitter that cuts inside every multi-byte UTF-8 sequence, between its
+ * first byte and each of the others, to catch code that decodes every
+ * chunk it reads with from_utf8 instead of carrying partial characters
+ * over. Data that isn't UTF-8 is split before every byte in 0x80 to 0xbf.
+ */
+pub struct Utf8Sequences;
+
+impl Splitter for Utf8Sequences {
+    fn split(&mut self, upcoming: &[u8]) -> usize {
+        // continuation bytes, which only ever follow the start of a sequence
+        match upcoming.iter().position(|byte| (0x80..0xc0).contains(byte)) {
+            Some(0) => 1,
+            Some(index) => index,
+            None => upcoming.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LayerExt;
+    use std::io::{BufRead, BufReader};
+
+    // The sizes of the reads it takes to read everything from `reader`
+    fn read_sizes_i(reader: &mut impl Read) -> Result<Vec<usize>> {
+        let mut sizes = vec![];
+        let mut buffer = [0; 64];
+        loop {
+            match reader.read(&mut buffer)? {
+                0 => return Ok(sizes),
+                n => sizes.push(n),
+            }
+        }
+    }
+
+    #[test]
+    fn delimiter_test() -> Result<()> {
+        let data = b"one\ntwo\n\nthree";
+        let mut reader = (&data[..]).split_by(Delimiter::new(b'\n'));
+        assert_eq!(read_sizes_i(&mut reader)?, [3, 1, 3, 1, 1, 5]);
+
+        // lines come out whole however they were split
+        let reader = BufReader::new((&data[..]).split_by(Delimiter::new(b'\n')));
+        let lines: Vec<String> = reader.lines().collect::<Result<_>>()?;
+        assert_eq!(lines, ["one", "two", "", "three"]);
+
+        let mut writer = vec![].split_by(Delimiter::new(b'\n'));
+        assert_eq!(writer.write(b"one\ntwo")?, 3);
+        assert_eq!(writer.write(b"\ntwo")?, 1);
+        writer.write_all(b"two\n")?;
+        assert_eq!(writer.into_inner(), b"one\ntwo\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn utf8_sequences_test() -> Result<()> {
+        let data = "aé€ b".as_bytes();
+        let mut reader = data.split_by(Utf8Sequences);
+        assert_eq!(read_sizes_i(&mut reader)?, [2, 1, 1, 1, 1, 2]);
+
+        // the first read ends part way through the é
+        let mut reader = data.split_by(Utf8Sequences);
+        let mut buffer = [0; 64];
+        let n = reader.read(&mut buffer)?;
+        assert!(std::str::from_utf8(&buffer[..n]).is_err());
+
+        let mut text = String::new();
+        data.split_by(Utf8Sequences).read_to_string(&mut text)?;
+        assert_eq!(text, "aé€ b");
+
+        Ok(())
+    }
+
+    #[test]
+    fn length_prefix_test() -> Result<()> {
+        let data = b"\x00\x03abc\x00\x00\x00\x04wxyz";
+        let mut reader = (&data[..]).split_by(LengthPrefix::big_endian(2));
+        assert_eq!(read_sizes_i(&mut reader)?, [1, 1, 1, 2, 1, 1, 1, 1, 2, 2]);
+
+        let mut writer = vec![].split_by(LengthPrefix::little_endian(4));
+        let mut data = &b"\x06\x00\x00\x00abcdef\x01\x00\x00\x00g"[..];
+        let mut sizes = vec![];
+        while !data.is_empty() {
+            let n = writer.write(data)?;
+            sizes.push(n);
+            data = &data[n..];
+        }
+        assert_eq!(sizes, [1, 1, 1, 1, 3, 3, 1, 1, 1, 1, 1]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,473 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::fmt;
+use std::io::{ErrorKind, Result, Write};
+use std::ops::RangeInclusive;
+
+use crate::{Decision, IoOp};
+
+// One bucket for 0, then one for each power of two
+const BUCKETS: usize = usize::BITS as usize + 1;
+
+/**
+ * How many operations were allowed each size, bucketed by powers of two:
+ * 0, 1, 2..=3, 4..=7, and so on. Display renders it as a text bar chart,
+ * for printing from a test to check that it exercised a good spread of
+ * tiny, medium, and near-full operations.
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SizeHistogram {
+    counts: [usize; BUCKETS],
+}
+
+impl Default for SizeHistogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; BUCKETS],
+        }
+    }
+}
+
+impl SizeHistogram {
+    pub(crate) fn record(&mut self, size: usize) {
+        let bucket = (usize::BITS - size.leading_zeros()) as usize;
+        self.counts[bucket] += 1;
+    }
+
+    // The sizes that fall in `bucket`
+    fn range(bucket: usize) -> RangeInclusive<usize> {
+        match bucket {
+            0 => 0..=0,
+            _ => 1 << (bucket - 1)..=usize::MAX >> (BUCKETS - 1 - bucket),
+        }
+    }
+
+    /**
+     * The non-empty buckets, smallest first, with the range of sizes each
+     * covers and how many operations fell in it.
+     */
+    pub fn buckets(&self) -> impl Iterator<Item = (RangeInclusive<usize>, usize)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| (Self::range(bucket), *count))
+    }
+
+    /**
+     * How many operations fell in any bucket.
+     */
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+}
+
+impl fmt::Debug for SizeHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.buckets()).finish()
+    }
+}
+
+impl fmt::Display for SizeHistogram {
+    // One row per non-empty bucket, with bars scaled to the largest
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let largest = self.counts.iter().copied().max().unwrap_or(0);
+        for (range, count) in self.buckets() {
+            let bar = (count * 40).div_ceil(largest);
+            let range = format!("{}..={}", range.start(), range.end());
+            writeln!(f, "{:>24} {:>8} {}", range, count, "#".repeat(bar))?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * One operation decided by an Injector, see IncompleteOptions::trace.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IoEvent {
+    pub op: IoOp,
+    /// The size of the buffer the operation was called with.
+    pub requested: usize,
+    /// How many bytes the operation was allowed to move, 0 if it failed.
+    pub granted: usize,
+    /// The stream position before the operation, as far as the policy
+    /// knows.
+    pub offset: u64,
+    /// The error injected instead of performing the operation, if any.
+    pub injected: Option<ErrorKind>,
+}
+
+impl IoEvent {
+    pub(crate) fn new(op: IoOp, requested: usize, offset: u64, decision: Decision) -> Self {
+        let (granted, injected) = match decision {
+            Decision::Full => (requested, None),
+            Decision::Limit(size) => (size.max(1).min(requested), None),
+            Decision::Zero => (0, None),
+            Decision::Fail(kind) => (0, Some(kind)),
+        };
+        Self {
+            op,
+            requested,
+            granted,
+            offset,
+            injected,
+        }
+    }
+
+    /**
+     * This event as a single line JSON object, with the operation and any
+     * injected error named as in their Debug output, for example:
+     *
+     * `{"op":"Read","requested":8,"granted":5,"offset":0,"injected":null}`
+     */
+    pub fn to_json(&self) -> String {
+        let injected = match self.injected {
+            Some(kind) => format!("\"{:?}\"", kind),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"op\":\"{:?}\",\"requested\":{},\"granted\":{},\"offset\":{},\"injected\":{}}}",
+            self.op, self.requested, self.granted, self.offset, injected,
+        )
+    }
+}
+
+/**
+ * Write `events` to `writer` as JSON lines, one IoEvent::to_json object per
+ * line, for example to attach the trace of a failing run as a CI artifact,
+ * or to diff it against the trace of a passing one.
+ */
+pub fn write_json_lines(events: &[IoEvent], mut writer: impl Write) -> Result<()> {
+    for event in events {
+        writeln!(writer, "{}", event.to_json())?;
+    }
+    writer.flush()
+}
+
+/**
+ * A category of fault that can be delivered to code under test, see
+ * FaultCoverage.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Fault {
+    /// A read allowed fewer bytes than it asked for.
+    ShortRead,
+    /// A write allowed fewer bytes than it asked for.
+    ShortWrite,
+    /// A read returned Ok(0) without reaching the end of the data.
+    ZeroRead,
+    /// A write returned Ok(0).
+    ZeroWrite,
+    /// An operation failed with Interrupted.
+    Interrupted,
+    /// An operation failed with WouldBlock.
+    WouldBlock,
+    /// An operation of this kind failed with any other injected error, for
+    /// example Error(IoOp::Flush) for a failed flush.
+    Error(IoOp),
+}
+
+const OPS: [IoOp; 7] = [
+    IoOp::Read,
+    IoOp::Write,
+    IoOp::Flush,
+    IoOp::Seek,
+    IoOp::SetLen,
+    IoOp::SyncAll,
+    IoOp::SyncData,
+];
+
+impl Fault {
+    // The fault delivered by `decision`, if any
+    fn classify(op: IoOp, len: usize, decision: Decision) -> Option<Self> {
+        match (decision, op) {
+            (Decision::Fail(ErrorKind::Interrupted), _) => Some(Fault::Interrupted),
+            (Decision::Fail(ErrorKind::WouldBlock), _) => Some(Fault::WouldBlock),
+            (Decision::Fail(_), _) => Some(Fault::Error(op)),
+            (Decision::Zero, IoOp::Read) if len > 0 => Some(Fault::ZeroRead),
+            (Decision::Zero, IoOp::Write) if len > 0 => Some(Fault::ZeroWrite),
+            (Decision::Limit(size), IoOp::Read) if size.max(1) < len => Some(Fault::ShortRead),
+            (Decision::Limit(size), IoOp::Write) if size.max(1) < len => Some(Fault::ShortWrite),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Fault::ShortRead => 0,
+            Fault::ShortWrite => 1,
+            Fault::ZeroRead => 2,
+            Fault::ZeroWrite => 3,
+            Fault::Interrupted => 4,
+            Fault::WouldBlock => 5,
+            Fault::Error(op) => 6 + OPS.iter().position(|o| *o == op).unwrap(),
+        }
+    }
+}
+
+/**
+ * How many times each category of fault was delivered, so that a test can
+ * check it exercised every kind of fault it configured:
+ *
+ * ```
+ * # use incomplete_file::{Fault, IncompleteOptions, IoOp};
+ * # use std::io::{ErrorKind, Write};
+ * # fn main() -> std::io::Result<()> {
+ * let mut writer = IncompleteOptions::new()
+ *     .fail_on(IoOp::Flush, 1, ErrorKind::Other)
+ *     .wrap_writer(vec![]);
+ * writer.write_all(b"abc")?;
+ * let _ = writer.flush();
+ * writer
+ *     .stats()
+ *     .faults
+ *     .assert_covered(&[Fault::ShortWrite, Fault::Error(IoOp::Flush)]);
+ * # Ok(())
+ * # }
+ * ```
+ */
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultCoverage {
+    counts: [usize; 6 + OPS.len()],
+}
+
+impl FaultCoverage {
+    pub fn count(&self, fault: Fault) -> usize {
+        self.counts[fault.index()]
+    }
+
+    /**
+     * The faults in `expected` that were never delivered.
+     */
+    pub fn missing(&self, expected: &[Fault]) -> Vec<Fault> {
+        expected
+            .iter()
+            .copied()
+            .filter(|fault| self.count(*fault) == 0)
+            .collect()
+    }
+
+    /**
+     * Panic unless every fault in `expected` was delivered at least once.
+     */
+    #[track_caller]
+    pub fn assert_covered(&self, expected: &[Fault]) {
+        let missing = self.missing(expected);
+        assert!(missing.is_empty(), "faults never delivered: {:?}", missing);
+    }
+
+    // Every fault delivered at least once, with its count
+    fn delivered(&self) -> impl Iterator<Item = (Fault, usize)> + '_ {
+        let faults = [
+            Fault::ShortRead,
+            Fault::ShortWrite,
+            Fault::ZeroRead,
+            Fault::ZeroWrite,
+            Fault::Interrupted,
+            Fault::WouldBlock,
+        ];
+        faults
+            .into_iter()
+            .chain(OPS.into_iter().map(Fault::Error))
+            .map(|fault| (fault, self.count(fault)))
+            .filter(|(_, count)| *count > 0)
+    }
+}
+
+impl fmt::Debug for FaultCoverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.delivered()).finish()
+    }
+}
+
+/**
+ * Counts of what went through an Injector, see Injector::stats. Use these to
+ * check that code under test made a sensible number of calls, and that
+ * truncation and faults actually happened.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IoStats {
+    pub reads: usize,
+    pub writes: usize,
+    pub flushes: usize,
+    /// The bytes reads and writes asked for, before truncation.
+    pub bytes_requested: u64,
+    /// The bytes reads and writes actually moved. Positional reads and
+    /// writes are not counted, because policies are not told when they
+    /// complete.
+    pub bytes_transferred: u64,
+    /// Reads and writes that were allowed fewer bytes than they asked for.
+    pub truncated: usize,
+    /// Operations failed with an injected error, including Interrupted and
+    /// WouldBlock.
+    pub injected_errors: usize,
+    /// The sizes reads were allowed, whether or not they were truncated.
+    pub read_sizes: SizeHistogram,
+    /// The sizes writes were allowed.
+    pub write_sizes: SizeHistogram,
+    /// Which categories of fault were delivered, and how often.
+    pub faults: FaultCoverage,
+}
+
+impl IoStats {
+    /**
+     * Panic unless at least one read or write was truncated, to catch chaos
+     * tests that silently test nothing, for example because every buffer
+     * was too small to truncate within the configured size bounds.
+     */
+    #[track_caller]
+    pub fn assert_partial_occurred(&self) {
+        assert!(
+            self.truncated > 0,
+            "none of {} reads and {} writes were truncated",
+            self.reads,
+            self.writes,
+        );
+    }
+
+    // Count a decision made for an operation of `len` bytes
+    pub(crate) fn decided(&mut self, op: IoOp, len: usize, decision: Decision) {
+        match op {
+            IoOp::Read => self.reads += 1,
+            IoOp::Write => self.writes += 1,
+            IoOp::Flush => self.flushes += 1,
+            _ => {}
+        }
+        if let IoOp::Read | IoOp::Write = op {
+            self.bytes_requested += len as u64;
+        }
+        if let Some(fault) = Fault::classify(op, len, decision) {
+            self.faults.counts[fault.index()] += 1;
+        }
+
+        let granted = match decision {
+            Decision::Full => len,
+            Decision::Limit(size) => size.max(1).min(len),
+            Decision::Zero => 0,
+            Decision::Fail(_) => {
+                self.injected_errors += 1;
+                return;
+            }
+        };
+        if granted < len {
+            self.truncated += 1;
+        }
+
+        match op {
+            IoOp::Read => self.read_sizes.record(granted),
+            IoOp::Write => self.write_sizes.record(granted),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn completed(&mut self, op: IoOp, transferred: usize) {
+        if let IoOp::Read | IoOp::Write = op {
+            self.bytes_transferred += transferred as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_test() {
+        let mut histogram = SizeHistogram::default();
+        for size in [0, 1, 2, 3, 4, 7, 8, usize::MAX] {
+            histogram.record(size);
+        }
+
+        let buckets: Vec<_> = histogram.buckets().collect();
+        assert_eq!(
+            buckets,
+            [
+                (0..=0, 1),
+                (1..=1, 1),
+                (2..=3, 2),
+                (4..=7, 2),
+                (8..=15, 1),
+                (1 << (usize::BITS - 1)..=usize::MAX, 1),
+            ]
+        );
+        assert_eq!(histogram.total(), 8);
+
+        // the fullest buckets get the longest bars
+        let text = histogram.to_string();
+        assert_eq!(text.lines().count(), 6);
+        assert!(text.lines().nth(2).unwrap().ends_with(&"#".repeat(40)));
+    }
+
+    #[test]
+    fn assert_partial_occurred_test() -> Result<()> {
+        use crate::IncompleteOptions;
+
+        let mut writer = IncompleteOptions::default().plan([1]).wrap_writer(vec![]);
+        writer.write_all(b"ab")?;
+        writer.stats().assert_partial_occurred();
+
+        // single byte writes can never be truncated
+        let mut writer = IncompleteOptions::default().wrap_writer(vec![]);
+        for byte in b"ab" {
+            writer.write_all(&[*byte])?;
+        }
+        let stats = writer.stats();
+        let result = std::panic::catch_unwind(|| stats.assert_partial_occurred());
+        assert!(result.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn coverage_test() -> Result<()> {
+        use crate::IncompleteOptions;
+
+        let mut writer = IncompleteOptions::default()
+            .plan([1])
+            .fail_on(IoOp::Write, 2, ErrorKind::Interrupted)
+            .fail_on(IoOp::Flush, 1, ErrorKind::Other)
+            .wrap_writer(vec![]);
+        writer.write_all(b"abc")?;
+        assert!(writer.flush().is_err());
+
+        let faults = writer.stats().faults;
+        assert_eq!(faults.count(Fault::ShortWrite), 1);
+        assert_eq!(faults.count(Fault::Interrupted), 1);
+        assert_eq!(faults.count(Fault::Error(IoOp::Flush)), 1);
+        faults.assert_covered(&[Fault::ShortWrite, Fault::Error(IoOp::Flush)]);
+        assert_eq!(
+            faults.missing(&[Fault::ShortRead, Fault::Interrupted, Fault::WouldBlock]),
+            [Fault::ShortRead, Fault::WouldBlock]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn json_lines_test() -> Result<()> {
+        let events = [
+            IoEvent::new(IoOp::Write, 8, 0, Decision::Limit(5)),
+            IoEvent::new(IoOp::Flush, 0, 5, Decision::Fail(ErrorKind::Other)),
+        ];
+
+        let mut out = vec![];
+        write_json_lines(&events, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "{\"op\":\"Write\",\"requested\":8,\"granted\":5,\"offset\":0,\"injected\":null}\n",
+                "{\"op\":\"Flush\",\"requested\":0,\"granted\":0,\"offset\":5,\"injected\":\"Other\"}\n",
+            )
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::io::{StderrLock, StdinLock, StdoutLock};
+
+use crate::{IncompleteOptions, IncompleteReader, IncompleteWriter, Injector};
+
+/**
+ * The locked standard input, with reads truncated the same way
+ * IncompleteReader does. It implements BufRead, so read_line and lines see
+ * short fills too, and with sizes down to a single byte UTF-8 sequences and
+ * CRLF line endings are split across reads.
+ */
+pub type IncompleteStdin<P = Injector> = IncompleteReader<StdinLock<'static>, P>;
+
+/**
+ * The locked standard output, with writes truncated and flushes failed the
+ * same way IncompleteWriter does.
+ */
+pub type IncompleteStdout<P = Injector> = IncompleteWriter<StdoutLock<'static>, P>;
+
+/**
+ * The locked standard error, see IncompleteStdout.
+ */
+pub type IncompleteStderr<P = Injector> = IncompleteWriter<StderrLock<'static>, P>;
+
+/**
+ * Lock the standard input with default options, see
+ * IncompleteOptions::wrap_stdin.
+ */
+pub fn incomplete_stdin() -> IncompleteStdin {
+    IncompleteOptions::new().wrap_stdin()
+}
+
+/**
+ * Lock the standard output with default options, see
+ * IncompleteOptions::wrap_stdout.
+ */
+pub fn incomplete_stdout() -> IncompleteStdout {
+    IncompleteOptions::new().wrap_stdout()
+}
+
+/**
+ * Lock the standard error with default options, see
+ * IncompleteOptions::wrap_stderr.
+ */
+pub fn incomplete_stderr() -> IncompleteStderr {
+    IncompleteOptions::new().wrap_stderr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IoOp;
+    use crate::Policy;
+    use std::io::{BufRead, ErrorKind, Read, Result, Write};
+
+    #[test]
+    fn stdin_test() -> Result<()> {
+        let mut options = IncompleteOptions::default();
+        options.fail_on(IoOp::Read, 1, ErrorKind::Other);
+
+        // the injected error is returned without waiting for input
+        let e = options.wrap_stdin().read(&mut [0; 16]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+
+        Ok(())
+    }
+
+    #[test]
+    fn split_lines_test() -> Result<()> {
+        // one byte reads split a multi-byte character and a CRLF, which
+        // read_line has to put back together, as they would be on stdin
+        let input = "caf\u{e9}\r\nna\u{ef}ve\r\n";
+        let mut reader = IncompleteOptions::default()
+            .policy(Policy::OneByte)
+            .wrap_reader(std::io::BufReader::new(input.as_bytes()));
+        assert_eq!(reader.fill_buf()?.len(), 1);
+
+        let mut lines = vec![];
+        let mut line = String::new();
+        while reader.read_line(&mut line)? != 0 {
+            lines.push(std::mem::take(&mut line));
+        }
+        assert_eq!(lines, ["caf\u{e9}\r\n", "na\u{ef}ve\r\n"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stdout_test() -> Result<()> {
+        let mut options = IncompleteOptions::default();
+        options.fail_on(IoOp::Flush, 1, ErrorKind::BrokenPipe);
+
+        // the injected error is returned without touching the terminal
+        for e in [options.wrap_stdout().flush(), options.wrap_stderr().flush()] {
+            assert_eq!(e.unwrap_err().kind(), ErrorKind::BrokenPipe);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// proptest strategies for generating injection plans
+
+use proptest::prelude::*;
+use proptest::sample::select;
+use std::io::ErrorKind;
+
+use crate::{Decision, IncompleteOptions, IoOp};
+
+// The errors schedule_strategy injects
+const KINDS: [ErrorKind; 8] = [
+    ErrorKind::Other,
+    ErrorKind::Interrupted,
+    ErrorKind::WouldBlock,
+    ErrorKind::UnexpectedEof,
+    ErrorKind::BrokenPipe,
+    ErrorKind::TimedOut,
+    ErrorKind::PermissionDenied,
+    ErrorKind::StorageFull,
+];
+
+const OPS: [IoOp; 7] = [
+    IoOp::Read,
+    IoOp::Write,
+    IoOp::Flush,
+    IoOp::Seek,
+    IoOp::SetLen,
+    IoOp::SyncAll,
+    IoOp::SyncData,
+];
+
+/**
+ * Plans for IncompleteOptions::plan of up to `max_len` sizes, each from 1
+ * to `max_size`. proptest shrinks them to fewer and smaller sizes.
+ */
+pub fn plan_strategy(max_len: usize, max_size: usize) -> impl Strategy<Value = Vec<usize>> {
+    prop::collection::vec(1..=max_size.max(1), 0..=max_len)
+}
+
+/**
+ * Scripts for IncompleteOptions::script of up to `max_len` decisions:
+ * passing an operation through, limiting it to between 1 and `max_size`
+ * bytes, or failing it with Interrupted. These are everything a correct
+ * read_exact or write_all loop must survive. proptest shrinks them to fewer
+ * decisions, and towards passing operations through.
+ */
+pub fn script_strategy(max_len: usize, max_size: usize) -> impl Strategy<Value = Vec<Decision>> {
+    let decision = prop_oneof![
+        Just(Decision::Full),
+        (1..=max_size.max(1)).prop_map(Decision::Limit),
+        Just(Decision::Fail(ErrorKind::Interrupted)),
+    ];
+    prop::collection::vec(decision, 0..=max_len)
+}
+
+/**
+ * Error schedules of up to `max_len` entries, each failing the n'th
+ * operation of some kind, with n from 1 to `max_count`, with one of a
+ * handful of common errors. Pass each entry to IncompleteOptions::fail_on.
+ */
+pub fn schedule_strategy(
+    max_len: usize,
+    max_count: usize,
+) -> impl Strategy<Value = Vec<(IoOp, usize, ErrorKind)>> {
+    let entry = (select(&OPS[..]), 1..=max_count.max(1), select(&KINDS[..]));
+    prop::collection::vec(entry, 0..=max_len)
+}
+
+/**
+ * IncompleteOptions following a script from script_strategy, for
+ * properties that should hold however operations are split up:
+ *
+ * ```
+ * # use incomplete_file::options_strategy;
+ * # use proptest::prelude::*;
+ * # use std::io::Read;
+ * proptest!(|(options in options_strategy(16, 8))| {
+ *     let mut reader = options.wrap_reader(&b"hello world"[..]);
+ *     let mut buffer = vec![];
+ *     reader.read_to_end(&mut buffer).unwrap();
+ *     prop_assert_eq!(buffer, b"hello world");
+ * });
+ * ```
+ */
+pub fn options_strategy(
+    max_len: usize,
+    max_size: usize,
+) -> impl Strategy<Value = IncompleteOptions> {
+    script_strategy(max_len, max_size).prop_map(|script| {
+        let mut options = IncompleteOptions::default();
+        options.script(script);
+        options
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    proptest! {
+        #[test]
+        fn write_all_test(
+            options in options_strategy(32, 16),
+            data in prop::collection::vec(any::<u8>(), 0..256),
+        ) {
+            let mut writer = options.wrap_writer(vec![]);
+            writer.write_all(&data).unwrap();
+            prop_assert_eq!(writer.into_inner(), data);
+        }
+
+        #[test]
+        fn read_exact_test(plan in plan_strategy(32, 16)) {
+            let mut reader = IncompleteOptions::default().plan(plan).wrap_reader(&[7; 100][..]);
+            let mut buffer = [0; 100];
+            reader.read_exact(&mut buffer).unwrap();
+            prop_assert_eq!(buffer, [7; 100]);
+        }
+
+        #[test]
+        fn schedule_test(schedule in schedule_strategy(4, 4)) {
+            let mut options = IncompleteOptions::default();
+            for (op, n, kind) in &schedule {
+                options.fail_on(*op, *n, *kind);
+            }
+
+            // each flush fails as the first entry for it says
+            let mut writer = options.wrap_writer(vec![]);
+            for count in 1..=4 {
+                let expected = schedule
+                    .iter()
+                    .find(|(op, n, _)| *op == IoOp::Flush && *n == count)
+                    .map(|(_, _, kind)| *kind);
+                prop_assert_eq!(writer.flush().err().map(|e| e.kind()), expected);
+            }
+        }
+    }
+}
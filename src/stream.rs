@@ -0,0 +1,228 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::io::{IoSlice, IoSliceMut, Read, Result, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{SocketAddr as UnixSocketAddr, UnixStream};
+
+use crate::policy::{
+    flush_through, read_through, read_vectored_through, write_through, write_vectored_through,
+};
+use crate::{IncompleteOptions, Injector, PartialPolicy};
+
+/**
+ * Two connected UnixStreams, with partial reads and writes injected on both
+ * ends with default options. See IncompleteOptions::unix_pair.
+ */
+#[cfg(unix)]
+pub fn incomplete_unix_pair() -> Result<(IncompleteStream<UnixStream>, IncompleteStream<UnixStream>)>
+{
+    IncompleteOptions::new().unix_pair()
+}
+
+/**
+ * Wraps a bidirectional stream such as a TcpStream or UnixStream, and
+ * truncates its reads and writes the same way IncompleteFile does. Reads
+ * and writes are decided by separate policies, so each direction can be
+ * configured (and is seeded) independently.
+ */
+pub struct IncompleteStream<
+    T: Read + Write,
+    R: PartialPolicy = Injector,
+    W: PartialPolicy = Injector,
+> {
+    inner: T,
+    read_policy: R,
+    write_policy: W,
+}
+
+impl<T: Read + Write> IncompleteStream<T> {
+    pub fn new(inner: T) -> Self {
+        IncompleteOptions::new().wrap_stream(inner)
+    }
+}
+
+impl<T: Read + Write, R: PartialPolicy, W: PartialPolicy> IncompleteStream<T, R, W> {
+    /**
+     * Wrap `inner`, letting `read_policy` decide how every read is
+     * truncated and `write_policy` every write and flush.
+     */
+    pub fn with_policies(inner: T, read_policy: R, write_policy: W) -> Self {
+        Self {
+            inner,
+            read_policy,
+            write_policy,
+        }
+    }
+
+    pub fn read_policy(&self) -> &R {
+        &self.read_policy
+    }
+
+    pub fn read_policy_mut(&mut self) -> &mut R {
+        &mut self.read_policy
+    }
+
+    pub fn write_policy(&self) -> &W {
+        &self.write_policy
+    }
+
+    pub fn write_policy_mut(&mut self) -> &mut W {
+        &mut self.write_policy
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /**
+     * Consume the wrapper, returning the wrapped stream.
+     */
+    pub fn into_inner(mut self) -> T {
+        self.read_policy.released();
+        self.write_policy.released();
+        self.inner
+    }
+}
+
+impl<R: PartialPolicy, W: PartialPolicy> IncompleteStream<TcpStream, R, W> {
+    // Pass-through, see TcpStream::shutdown
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        self.inner.shutdown(how)
+    }
+
+    // Pass-through, see TcpStream::peer_addr
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    // Pass-through, see TcpStream::local_addr
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(unix)]
+impl<R: PartialPolicy, W: PartialPolicy> IncompleteStream<UnixStream, R, W> {
+    // Pass-through, see UnixStream::shutdown
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        self.inner.shutdown(how)
+    }
+
+    // Pass-through, see UnixStream::peer_addr
+    pub fn peer_addr(&self) -> Result<UnixSocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    // Pass-through, see UnixStream::local_addr
+    pub fn local_addr(&self) -> Result<UnixSocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+impl<T: Read + Write, R: PartialPolicy, W: PartialPolicy> Read for IncompleteStream<T, R, W> {
+    // See IncompleteFile's Read implementation
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        read_through(&mut self.read_policy, buf, |buf| self.inner.read(buf))
+    }
+
+    // See IncompleteFile's Read implementation
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        read_vectored_through(&mut self.read_policy, bufs, |buf| self.inner.read(buf))
+    }
+}
+
+impl<T: Read + Write, R: PartialPolicy, W: PartialPolicy> Write for IncompleteStream<T, R, W> {
+    // See IncompleteFile's Write implementation
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        write_through(&mut self.write_policy, buf, |buf| self.inner.write(buf))
+    }
+
+    // See IncompleteFile's Write implementation
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        write_vectored_through(&mut self.write_policy, bufs, |bufs| {
+            self.inner.write_vectored(bufs)
+        })
+    }
+
+    // Pass-through flush, unless the write policy fails it
+    fn flush(&mut self) -> Result<()> {
+        flush_through(&mut self.write_policy, || self.inner.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IoOp;
+    #[cfg(unix)]
+    use rand::RngCore;
+    use std::io::ErrorKind;
+    use std::net::TcpListener;
+
+    #[test]
+    fn tcp_test() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+
+        // echo a line back, then hang up
+        let thread = std::thread::spawn(move || -> Result<()> {
+            let (socket, _) = listener.accept()?;
+            let mut server = IncompleteStream::new(socket);
+            let mut line = [0; 12];
+            server.read_exact(&mut line)?;
+            server.write_all(&line)?;
+            server.shutdown(Shutdown::Write)
+        });
+
+        // reads pass through, and every write is one byte
+        let mut client = IncompleteStream::with_policies(
+            TcpStream::connect(address)?,
+            |_, requested| requested,
+            |_, _| 1,
+        );
+        assert_eq!(client.peer_addr()?, address);
+
+        assert_eq!(client.write(b"hello world\n")?, 1);
+        client.write_all(b"ello world\n")?;
+        let mut echoed = vec![];
+        client.read_to_end(&mut echoed)?;
+        assert_eq!(echoed, b"hello world\n");
+
+        thread.join().unwrap()?;
+
+        // the write policy also decides flushes
+        let mut stream = IncompleteOptions::default()
+            .fail_on(IoOp::Flush, 1, ErrorKind::Other)
+            .wrap_stream(std::io::Cursor::new(vec![]));
+        assert_eq!(stream.flush().unwrap_err().kind(), ErrorKind::Other);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_pair_test() -> Result<()> {
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        let (mut a, mut b) = incomplete_unix_pair()?;
+        let expected = random_data.clone();
+        let thread = std::thread::spawn(move || -> Result<()> {
+            a.write_all(&random_data)?;
+            // dropping a closes the socket, so b sees EOF
+            Ok(())
+        });
+
+        let mut received = vec![];
+        b.read_to_end(&mut received)?;
+        thread.join().unwrap()?;
+        assert_eq!(received, expected);
+
+        Ok(())
+    }
+}
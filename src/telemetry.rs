@@ -0,0 +1,275 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+// Reporting decisions to the observability crates enabled by features
+
+use crate::{IncompleteOptions, IoEvent, IoOp};
+
+/**
+ * Where an Injector reports its decisions, besides its own stats and trace.
+ */
+pub(crate) struct Telemetry {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "log")]
+    log_level: log::Level,
+}
+
+// The label metrics are published with for each kind of operation
+#[cfg(feature = "metrics")]
+fn op_label(op: IoOp) -> &'static str {
+    match op {
+        IoOp::Read => "read",
+        IoOp::Write => "write",
+        IoOp::Flush => "flush",
+        IoOp::Seek => "seek",
+        IoOp::SetLen => "set_len",
+        IoOp::SyncAll => "sync_all",
+        IoOp::SyncData => "sync_data",
+    }
+}
+
+impl Telemetry {
+    #[allow(unused_variables)]
+    pub(crate) fn new(options: &IncompleteOptions, seed: Option<u64>) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!("incomplete_file", seed),
+            #[cfg(feature = "log")]
+            log_level: options.log_level,
+        }
+    }
+
+    #[allow(unused_variables)]
+    pub(crate) fn decided(&self, event: &IoEvent) {
+        #[cfg(feature = "tracing")]
+        {
+            let _entered = self.span.enter();
+            tracing::trace!(
+                op = ?event.op,
+                requested = event.requested,
+                granted = event.granted,
+                offset = event.offset,
+                injected = ?event.injected,
+                "decided",
+            );
+        }
+
+        #[cfg(feature = "log")]
+        log::log!(
+            target: "incomplete_file",
+            self.log_level,
+            "{:?} of {} bytes at offset {}: granted {}, injected {:?}",
+            event.op,
+            event.requested,
+            event.offset,
+            event.granted,
+            event.injected,
+        );
+
+        #[cfg(feature = "metrics")]
+        {
+            let op = op_label(event.op);
+            metrics::counter!("incomplete_file_operations", "op" => op).increment(1);
+            match event.injected {
+                Some(kind) => {
+                    let kind = format!("{:?}", kind);
+                    metrics::counter!("incomplete_file_injected_errors", "op" => op, "kind" => kind)
+                        .increment(1);
+                }
+                None if matches!(event.op, IoOp::Read | IoOp::Write) => {
+                    if event.granted < event.requested {
+                        metrics::counter!("incomplete_file_truncations", "op" => op).increment(1);
+                    }
+                    metrics::histogram!("incomplete_file_granted_bytes", "op" => op)
+                        .record(event.granted as f64);
+                }
+                None => {}
+            }
+        }
+    }
+
+    #[allow(unused_variables)]
+    pub(crate) fn completed(&self, op: IoOp, transferred: usize) {
+        #[cfg(feature = "metrics")]
+        if let IoOp::Read | IoOp::Write = op {
+            metrics::counter!("incomplete_file_bytes", "op" => op_label(op))
+                .increment(transferred as u64);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::io::{Read, Result};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::IncompleteOptions;
+
+    #[derive(Default)]
+    struct Counts {
+        spans: AtomicUsize,
+        events: AtomicUsize,
+    }
+
+    // Counts the spans and events it sees
+    struct CountingSubscriber(Arc<Counts>);
+
+    impl Subscriber for CountingSubscriber {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _span: &Attributes<'_>) -> Id {
+            Id::from_u64(self.0.spans.fetch_add(1, Ordering::SeqCst) as u64 + 1)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {
+            self.0.events.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    fn tracing_test() -> Result<()> {
+        let counts = Arc::new(Counts::default());
+        let subscriber = CountingSubscriber(counts.clone());
+
+        tracing::subscriber::with_default(subscriber, || -> Result<()> {
+            let mut reader = IncompleteOptions::default()
+                .plan([1, 1])
+                .wrap_reader(&b"abc"[..]);
+            let mut buffer = vec![];
+            reader.read_to_end(&mut buffer)?;
+            Ok(())
+        })?;
+
+        // one span for the reader, and an event for each of its reads
+        assert_eq!(counts.spans.load(Ordering::SeqCst), 1);
+        assert!(counts.events.load(Ordering::SeqCst) >= 4);
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "log"))]
+mod log_tests {
+    use log::{Level, Log, Metadata, Record};
+    use std::io::{Read, Result};
+    use std::sync::Mutex;
+
+    use crate::IncompleteOptions;
+
+    // Keeps every record logged by this crate
+    struct KeepingLogger(Mutex<Vec<(Level, String)>>);
+
+    impl Log for KeepingLogger {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.target() == "incomplete_file"
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            if self.enabled(record.metadata()) {
+                let message = record.args().to_string();
+                self.0.lock().unwrap().push((record.level(), message));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: KeepingLogger = KeepingLogger(Mutex::new(vec![]));
+
+    #[test]
+    fn log_test() -> Result<()> {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let mut reader = IncompleteOptions::default()
+            .plan([1])
+            .log_level(Level::Debug)
+            .wrap_reader(&b"abc"[..]);
+        reader.read_exact(&mut [0; 3])?;
+
+        // other tests may be logging at the same time, but not at Debug
+        let debug: Vec<_> = LOGGER
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(level, _)| *level == Level::Debug)
+            .cloned()
+            .collect();
+        assert_eq!(debug.len(), 2);
+        assert_eq!(
+            debug[0].1,
+            "Read of 3 bytes at offset 0: granted 1, injected None"
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod metrics_tests {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use std::io::{ErrorKind, Result, Write};
+
+    use crate::{IncompleteOptions, IoOp};
+
+    #[test]
+    fn metrics_test() -> Result<()> {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || -> Result<()> {
+            let mut writer = IncompleteOptions::default()
+                .plan([2])
+                .fail_on(IoOp::Flush, 1, ErrorKind::Other)
+                .wrap_writer(vec![]);
+            writer.write_all(b"hello")?;
+            assert!(writer.flush().is_err());
+            Ok(())
+        })?;
+
+        // snapshots drain the counters, so take one for every check
+        let snapshot = snapshotter.snapshot().into_vec();
+        let value = |name: &str| {
+            snapshot
+                .iter()
+                .find(|(key, _, _, _)| key.key().name() == name)
+                .map(|(_, _, _, value)| value)
+        };
+        assert_eq!(
+            value("incomplete_file_bytes"),
+            Some(&DebugValue::Counter(5))
+        );
+        assert_eq!(
+            value("incomplete_file_truncations"),
+            Some(&DebugValue::Counter(1))
+        );
+        assert_eq!(
+            value("incomplete_file_injected_errors"),
+            Some(&DebugValue::Counter(1))
+        );
+        assert!(matches!(
+            value("incomplete_file_granted_bytes"),
+            Some(DebugValue::Histogram(sizes)) if sizes.len() == 2
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use rand::Rng;
+use std::io::{IoSlice, Result, Write};
+
+use crate::policy::{flush_through, write_through, write_vectored_through};
+use crate::{IncompleteOptions, Injector, IoEvent, IoOp, IoStats, PartialPolicy};
+
+/**
+ * Wraps any std::io::Write and truncates each write the same way
+ * IncompleteFile does, so that short-write handling can be tested in code
+ * that writes to sockets, Vec<u8> sinks, or BufWriters.
+ */
+pub struct IncompleteWriter<W: Write, P: PartialPolicy = Injector> {
+    inner: W,
+    policy: P,
+}
+
+impl<W: Write> IncompleteWriter<W> {
+    pub fn new(inner: W) -> Self {
+        IncompleteOptions::new().wrap_writer(inner)
+    }
+}
+
+impl<W: Write, G: Rng> IncompleteWriter<W, Injector<G>> {
+    /**
+     * The seed this writer's RNG was created with, see IncompleteOptions::seed.
+     */
+    pub fn seed(&self) -> Option<u64> {
+        self.policy.seed()
+    }
+
+    /**
+     * Counts of this writer's operations so far, see Injector::stats.
+     */
+    pub fn stats(&self) -> IoStats {
+        self.policy.stats()
+    }
+
+    /**
+     * Every operation on this writer so far, see Injector::events.
+     */
+    pub fn events(&self) -> &[IoEvent] {
+        self.policy.events()
+    }
+}
+
+impl<W: Write, F: FnMut(IoOp, usize) -> usize> IncompleteWriter<W, F> {
+    /**
+     * Wrap `inner`, calling `size_fn` to pick the size every write is limited
+     * to, see IncompleteFile::with_size_fn.
+     */
+    pub fn with_size_fn(inner: W, size_fn: F) -> Self {
+        Self::with_policy(inner, size_fn)
+    }
+}
+
+impl<W: Write, P: PartialPolicy> IncompleteWriter<W, P> {
+    /**
+     * Wrap `inner`, letting `policy` decide how every write is truncated.
+     */
+    pub fn with_policy(inner: W, policy: P) -> Self {
+        Self { inner, policy }
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+
+    /**
+     * Consume the wrapper, returning the wrapped writer.
+     */
+    pub fn into_inner(mut self) -> W {
+        self.policy.released();
+        self.inner
+    }
+}
+
+impl<W: Write, P: PartialPolicy> Write for IncompleteWriter<W, P> {
+    // See IncompleteFile's Write implementation
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        write_through(&mut self.policy, buf, |buf| self.inner.write(buf))
+    }
+
+    // See IncompleteFile's Write implementation
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        write_vectored_through(&mut self.policy, bufs, |bufs| {
+            self.inner.write_vectored(bufs)
+        })
+    }
+
+    // Pass-through flush, unless the policy fails it
+    fn flush(&mut self) -> Result<()> {
+        flush_through(&mut self.policy, || self.inner.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    #[test]
+    fn write_test() -> Result<()> {
+        let mut random_data = vec![0; 320 * 64];
+        rand::thread_rng().fill_bytes(&mut random_data);
+
+        let mut writer = IncompleteWriter::new(Vec::new());
+
+        // a short write must happen at least once for a buffer this size
+        let n = writer.write(&random_data)?;
+        assert!(n < random_data.len());
+
+        writer.write_all(&random_data[n..])?;
+        writer.flush()?;
+
+        assert_eq!(random_data, writer.into_inner());
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_vectored_test() -> Result<()> {
+        let first = vec![1; 320];
+        let second = vec![2; 320];
+        let mut expected = first.clone();
+        expected.extend_from_slice(&second);
+
+        let mut writer = IncompleteWriter::new(Vec::new());
+        let mut bufs = [IoSlice::new(&first), IoSlice::new(&second)];
+        let mut bufs = &mut bufs[..];
+
+        // only a prefix is written each time, so bufs must be advanced
+        let mut calls = 0;
+        while !bufs.is_empty() {
+            let n = writer.write_vectored(bufs)?;
+            IoSlice::advance_slices(&mut bufs, n);
+            calls += 1;
+        }
+
+        assert!(calls > 1);
+        assert_eq!(writer.into_inner(), expected);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::io::{Result, Write};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+// Run incomplete-cat with `args`, piping `input` into it from another thread
+// so that a full pipe cannot deadlock it, and ignoring the broken pipe if it
+// stops reading early
+fn cat_i(args: &[&str], input: &[u8]) -> Result<Output> {
+    let mut cat = Command::new(env!("CARGO_BIN_EXE_incomplete-cat"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = cat.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+
+    let output = cat.wait_with_output()?;
+    writer.join().unwrap();
+    Ok(output)
+}
+
+#[test]
+fn incomplete_cat_test() -> Result<()> {
+    let data: Vec<u8> = (0..64 * 1024).map(|i| (i * 7 % 251) as u8).collect();
+
+    // what goes in comes out, and the seed is printed to replay it with
+    let output = cat_i(&["--seed", "1", "--max-size", "3"], &data)?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, data);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "incomplete-cat: seed 1\n"
+    );
+
+    // a flag it does not know
+    let output = cat_i(&["--no-such-flag"], &[])?;
+    assert!(!output.status.success());
+    let output = cat_i(&["--throttle", "0"], &[])?;
+    assert!(!output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn incomplete_stdin_test() -> Result<()> {
+    // one byte reads from the real stdin split a multi-byte character and
+    // a CRLF, and still none of it is lost
+    let data = "caf\u{e9}\r\nna\u{ef}ve\r\n";
+
+    let output = cat_i(
+        &["--seed", "2", "--max-size", "1", "--quiet"],
+        data.as_bytes(),
+    )?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), data);
+
+    Ok(())
+}
+
+#[test]
+fn fail_after_bytes_test() -> Result<()> {
+    let data: Vec<u8> = (0..64 * 1024).map(|i| (i * 7 % 251) as u8).collect();
+
+    // the consumer gets exactly the bytes before the failure, then EOF
+    let output = cat_i(&["--max-size", "7", "--fail-after-bytes", "1000"], &data)?;
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, &data[..1000]);
+
+    Ok(())
+}
+
+#[test]
+fn fail_after_ops_test() -> Result<()> {
+    let output = cat_i(
+        &["--max-size", "1", "--fail-after-ops", "4", "--quiet"],
+        b"hello world",
+    )?;
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, b"hell");
+
+    Ok(())
+}
+
+#[test]
+fn latency_test() -> Result<()> {
+    // five one byte reads, and the writes, each sleep first
+    let start = Instant::now();
+    let output = cat_i(&["--max-size", "1", "--latency", "20", "--quiet"], b"hello")?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"hello");
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    Ok(())
+}
+
+#[test]
+fn throttle_test() -> Result<()> {
+    // 2000 bytes at 4000 bytes a second take about half a second
+    let data = vec![7; 2000];
+    let start = Instant::now();
+    let output = cat_i(&["--throttle", "4000", "--quiet"], &data)?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, data);
+    assert!(start.elapsed() >= Duration::from_millis(400));
+
+    Ok(())
+}